@@ -0,0 +1,69 @@
+use super::{Authenticator, Principal, Scopes};
+use crate::error::Error;
+use axum::{extract::Query, http::request::Parts};
+use serde::Deserialize;
+use std::{collections::HashMap, fs, path::Path};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+/// `EventSource` cannot set headers, so browsers pass the key as a query parameter.
+pub const API_KEY_QUERY: &str = "api_key";
+
+#[derive(Debug, Deserialize)]
+struct ApiKeyEntry {
+    name: String,
+    key: String,
+    services: Vec<String>,
+}
+
+/// Static API keys loaded from a JSON file of `{ "name", "key", "services" }` entries.
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    keys: HashMap<String, Principal>,
+}
+
+impl ApiKeys {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|e| {
+            Error::Config(format!("failed to read API keys `{}`: {e}", path.display()))
+        })?;
+        Self::from_json(&contents)
+    }
+
+    pub fn from_json(contents: &str) -> Result<Self, Error> {
+        let entries: Vec<ApiKeyEntry> = serde_json::from_str(contents)
+            .map_err(|e| Error::Config(format!("invalid API keys file: {e}")))?;
+        let mut keys = HashMap::with_capacity(entries.len());
+        for entry in entries {
+            let scopes = Scopes::parse(&entry.services)
+                .map_err(|e| Error::Config(format!("API key `{}`: {e}", entry.name)))?;
+            let principal = Principal {
+                subject: entry.name,
+                scopes,
+            };
+            keys.insert(entry.key, principal);
+        }
+        Ok(ApiKeys { keys })
+    }
+
+    fn extract(parts: &Parts) -> Option<String> {
+        if let Some(value) = parts.headers.get(API_KEY_HEADER) {
+            return value.to_str().ok().map(str::to_owned);
+        }
+        let Query(mut query) = Query::<HashMap<String, String>>::try_from_uri(&parts.uri).ok()?;
+        query.remove(API_KEY_QUERY)
+    }
+}
+
+impl Authenticator for ApiKeys {
+    fn authenticate(&self, parts: &Parts) -> Result<Option<Principal>, Error> {
+        let Some(key) = Self::extract(parts) else {
+            return Ok(None);
+        };
+        self.keys
+            .get(&key)
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| Error::Unauthorized("invalid API key".to_string()))
+    }
+}
@@ -0,0 +1,146 @@
+use super::{Authenticator, Principal, Scopes};
+use crate::error::Error;
+use axum::http::{header::AUTHORIZATION, request::Parts};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation, decode, decode_header,
+    jwk::{Jwk, JwkSet},
+};
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    services: Vec<String>,
+}
+
+/// Verifies bearer tokens against a JWKS loaded at startup. The `services`
+/// claim lists what the token holder may stream.
+///
+/// A token must be signed with the algorithm its key declares, or with one of
+/// the configured algorithms when the key declares none; the token's own
+/// header is never trusted to pick it.
+#[derive(Debug)]
+pub struct JwtVerifier {
+    jwks: JwkSet,
+    issuer: Option<String>,
+    audience: Option<String>,
+    algorithms: Vec<Algorithm>,
+}
+
+impl JwtVerifier {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("failed to read JWKS `{}`: {e}", path.display())))?;
+        Self::from_json(&contents)
+    }
+
+    pub fn from_json(contents: &str) -> Result<Self, Error> {
+        let jwks: JwkSet = serde_json::from_str(contents)
+            .map_err(|e| Error::Config(format!("invalid JWKS: {e}")))?;
+        Ok(JwtVerifier {
+            jwks,
+            issuer: None,
+            audience: None,
+            algorithms: Vec::new(),
+        })
+    }
+
+    pub fn with_issuer(mut self, issuer: Option<String>) -> Self {
+        self.issuer = issuer;
+        self
+    }
+
+    pub fn with_audience(mut self, audience: Option<String>) -> Self {
+        self.audience = audience;
+        self
+    }
+
+    /// Restricts keys to `algorithms`, and accepts them for keys without an
+    /// `alg`.
+    pub fn with_algorithms(mut self, algorithms: &[String]) -> Result<Self, Error> {
+        self.algorithms = algorithms
+            .iter()
+            .map(|name| {
+                name.parse()
+                    .map_err(|_| Error::Config(format!("invalid JWT algorithm `{name}`")))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(self)
+    }
+
+    /// The algorithms tokens signed by `jwk` may use.
+    fn algorithms(&self, jwk: &Jwk) -> Result<Vec<Algorithm>, String> {
+        match jwk.common.key_algorithm {
+            Some(declared) => {
+                let algorithm: Algorithm = declared
+                    .to_string()
+                    .parse()
+                    .map_err(|_| format!("key algorithm {declared} cannot verify tokens"))?;
+                if !self.algorithms.is_empty() && !self.algorithms.contains(&algorithm) {
+                    return Err(format!("key algorithm {declared} is not allowed"));
+                }
+                Ok(vec![algorithm])
+            }
+            None if !self.algorithms.is_empty() => Ok(self.algorithms.clone()),
+            None => Err("key declares no algorithm and none are configured".to_string()),
+        }
+    }
+
+    fn verify(&self, token: &str) -> Result<Principal, String> {
+        let header = decode_header(token).map_err(|e| e.to_string())?;
+        let jwk = match &header.kid {
+            Some(kid) => self.jwks.find(kid),
+            None if self.jwks.keys.len() == 1 => self.jwks.keys.first(),
+            None => None,
+        }
+        .ok_or("no matching key in JWKS")?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| e.to_string())?;
+        let algorithms = self.algorithms(jwk)?;
+        if !algorithms.contains(&header.alg) {
+            return Err(format!(
+                "algorithm {:?} is not allowed for this key",
+                header.alg
+            ));
+        }
+
+        let mut validation = Validation::new(header.alg);
+        validation.algorithms = algorithms;
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let claims = decode::<Claims>(token, &key, &validation)
+            .map_err(|e| e.to_string())?
+            .claims;
+        let scopes = Scopes::parse(&claims.services)?;
+        Ok(Principal {
+            subject: claims.sub,
+            scopes,
+        })
+    }
+}
+
+impl Authenticator for JwtVerifier {
+    fn authenticate(&self, parts: &Parts) -> Result<Option<Principal>, Error> {
+        let Some(value) = parts.headers.get(AUTHORIZATION) else {
+            return Ok(None);
+        };
+        let Some(token) = value
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return Ok(None);
+        };
+        self.verify(token)
+            .map(Some)
+            .map_err(|e| Error::Unauthorized(format!("invalid bearer token: {e}")))
+    }
+}
@@ -0,0 +1,272 @@
+use crate::{config::AuthConfig, error::Error};
+use axum::{
+    extract::{Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::Response,
+};
+use proto_definitions::v1::Service;
+use std::{collections::HashSet, fmt::Debug, sync::Arc};
+use tracing::{debug, instrument, warn};
+
+pub mod api_key;
+pub mod jwt;
+
+pub use api_key::ApiKeys;
+pub use jwt::JwtVerifier;
+
+/// Services a principal is allowed to stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Scopes {
+    All,
+    Services(HashSet<i32>),
+}
+
+impl Scopes {
+    /// Parses service names such as `mastodon` or `x`; `*` grants every service.
+    pub fn parse<I, S>(services: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut allowed = HashSet::new();
+        for name in services {
+            let name = name.as_ref();
+            if name == "*" {
+                return Ok(Scopes::All);
+            }
+            let service = Service::from_str_name(&name.to_uppercase())
+                .ok_or_else(|| format!("unknown service `{name}`"))?;
+            allowed.insert(service as i32);
+        }
+        Ok(Scopes::Services(allowed))
+    }
+
+    pub fn allows(&self, service: i32) -> bool {
+        match self {
+            Scopes::All => true,
+            Scopes::Services(services) => services.contains(&service),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Scopes::Services(services) if services.is_empty())
+    }
+}
+
+/// The authenticated caller, available to handlers as a request extension.
+#[derive(Clone, Debug)]
+pub struct Principal {
+    pub subject: String,
+    pub scopes: Scopes,
+}
+
+impl Principal {
+    pub fn anonymous() -> Self {
+        Principal {
+            subject: "anonymous".to_string(),
+            scopes: Scopes::All,
+        }
+    }
+}
+
+/// A source of credentials. Returns `Ok(None)` when the request carries no
+/// credential it understands, so the next authenticator can have a go.
+pub trait Authenticator: Debug + Send + Sync {
+    fn authenticate(&self, parts: &Parts) -> Result<Option<Principal>, Error>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Auth {
+    authenticators: Vec<Arc<dyn Authenticator>>,
+}
+
+impl Auth {
+    pub fn from_config(config: &AuthConfig) -> Result<Self, Error> {
+        let mut auth = Auth::default();
+        if let Some(path) = &config.api_keys_file {
+            auth = auth.with(ApiKeys::from_file(path)?);
+        }
+        if let Some(path) = &config.jwks_file {
+            let verifier = JwtVerifier::from_file(path)?
+                .with_issuer(config.jwt_issuer.clone())
+                .with_audience(config.jwt_audience.clone())
+                .with_algorithms(&config.jwt_algorithms)?;
+            auth = auth.with(verifier);
+        }
+        if !auth.is_enabled() {
+            warn!("No API keys or JWKS configured, authentication is disabled");
+        }
+        Ok(auth)
+    }
+
+    pub fn with<A>(mut self, authenticator: A) -> Self
+    where
+        A: Authenticator + 'static,
+    {
+        self.authenticators.push(Arc::new(authenticator));
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.authenticators.is_empty()
+    }
+
+    /// The principal behind the request's credentials, if authentication is
+    /// enabled and they verify.
+    pub fn identify(&self, parts: &Parts) -> Option<Principal> {
+        if !self.is_enabled() {
+            return None;
+        }
+        self.authenticate(parts).ok()
+    }
+
+    fn authenticate(&self, parts: &Parts) -> Result<Principal, Error> {
+        if !self.is_enabled() {
+            return Ok(Principal::anonymous());
+        }
+        for authenticator in &self.authenticators {
+            if let Some(principal) = authenticator.authenticate(parts)? {
+                return Ok(principal);
+            }
+        }
+        Err(Error::Unauthorized("missing credentials".to_string()))
+    }
+}
+
+#[instrument(name = "authenticate", target = "api::auth", skip_all)]
+pub async fn middleware(
+    State(auth): State<Auth>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let (mut parts, body) = request.into_parts();
+    // The limit layer may already have verified the credentials.
+    let principal = match parts.extensions.get::<Principal>() {
+        Some(principal) => principal.clone(),
+        None => auth.authenticate(&parts).inspect_err(|e| {
+            warn!("Rejected request to {}: {}", parts.uri.path(), e);
+        })?,
+    };
+    debug!(subject = %principal.subject, "request authenticated");
+    parts.extensions.insert(principal);
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ApiKeys, Auth, JwtVerifier, Scopes};
+    use crate::error::Error;
+    use axum::{body::Body, http::Request};
+    use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+    use proto_definitions::v1::Service;
+    use serde_json::json;
+
+    const API_KEYS: &str = r#"[{"name": "ui", "key": "ui-key", "services": ["mastodon"]}]"#;
+    const JWKS: &str =
+        r#"{"keys": [{"kty": "oct", "kid": "test", "alg": "HS256", "k": "c2VjcmV0"}]}"#;
+
+    fn auth() -> Auth {
+        Auth::default()
+            .with(ApiKeys::from_json(API_KEYS).unwrap())
+            .with(JwtVerifier::from_json(JWKS).unwrap())
+    }
+
+    fn parts(request: Request<Body>) -> axum::http::request::Parts {
+        request.into_parts().0
+    }
+
+    #[test]
+    fn scopes_parse_service_names() {
+        let scopes = Scopes::parse(["mastodon"]).unwrap();
+        assert!(scopes.allows(Service::Mastodon as i32));
+        assert!(!scopes.allows(Service::X as i32));
+        assert_eq!(Scopes::parse(["x", "*"]).unwrap(), Scopes::All);
+        assert!(Scopes::parse(["myspace"]).is_err());
+    }
+
+    #[test]
+    fn api_key_from_header_or_query() {
+        let auth = auth();
+        let header = Request::builder()
+            .uri("/sse")
+            .header("x-api-key", "ui-key")
+            .body(Body::empty())
+            .unwrap();
+        let query = Request::builder()
+            .uri("/sse?api_key=ui-key")
+            .body(Body::empty())
+            .unwrap();
+        for request in [header, query] {
+            let principal = auth.authenticate(&parts(request)).unwrap();
+            assert_eq!(principal.subject, "ui");
+            assert!(!principal.scopes.allows(Service::X as i32));
+        }
+    }
+
+    #[test]
+    fn missing_or_invalid_credentials_are_rejected() {
+        let auth = auth();
+        let missing = Request::builder().uri("/sse").body(Body::empty()).unwrap();
+        let invalid = Request::builder()
+            .uri("/sse?api_key=nope")
+            .body(Body::empty())
+            .unwrap();
+        for request in [missing, invalid] {
+            let result = auth.authenticate(&parts(request));
+            assert!(matches!(result, Err(Error::Unauthorized(_))));
+        }
+    }
+
+    #[test]
+    fn bearer_token_verified_against_jwks() {
+        let mut header = Header::new(jsonwebtoken::Algorithm::HS256);
+        header.kid = Some("test".to_string());
+        let claims = json!({"sub": "dashboard", "services": ["x"], "exp": u32::MAX});
+        let token = encode(&header, &claims, &EncodingKey::from_secret(b"secret")).unwrap();
+        let request = Request::builder()
+            .uri("/sse")
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        let principal = auth().authenticate(&parts(request)).unwrap();
+        assert_eq!(principal.subject, "dashboard");
+        assert!(principal.scopes.allows(Service::X as i32));
+
+        let forged = encode(&header, &claims, &EncodingKey::from_secret(b"forged")).unwrap();
+        let request = Request::builder()
+            .uri("/sse")
+            .header("authorization", format!("Bearer {forged}"))
+            .body(Body::empty())
+            .unwrap();
+        assert!(auth().authenticate(&parts(request)).is_err());
+    }
+
+    #[test]
+    fn bearer_token_must_use_the_keys_algorithm() {
+        let claims = json!({"sub": "dashboard", "exp": u32::MAX});
+        let token = |algorithm| {
+            let mut header = Header::new(algorithm);
+            header.kid = Some("test".to_string());
+            let token = encode(&header, &claims, &EncodingKey::from_secret(b"secret")).unwrap();
+            Request::builder()
+                .uri("/sse")
+                .header("authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let result = auth().authenticate(&parts(token(Algorithm::HS512)));
+        assert!(matches!(result, Err(Error::Unauthorized(_))));
+
+        const UNDECLARED: &str = r#"{"keys": [{"kty": "oct", "kid": "test", "k": "c2VjcmV0"}]}"#;
+        let verifier = || JwtVerifier::from_json(UNDECLARED).unwrap();
+        let auth = Auth::default().with(verifier());
+        assert!(auth.authenticate(&parts(token(Algorithm::HS256))).is_err());
+        let allowed = ["HS384".to_string()];
+        let auth = Auth::default().with(verifier().with_algorithms(&allowed).unwrap());
+        assert!(auth.authenticate(&parts(token(Algorithm::HS256))).is_err());
+        assert!(auth.authenticate(&parts(token(Algorithm::HS384))).is_ok());
+        assert!(verifier().with_algorithms(&["none".to_string()]).is_err());
+    }
+}
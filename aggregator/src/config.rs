@@ -0,0 +1,288 @@
+use crate::error::Error;
+use std::{env, path::PathBuf, str::FromStr};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub redis_host: String,
+    pub redis_port: u16,
+    pub redis_password: String,
+    pub transport: Transport,
+    pub auth: AuthConfig,
+    pub limits: LimitConfig,
+    pub cors: CorsConfig,
+    pub trends: TrendsConfig,
+    pub stats: StatsConfig,
+    pub subscriptions: SubscriptionsConfig,
+}
+
+/// How batches arrive from the social-consumer; must match its `REDIS_TRANSPORT`.
+#[derive(Clone, Debug)]
+pub enum Transport {
+    PubSub {
+        channel: String,
+    },
+    /// A capped Redis stream whose entry ids double as SSE event ids.
+    Stream {
+        key: String,
+    },
+}
+
+impl Transport {
+    pub fn from_env() -> Result<Self, Error> {
+        match env::var("REDIS_TRANSPORT").as_deref() {
+            Ok("pubsub") | Err(_) => Ok(Transport::PubSub {
+                channel: env::var("REDIS_CHANNEL").unwrap_or_else(|_| "posts.live".to_string()),
+            }),
+            Ok("stream") => Ok(Transport::Stream {
+                key: env::var("REDIS_STREAM").unwrap_or_else(|_| "posts.stream".to_string()),
+            }),
+            Ok(other) => Err(Error::Config(format!(
+                "REDIS_TRANSPORT must be `pubsub` or `stream`, got `{other}`"
+            ))),
+        }
+    }
+}
+
+/// Authentication is disabled when neither an API key file nor a JWKS file is set.
+#[derive(Clone, Debug, Default)]
+pub struct AuthConfig {
+    pub api_keys_file: Option<PathBuf>,
+    pub jwks_file: Option<PathBuf>,
+    pub jwt_issuer: Option<String>,
+    pub jwt_audience: Option<String>,
+    /// Algorithms accepted for JWKS keys that do not declare their own `alg`.
+    pub jwt_algorithms: Vec<String>,
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Self {
+        AuthConfig {
+            api_keys_file: env::var("API_KEYS_FILE").ok().map(PathBuf::from),
+            jwks_file: env::var("JWKS_FILE").ok().map(PathBuf::from),
+            jwt_issuer: env::var("JWT_ISSUER").ok(),
+            jwt_audience: env::var("JWT_AUDIENCE").ok(),
+            jwt_algorithms: parse_list("JWT_ALGORITHMS").unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LimitConfig {
+    /// Open connections across all clients, streams included.
+    pub max_connections: usize,
+    /// Concurrent SSE streams a single authenticated caller or IP may hold.
+    pub max_streams_per_client: usize,
+    /// Sustained request rate per client on non-streaming routes.
+    pub requests_per_minute: u32,
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        LimitConfig {
+            max_connections: 1000,
+            max_streams_per_client: 5,
+            requests_per_minute: 120,
+        }
+    }
+}
+
+impl LimitConfig {
+    pub fn from_env() -> Result<Self, Error> {
+        let defaults = LimitConfig::default();
+        Ok(LimitConfig {
+            max_connections: parse_env("MAX_CONNECTIONS", defaults.max_connections)?,
+            max_streams_per_client: parse_env(
+                "MAX_STREAMS_PER_CLIENT",
+                defaults.max_streams_per_client,
+            )?,
+            requests_per_minute: parse_env("REQUESTS_PER_MINUTE", defaults.requests_per_minute)?,
+        })
+    }
+}
+
+fn parse_env<T: FromStr>(name: &str, default: T) -> Result<T, Error> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| Error::Config(format!("{name} has an invalid value `{value}`"))),
+        Err(_) => Ok(default),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CorsConfig {
+    /// Exact origins or patterns such as `https://*.example.com` and `http://localhost:*`.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+}
+
+impl CorsConfig {
+    /// Any local port, so Vite and Storybook dev servers work out of the box.
+    pub fn dev() -> Self {
+        CorsConfig {
+            allowed_origins: vec![
+                "http://localhost:*".to_string(),
+                "http://127.0.0.1:*".to_string(),
+            ],
+            ..Self::prod()
+        }
+    }
+
+    /// No origins are allowed until `CORS_ALLOWED_ORIGINS` lists them.
+    pub fn prod() -> Self {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".to_string()],
+            allowed_headers: [
+                "authorization",
+                "content-type",
+                "last-event-id",
+                "x-api-key",
+            ]
+            .map(String::from)
+            .to_vec(),
+            allow_credentials: false,
+        }
+    }
+
+    pub fn from_env() -> Result<Self, Error> {
+        let mut config = match env::var("CORS_PRESET").as_deref() {
+            // Unset means production, so a forgotten variable fails closed.
+            Ok("prod") | Err(_) => Self::prod(),
+            Ok("dev") => Self::dev(),
+            Ok(other) => {
+                return Err(Error::Config(format!(
+                    "CORS_PRESET must be `dev` or `prod`, got `{other}`"
+                )));
+            }
+        };
+        if let Some(origins) = parse_list("CORS_ALLOWED_ORIGINS") {
+            config.allowed_origins = origins;
+        }
+        if let Some(methods) = parse_list("CORS_ALLOWED_METHODS") {
+            config.allowed_methods = methods;
+        }
+        if let Some(headers) = parse_list("CORS_ALLOWED_HEADERS") {
+            config.allowed_headers = headers;
+        }
+        config.allow_credentials = parse_env("CORS_ALLOW_CREDENTIALS", config.allow_credentials)?;
+        Ok(config)
+    }
+}
+
+fn parse_list(name: &str) -> Option<Vec<String>> {
+    env::var(name).ok().map(|value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect()
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct TrendsConfig {
+    /// Key prefix; must match the social-consumer's `TRENDS_PREFIX`.
+    pub prefix: String,
+    /// How often SSE clients get a `trends` event, 0 disables them.
+    pub interval_secs: u64,
+}
+
+impl Default for TrendsConfig {
+    fn default() -> Self {
+        TrendsConfig {
+            prefix: "trends".to_string(),
+            interval_secs: 30,
+        }
+    }
+}
+
+impl TrendsConfig {
+    pub fn from_env() -> Result<Self, Error> {
+        let defaults = TrendsConfig::default();
+        Ok(TrendsConfig {
+            prefix: env::var("TRENDS_PREFIX").unwrap_or(defaults.prefix),
+            interval_secs: parse_env("TRENDS_INTERVAL_SECS", defaults.interval_secs)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StatsConfig {
+    /// Key prefix; must match the social-consumer's `STATS_PREFIX`.
+    pub prefix: String,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        StatsConfig {
+            prefix: "stats".to_string(),
+        }
+    }
+}
+
+impl StatsConfig {
+    pub fn from_env() -> Self {
+        StatsConfig {
+            prefix: env::var("STATS_PREFIX").unwrap_or(StatsConfig::default().prefix),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SubscriptionsConfig {
+    /// Matches for rule `id` arrive on `{channel_prefix}.{id}`; must match the
+    /// social-consumer's `SUBSCRIPTIONS_CHANNEL_PREFIX`.
+    pub channel_prefix: String,
+}
+
+impl Default for SubscriptionsConfig {
+    fn default() -> Self {
+        SubscriptionsConfig {
+            channel_prefix: "subscriptions".to_string(),
+        }
+    }
+}
+
+impl SubscriptionsConfig {
+    pub fn from_env() -> Self {
+        SubscriptionsConfig {
+            channel_prefix: env::var("SUBSCRIPTIONS_CHANNEL_PREFIX")
+                .unwrap_or(SubscriptionsConfig::default().channel_prefix),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, Error> {
+        let redis_host = env::var("REDIS_HOST").unwrap_or_else(|_| "redis".to_string());
+        let redis_port = env::var("REDIS_PORT")
+            .unwrap_or_else(|_| "6379".to_string())
+            .parse::<u16>()
+            .map_err(|_| Error::Config("REDIS_PORT must be a valid port".to_string()))?;
+        let redis_password = env::var("REDIS_PASSWORD")
+            .map_err(|_| Error::Config("REDIS_PASSWORD must be set".to_string()))?;
+        Ok(Config {
+            redis_host,
+            redis_port,
+            redis_password,
+            transport: Transport::from_env()?,
+            auth: AuthConfig::from_env(),
+            limits: LimitConfig::from_env()?,
+            cors: CorsConfig::from_env()?,
+            trends: TrendsConfig::from_env()?,
+            stats: StatsConfig::from_env(),
+            subscriptions: SubscriptionsConfig::from_env(),
+        })
+    }
+
+    pub fn redis_url(&self) -> String {
+        format!(
+            "redis://:{}@{}:{}",
+            self.redis_password, self.redis_host, self.redis_port
+        )
+    }
+}
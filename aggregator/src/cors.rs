@@ -0,0 +1,235 @@
+use crate::{config::CorsConfig, error::Error};
+use axum::http::{HeaderName, HeaderValue, Method, request::Parts};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// An allowed origin: `*`, an exact origin, or a pattern such as
+/// `https://*.example.com` or `http://localhost:*`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OriginPattern {
+    Any,
+    Pattern {
+        scheme: String,
+        host: HostPattern,
+        port: PortPattern,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostPattern {
+    Exact(String),
+    /// Matches any subdomain of the given suffix, but not the bare domain.
+    Subdomain(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PortPattern {
+    Default,
+    Exact(u16),
+    Any,
+}
+
+impl OriginPattern {
+    pub fn parse(pattern: &str) -> Result<Self, Error> {
+        let pattern = pattern.trim();
+        if pattern == "*" {
+            return Ok(OriginPattern::Any);
+        }
+        let invalid = || Error::Config(format!("invalid CORS origin pattern `{pattern}`"));
+        let (scheme, authority) = pattern.split_once("://").ok_or_else(invalid)?;
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, "*")) => (host, PortPattern::Any),
+            Some((host, port)) => (
+                host,
+                PortPattern::Exact(port.parse().map_err(|_| invalid())?),
+            ),
+            None => (authority, PortPattern::Default),
+        };
+        let host = match host.strip_prefix("*.") {
+            Some(suffix) if !suffix.is_empty() => HostPattern::Subdomain(suffix.to_lowercase()),
+            Some(_) => return Err(invalid()),
+            None if !host.is_empty() && !host.contains('*') => {
+                HostPattern::Exact(host.to_lowercase())
+            }
+            None => return Err(invalid()),
+        };
+        Ok(OriginPattern::Pattern {
+            scheme: scheme.to_lowercase(),
+            host,
+            port,
+        })
+    }
+
+    pub fn matches(&self, origin: &str) -> bool {
+        let OriginPattern::Pattern { scheme, host, port } = self else {
+            return true;
+        };
+        let Some((origin_scheme, authority)) = origin.split_once("://") else {
+            return false;
+        };
+        let (origin_host, origin_port) = match authority.rsplit_once(':') {
+            Some((host, port)) => match port.parse::<u16>() {
+                Ok(port) => (host, Some(port)),
+                Err(_) => return false,
+            },
+            None => (authority, None),
+        };
+        let origin_host = origin_host.to_lowercase();
+        let host_matches = match host {
+            HostPattern::Exact(exact) => origin_host == *exact,
+            HostPattern::Subdomain(suffix) => origin_host
+                .strip_suffix(suffix.as_str())
+                .is_some_and(|prefix| prefix.len() > 1 && prefix.ends_with('.')),
+        };
+        let port_matches = match port {
+            PortPattern::Default => origin_port.is_none(),
+            PortPattern::Exact(exact) => origin_port == Some(*exact),
+            PortPattern::Any => true,
+        };
+        origin_scheme.eq_ignore_ascii_case(scheme) && host_matches && port_matches
+    }
+}
+
+pub fn layer(config: &CorsConfig) -> Result<CorsLayer, Error> {
+    let origins = config
+        .allowed_origins
+        .iter()
+        .map(|origin| OriginPattern::parse(origin))
+        .collect::<Result<Vec<_>, _>>()?;
+    if config.allow_credentials && origins.contains(&OriginPattern::Any) {
+        return Err(Error::Config(
+            "CORS origin `*` cannot be combined with allowing credentials".to_string(),
+        ));
+    }
+    let methods = config
+        .allowed_methods
+        .iter()
+        .map(|method| {
+            method
+                .parse::<Method>()
+                .map_err(|_| Error::Config(format!("invalid CORS method `{method}`")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let headers = config
+        .allowed_headers
+        .iter()
+        .map(|header| {
+            header
+                .parse::<HeaderName>()
+                .map_err(|_| Error::Config(format!("invalid CORS header `{header}`")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // A predicate mirrors the request origin so that patterns can be matched;
+    // `*` is refused above when credentials are allowed.
+    let allow_origin = AllowOrigin::predicate(move |origin: &HeaderValue, _: &Parts| {
+        origin
+            .to_str()
+            .is_ok_and(|origin| origins.iter().any(|pattern| pattern.matches(origin)))
+    });
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .allow_credentials(config.allow_credentials))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{OriginPattern, layer};
+    use crate::{
+        config::CorsConfig,
+        error::Error,
+        test::{get_router_with, test_config},
+    };
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode, header},
+    };
+    use tower::ServiceExt;
+
+    fn preflight(origin: &str) -> Request<Body> {
+        Request::builder()
+            .method("OPTIONS")
+            .uri("/sse")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "x-api-key")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn prod_router() -> Router {
+        let mut config = test_config();
+        config.cors = CorsConfig {
+            allowed_origins: vec![
+                "https://ui.example.com".to_string(),
+                "https://*.preview.example.com".to_string(),
+            ],
+            allow_credentials: true,
+            ..CorsConfig::prod()
+        };
+        get_router_with(config)
+    }
+
+    #[test]
+    fn origin_patterns_match() {
+        let subdomain = OriginPattern::parse("https://*.example.com").unwrap();
+        assert!(subdomain.matches("https://a.example.com"));
+        assert!(subdomain.matches("https://a.b.example.com"));
+        assert!(!subdomain.matches("https://example.com"));
+        assert!(!subdomain.matches("https://evilexample.com"));
+        assert!(!subdomain.matches("http://a.example.com"));
+
+        let any_port = OriginPattern::parse("http://localhost:*").unwrap();
+        assert!(any_port.matches("http://localhost:5173"));
+        assert!(any_port.matches("http://localhost"));
+        assert!(!any_port.matches("http://localhost.evil.com:80"));
+        assert!(OriginPattern::parse("example.com").is_err());
+    }
+
+    #[test]
+    fn any_origin_is_refused_with_credentials() {
+        let config = CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allow_credentials: true,
+            ..CorsConfig::prod()
+        };
+        assert!(matches!(layer(&config), Err(Error::Config(_))));
+        let config = CorsConfig {
+            allow_credentials: false,
+            ..config
+        };
+        assert!(layer(&config).is_ok());
+    }
+
+    #[tokio::test]
+    async fn preflight_allows_configured_origins() {
+        for origin in ["https://ui.example.com", "https://pr-1.preview.example.com"] {
+            let response = prod_router().oneshot(preflight(origin)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let headers = response.headers();
+            assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+            assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+            assert!(
+                headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+                    .to_str()
+                    .unwrap()
+                    .contains("x-api-key")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn preflight_rejects_unknown_origins() {
+        let response = prod_router()
+            .oneshot(preflight("http://localhost:5173"))
+            .await
+            .unwrap();
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+}
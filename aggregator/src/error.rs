@@ -2,13 +2,20 @@
 use axum::{
     Json,
     extract::rejection::JsonRejection,
-    http::StatusCode,
+    http::{
+        HeaderValue, StatusCode,
+        header::{RETRY_AFTER, WWW_AUTHENTICATE},
+    },
     response::{IntoResponse, Response},
 };
+use redis::RedisError;
 use serde::Serialize;
 use thiserror::Error as ThisError;
 use validator::ValidationErrors;
 
+/// Seconds a client is asked to wait before retrying when a backing service is down.
+pub const RETRY_AFTER_SECS: u64 = 5;
+
 #[derive(Debug, ThisError)]
 pub enum Error {
     #[error("Resource not found")]
@@ -20,6 +27,18 @@ pub enum Error {
     JsonRejection(#[from] JsonRejection),
     #[error("Validation Error: {0}")]
     Validation(#[from] ValidationErrors),
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    #[error("Service temporarily unavailable: {0}")]
+    Redis(#[from] RedisError),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    #[error("Too many requests: {reason} limit exceeded")]
+    TooManyRequests { reason: String, retry_after: u64 },
 }
 
 #[derive(Serialize)]
@@ -27,14 +46,42 @@ struct ErrorResponse {
     message: String,
 }
 
+impl Error {
+    fn retry_after(&self) -> Option<u64> {
+        match self {
+            Self::Redis(_) => Some(RETRY_AFTER_SECS),
+            Self::TooManyRequests { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        }
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
+        let retry_after = self.retry_after();
         let (status, message) = match self {
             Self::NotFound => (StatusCode::NOT_FOUND, self.to_string()),
             Self::InternalServerError => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
             Self::JsonRejection(rejection) => (rejection.status(), rejection.body_text()),
             Self::Validation(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            Self::BadRequest(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            Self::Config(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
+            Self::Redis(_) => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            Self::Unauthorized(_) => (StatusCode::UNAUTHORIZED, self.to_string()),
+            Self::Forbidden(_) => (StatusCode::FORBIDDEN, self.to_string()),
+            Self::TooManyRequests { .. } => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
         };
-        (status, Json(ErrorResponse { message })).into_response()
+        let mut response = (status, Json(ErrorResponse { message })).into_response();
+        if status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
     }
 }
@@ -0,0 +1,77 @@
+use crate::{auth::Scopes, config::Transport, error::Error};
+use axum::response::sse::Event;
+use futures_util::stream::BoxStream;
+use prost::Message;
+use proto_definitions::v1::PostBatch;
+use redis::RedisError;
+use std::{convert::Infallible, time::Duration};
+use tokio::time::sleep;
+use tracing::{error, warn};
+
+pub mod pubsub;
+pub mod stream;
+
+pub type EventStream = BoxStream<'static, Result<Event, Infallible>>;
+
+const RECONNECT_MIN_BACKOFF: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Opens a live feed of post batches for `scopes`. Connection errors here are
+/// returned so the handler can answer 503; once streaming, the feed reconnects
+/// on its own.
+pub async fn open(
+    client: redis::Client,
+    transport: &Transport,
+    scopes: Scopes,
+    last_event_id: Option<String>,
+) -> Result<EventStream, Error> {
+    match transport {
+        Transport::PubSub { channel } => pubsub::open(client, channel.clone(), scopes).await,
+        Transport::Stream { key } => stream::open(client, key.clone(), scopes, last_event_id).await,
+    }
+}
+
+/// Decodes a `PostBatch`, drops posts outside `scopes` and renders the rest
+/// as a JSON event. Returns `None` when there is nothing to send.
+fn batch_event(payload: &[u8], scopes: &Scopes) -> Option<Event> {
+    let mut post_batch = match PostBatch::decode(payload) {
+        Ok(batch) => batch,
+        Err(e) => {
+            error!("Failed to decode Protobuf message: {}", e);
+            return None;
+        }
+    };
+    post_batch.posts.retain(|post| scopes.allows(post.service));
+    if post_batch.posts.is_empty() {
+        return None;
+    }
+    match serde_json::to_string(&post_batch) {
+        Ok(json) => Some(Event::default().data(json)),
+        Err(e) => {
+            error!("Failed to serialize PostBatch to JSON: {}", e);
+            None
+        }
+    }
+}
+
+/// Retries `connect` with exponential backoff until it succeeds.
+async fn reconnect<T, F, Fut>(what: &str, mut connect: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, RedisError>>,
+{
+    let mut backoff = RECONNECT_MIN_BACKOFF;
+    loop {
+        match connect().await {
+            Ok(connection) => return connection,
+            Err(e) => {
+                warn!(
+                    "Failed to reconnect to Redis {}: {}. Retrying in {:?}",
+                    what, e, backoff
+                );
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF);
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use super::{EventStream, batch_event, reconnect};
+use crate::{auth::Scopes, error::Error};
+use futures_util::stream::{self, StreamExt};
+use redis::{RedisError, aio::PubSub};
+use tracing::{error, info, warn};
+
+struct Subscription {
+    client: redis::Client,
+    channel: String,
+    pubsub: Option<PubSub>,
+    scopes: Scopes,
+}
+
+async fn subscribe(client: &redis::Client, channel: &str) -> Result<PubSub, RedisError> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(channel).await?;
+    Ok(pubsub)
+}
+
+/// Pub/sub only delivers what is published while subscribed, so anything sent
+/// during a reconnect is lost.
+pub async fn open(
+    client: redis::Client,
+    channel: String,
+    scopes: Scopes,
+) -> Result<EventStream, Error> {
+    let pubsub = subscribe(&client, &channel).await?;
+    let subscription = Subscription {
+        client,
+        channel,
+        pubsub: Some(pubsub),
+        scopes,
+    };
+
+    let stream = stream::unfold(subscription, |mut subscription| async {
+        loop {
+            let Some(pubsub) = subscription.pubsub.as_mut() else {
+                let pubsub = reconnect("pubsub", || {
+                    subscribe(&subscription.client, &subscription.channel)
+                })
+                .await;
+                info!("Resubscribed to Redis channel '{}'", subscription.channel);
+                subscription.pubsub = Some(pubsub);
+                continue;
+            };
+            let Some(msg) = pubsub.on_message().next().await else {
+                warn!("Redis subscription closed, reconnecting");
+                subscription.pubsub = None;
+                continue;
+            };
+            let payload: Vec<u8> = match msg.get_payload() {
+                Ok(payload) => payload,
+                Err(e) => {
+                    error!("Failed to get payload from Redis message: {}", e);
+                    continue;
+                }
+            };
+            if let Some(event) = batch_event(&payload, &subscription.scopes) {
+                return Some((Ok(event), subscription));
+            }
+        }
+    });
+    Ok(stream.boxed())
+}
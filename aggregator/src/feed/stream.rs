@@ -0,0 +1,203 @@
+use super::{EventStream, batch_event, reconnect};
+use crate::{auth::Scopes, error::Error};
+use axum::response::sse::Event;
+use futures_util::stream::{self, StreamExt};
+use redis::{
+    AsyncCommands, RedisError,
+    aio::MultiplexedConnection,
+    streams::{StreamRangeReply, StreamReadOptions, StreamReadReply},
+};
+use std::collections::VecDeque;
+use tracing::{debug, info, warn};
+
+/// Must match the field the social-consumer writes batches under.
+const BATCH_FIELD: &str = "batch";
+const BLOCK_MS: usize = 5_000;
+const READ_COUNT: usize = 16;
+
+struct Reader {
+    client: redis::Client,
+    key: String,
+    connection: Option<MultiplexedConnection>,
+    /// Id of the last entry read; sent as the SSE event id so clients can
+    /// resume with `Last-Event-ID`.
+    last_id: String,
+    pending: VecDeque<Event>,
+    scopes: Scopes,
+}
+
+/// Redis stream ids are `<millis>-<sequence>`.
+fn parse_stream_id(id: &str) -> Option<(u64, u64)> {
+    let (millis, seq) = id.split_once('-')?;
+    Some((millis.parse().ok()?, seq.parse().ok()?))
+}
+
+fn is_stream_id(id: &str) -> bool {
+    parse_stream_id(id).is_some()
+}
+
+/// Where a client's `XREAD` cursor starts.
+#[derive(Debug, PartialEq, Eq)]
+enum Start {
+    /// After the client's `Last-Event-ID`.
+    Resume(String),
+    /// After a `Last-Event-ID` older than anything left in the stream, so
+    /// entries trimmed by `MAXLEN` may have been missed.
+    Trimmed(String),
+    /// After the newest entry, or from the beginning of an empty stream.
+    Tail(String),
+}
+
+/// Resumes after `last_event_id` when it is a stream id the stream can still
+/// move past, otherwise starts at the tail. Ids beyond the newest entry, as
+/// left by a stream that was deleted and recreated, would never be passed.
+fn start_cursor(last_event_id: Option<&str>, oldest: Option<&str>, newest: Option<&str>) -> Start {
+    let tail = || Start::Tail(newest.unwrap_or("0-0").to_string());
+    let Some((id, resume)) =
+        last_event_id.and_then(|id| parse_stream_id(id).map(|parsed| (id, parsed)))
+    else {
+        return tail();
+    };
+    match newest.and_then(parse_stream_id) {
+        Some(newest) if resume <= newest => {}
+        _ => return tail(),
+    }
+    match oldest.and_then(parse_stream_id) {
+        Some(oldest) if resume < oldest => Start::Trimmed(id.to_string()),
+        _ => Start::Resume(id.to_string()),
+    }
+}
+
+fn first_id(reply: StreamRangeReply) -> Option<String> {
+    reply.ids.into_iter().next().map(|entry| entry.id)
+}
+
+/// Reads from a capped Redis stream with a per-client `XREAD` cursor. Consumer
+/// groups are not used because every client needs every entry.
+pub async fn open(
+    client: redis::Client,
+    key: String,
+    scopes: Scopes,
+    last_event_id: Option<String>,
+) -> Result<EventStream, Error> {
+    let mut connection = client.get_multiplexed_async_connection().await?;
+    let resume = last_event_id.filter(|id| is_stream_id(id));
+    // Pin the cursor to the current tail instead of `$`, so entries added
+    // between two blocking reads are not skipped.
+    let newest = first_id(connection.xrevrange_count(&key, "+", "-", 1).await?);
+    let oldest = match resume {
+        Some(_) => first_id(connection.xrange_count(&key, "-", "+", 1).await?),
+        None => None,
+    };
+    let last_id = match start_cursor(resume.as_deref(), oldest.as_deref(), newest.as_deref()) {
+        Start::Resume(id) => {
+            debug!("Resuming stream '{}' after {}", key, id);
+            id
+        }
+        Start::Trimmed(id) => {
+            warn!(
+                "Resuming stream '{}' after {}, which has been trimmed; entries may be missing",
+                key, id
+            );
+            id
+        }
+        Start::Tail(id) => id,
+    };
+    let reader = Reader {
+        client,
+        key,
+        connection: Some(connection),
+        last_id,
+        pending: VecDeque::new(),
+        scopes,
+    };
+
+    let stream = stream::unfold(reader, |mut reader| async {
+        loop {
+            if let Some(event) = reader.pending.pop_front() {
+                return Some((Ok(event), reader));
+            }
+            let Some(connection) = reader.connection.as_mut() else {
+                let connection = reconnect("stream", || {
+                    reader.client.get_multiplexed_async_connection()
+                })
+                .await;
+                info!("Reconnected to Redis stream '{}'", reader.key);
+                reader.connection = Some(connection);
+                continue;
+            };
+            let options = StreamReadOptions::default()
+                .block(BLOCK_MS)
+                .count(READ_COUNT);
+            let reply: Result<Option<StreamReadReply>, RedisError> = connection
+                .xread_options(&[&reader.key], &[&reader.last_id], &options)
+                .await;
+            let reply = match reply {
+                Ok(Some(reply)) => reply,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Failed to read Redis stream: {}, reconnecting", e);
+                    reader.connection = None;
+                    continue;
+                }
+            };
+            for entry in reply.keys.into_iter().flat_map(|key| key.ids) {
+                reader.last_id = entry.id.clone();
+                let Some(payload) = entry.get::<Vec<u8>>(BATCH_FIELD) else {
+                    warn!("Stream entry {} has no `{}` field", entry.id, BATCH_FIELD);
+                    continue;
+                };
+                if let Some(event) = batch_event(&payload, &reader.scopes) {
+                    reader.pending.push_back(event.id(entry.id));
+                }
+            }
+        }
+    });
+    Ok(stream.boxed())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Start, is_stream_id, start_cursor};
+
+    #[test]
+    fn only_stream_ids_resume() {
+        assert!(is_stream_id("1718000000000-0"));
+        assert!(!is_stream_id("$"));
+        assert!(!is_stream_id("1718000000000"));
+        assert!(!is_stream_id("abc-1"));
+    }
+
+    #[test]
+    fn cursor_starts_from_last_event_id_or_tail() {
+        let (oldest, newest) = (Some("100-0"), Some("200-3"));
+        assert_eq!(
+            start_cursor(Some("150-1"), oldest, newest),
+            Start::Resume("150-1".to_string())
+        );
+        assert_eq!(
+            start_cursor(Some("200-3"), oldest, newest),
+            Start::Resume("200-3".to_string())
+        );
+        assert_eq!(
+            start_cursor(Some("99-9"), oldest, newest),
+            Start::Trimmed("99-9".to_string())
+        );
+        assert_eq!(
+            start_cursor(None, None, newest),
+            Start::Tail("200-3".to_string())
+        );
+        assert_eq!(
+            start_cursor(Some("garbage"), oldest, newest),
+            Start::Tail("200-3".to_string())
+        );
+        assert_eq!(
+            start_cursor(Some("300-0"), oldest, newest),
+            Start::Tail("200-3".to_string())
+        );
+        assert_eq!(
+            start_cursor(Some("150-1"), None, None),
+            Start::Tail("0-0".to_string())
+        );
+    }
+}
@@ -1,55 +1,121 @@
+mod auth;
+mod config;
+mod cors;
 mod error;
+mod feed;
 mod json;
+mod limit;
 mod routes;
+mod trends;
 
-use axum::{
-    http::{HeaderValue, Method},
-    routing::get,
+pub use config::{
+    AuthConfig, Config, CorsConfig, LimitConfig, StatsConfig, SubscriptionsConfig, Transport,
+    TrendsConfig,
+};
+pub use error::Error;
+
+use axum::{middleware, routing::get};
+use std::sync::Arc;
+use tower_http::trace::TraceLayer;
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
 };
-use std::env;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
-use utoipa::OpenApi;
 use utoipa_axum::router::OpenApiRouter;
 
 #[derive(Clone, Debug)]
 struct AppState {
     redis_client: redis::Client,
+    transport: Transport,
+    limiter: Arc<limit::Limiter>,
+    trends: TrendsConfig,
+    trend_events: trends::TrendEvents,
+    stats: StatsConfig,
+    subscriptions: SubscriptionsConfig,
 }
 
-pub fn router() -> OpenApiRouter {
-    let cors_origin = "http://localhost:5173".parse::<HeaderValue>().unwrap();
-    let redis_host = env::var("REDIS_HOST").unwrap_or_else(|_| "redis".to_string());
-    let redis_port = env::var("REDIS_PORT").unwrap_or_else(|_| "6379".to_string());
-    let redis_password = env::var("REDIS_PASSWORD").expect("REDIS_PASSWORD must be set"); // Or handle the error gracefully
-    let redis_url = format!("redis://:{}@{}:{}", redis_password, redis_host, redis_port);
-
-    let redis_client = redis::Client::open(redis_url).expect("Failed to create Redis client");
-    let app_state = AppState { redis_client };
+pub fn router(config: Config) -> Result<OpenApiRouter, Error> {
+    let redis_client = redis::Client::open(config.redis_url())?;
+    let auth = auth::Auth::from_config(&config.auth)?;
+    let cors = cors::layer(&config.cors)?;
+    let limiter = Arc::new(limit::Limiter::new(config.limits).with_auth(auth.clone()));
+    let trend_events = trends::TrendEvents::new(redis_client.clone(), config.trends.clone());
+    let app_state = AppState {
+        redis_client,
+        transport: config.transport,
+        limiter: limiter.clone(),
+        trends: config.trends,
+        trend_events,
+        stats: config.stats,
+        subscriptions: config.subscriptions,
+    };
 
-    OpenApiRouter::new()
+    Ok(OpenApiRouter::new()
         .route("/sse", get(routes::sse))
+        .route("/trends", get(routes::trends))
+        .route("/stats", get(routes::stats))
+        .route("/subscriptions/{id}/sse", get(routes::subscription_sse))
+        .route_layer(middleware::from_fn_with_state(auth, auth::middleware))
         .route("/health", get(routes::health))
+        .route("/metrics", get(routes::metrics))
         .fallback(routes::not_found)
         .with_state(app_state)
-        .layer(
-            CorsLayer::new()
-                .allow_origin(cors_origin)
-                .allow_methods([Method::GET]),
-        )
-        .layer(TraceLayer::new_for_http())
+        .layer(middleware::from_fn_with_state(limiter, limit::middleware))
+        .layer(cors)
+        .layer(TraceLayer::new_for_http()))
 }
 
 #[derive(OpenApi)]
 #[openapi(
     info(title = "Aggregator", description = "Social Aggregator",),
-    paths(routes::health::route, routes::sse::route,)
+    paths(
+        routes::health::route,
+        routes::metrics::route,
+        routes::sse::route,
+        routes::trends::route,
+        routes::stats::route,
+        routes::subscriptions::route,
+    ),
+    modifiers(&SecuritySchemes)
 )]
 pub struct ApiDoc;
 
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(
+                auth::api_key::API_KEY_HEADER,
+            ))),
+        );
+        components.add_security_scheme(
+            "api_key_query",
+            SecurityScheme::ApiKey(ApiKey::Query(ApiKeyValue::new(
+                auth::api_key::API_KEY_QUERY,
+            ))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
 #[cfg(test)]
 mod test {
     #![allow(unused)]
-    use super::router;
+    use super::{
+        AuthConfig, Config, CorsConfig, LimitConfig, StatsConfig, SubscriptionsConfig, Transport,
+        TrendsConfig, router,
+    };
     use axum::{
         Router,
         body::Body,
@@ -57,10 +123,32 @@ mod test {
     };
     use http_body_util::BodyExt;
     use serde_json::Value;
+    use std::sync::Arc;
     use tower::ServiceExt;
 
+    pub fn test_config() -> Config {
+        Config {
+            redis_host: "127.0.0.1".to_string(),
+            redis_port: 1,
+            redis_password: "password".to_string(),
+            transport: Transport::PubSub {
+                channel: "posts.test".to_string(),
+            },
+            auth: AuthConfig::default(),
+            limits: LimitConfig::default(),
+            cors: CorsConfig::dev(),
+            trends: TrendsConfig::default(),
+            stats: StatsConfig::default(),
+            subscriptions: SubscriptionsConfig::default(),
+        }
+    }
+
     pub fn get_router() -> Router {
-        router().split_for_parts().0
+        get_router_with(test_config())
+    }
+
+    pub fn get_router_with(config: Config) -> Router {
+        router(config).unwrap().split_for_parts().0
     }
 
     pub async fn get_response_body(response: Response<Body>) -> Value {
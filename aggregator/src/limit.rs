@@ -0,0 +1,391 @@
+use crate::{auth::Auth, config::LimitConfig, error::Error};
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{ConnectInfo, Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::Response,
+};
+use http_body::{Frame, SizeHint};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+use tracing::{instrument, warn};
+
+/// Routes ending in this are long-lived streams rather than REST calls.
+const STREAM_SUFFIX: &str = "/sse";
+/// Buckets idle for longer than this are refilled anyway, so they can be dropped.
+const BUCKET_IDLE_SECS: f64 = 60.0;
+const BUCKET_SWEEP_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+enum Rejection {
+    Connections,
+    Streams,
+    Rate,
+}
+
+impl Rejection {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Rejection::Connections => "connections",
+            Rejection::Streams => "streams",
+            Rejection::Rate => "rate",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Shared limiter state behind the `middleware` layer.
+#[derive(Debug)]
+pub struct Limiter {
+    config: LimitConfig,
+    auth: Auth,
+    connections: AtomicUsize,
+    streams: Mutex<HashMap<String, usize>>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    rejected_connections: AtomicU64,
+    rejected_streams: AtomicU64,
+    rejected_rate: AtomicU64,
+}
+
+impl Limiter {
+    pub fn new(config: LimitConfig) -> Self {
+        Limiter {
+            config,
+            auth: Auth::default(),
+            connections: AtomicUsize::new(0),
+            streams: Mutex::new(HashMap::new()),
+            buckets: Mutex::new(HashMap::new()),
+            rejected_connections: AtomicU64::new(0),
+            rejected_streams: AtomicU64::new(0),
+            rejected_rate: AtomicU64::new(0),
+        }
+    }
+
+    /// Verifies credentials with `auth` so clients holding one are limited
+    /// by who they are rather than by address.
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    fn reject(&self, rejection: Rejection, client: &str, retry_after: u64) -> Error {
+        let counter = match rejection {
+            Rejection::Connections => &self.rejected_connections,
+            Rejection::Streams => &self.rejected_streams,
+            Rejection::Rate => &self.rejected_rate,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        warn!(client, reason = rejection.as_str(), "request rejected");
+        Error::TooManyRequests {
+            reason: rejection.as_str().to_string(),
+            retry_after,
+        }
+    }
+
+    fn acquire_connection(self: &Arc<Self>, client: &str) -> Result<Permit, Error> {
+        let previous = self.connections.fetch_add(1, Ordering::AcqRel);
+        let permit = Permit {
+            limiter: self.clone(),
+            stream_client: None,
+        };
+        if previous >= self.config.max_connections {
+            drop(permit);
+            return Err(self.reject(Rejection::Connections, client, 1));
+        }
+        Ok(permit)
+    }
+
+    fn acquire_stream(&self, permit: &mut Permit, client: &str) -> Result<(), Error> {
+        let mut streams = self.streams.lock().unwrap();
+        let active = streams.entry(client.to_string()).or_default();
+        if *active >= self.config.max_streams_per_client {
+            drop(streams);
+            return Err(self.reject(Rejection::Streams, client, 5));
+        }
+        *active += 1;
+        permit.stream_client = Some(client.to_string());
+        Ok(())
+    }
+
+    fn check_rate(&self, client: &str) -> Result<(), Error> {
+        let capacity = f64::from(self.config.requests_per_minute);
+        let per_second = capacity / 60.0;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > BUCKET_SWEEP_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                now.duration_since(bucket.updated).as_secs_f64() < BUCKET_IDLE_SECS
+            });
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            let retry_after = ((1.0 - bucket.tokens) / per_second).ceil() as u64;
+            drop(buckets);
+            return Err(self.reject(Rejection::Rate, client, retry_after.max(1)));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Renders current usage and rejection counts in the Prometheus text format.
+    pub fn render_metrics(&self) -> String {
+        let mut out = String::new();
+        let streams: usize = self.streams.lock().unwrap().values().sum();
+        let _ = writeln!(
+            out,
+            "# TYPE aggregator_connections gauge\naggregator_connections {}",
+            self.connections.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE aggregator_streams gauge\naggregator_streams {streams}"
+        );
+        let _ = writeln!(out, "# TYPE aggregator_rejections_total counter");
+        for (rejection, counter) in [
+            (Rejection::Connections, &self.rejected_connections),
+            (Rejection::Streams, &self.rejected_streams),
+            (Rejection::Rate, &self.rejected_rate),
+        ] {
+            let _ = writeln!(
+                out,
+                "aggregator_rejections_total{{reason=\"{}\"}} {}",
+                rejection.as_str(),
+                counter.load(Ordering::Relaxed)
+            );
+        }
+        out
+    }
+}
+
+/// Held for as long as the response body is alive, so SSE streams keep
+/// their slot until the client goes away.
+#[derive(Debug)]
+struct Permit {
+    limiter: Arc<Limiter>,
+    stream_client: Option<String>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.connections.fetch_sub(1, Ordering::AcqRel);
+        if let Some(client) = self.stream_client.take() {
+            let mut streams = self.limiter.streams.lock().unwrap();
+            if let Some(active) = streams.get_mut(&client) {
+                *active -= 1;
+                if *active == 0 {
+                    streams.remove(&client);
+                }
+            }
+        }
+    }
+}
+
+struct PermitBody {
+    inner: Body,
+    _permit: Permit,
+}
+
+impl HttpBody for PermitBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Identifies a client by its verified principal, otherwise by peer address.
+/// Credentials that do not check out are ignored, so made-up keys cannot
+/// buy a fresh bucket. A verified principal is left in the extensions for
+/// the auth layer to reuse.
+fn client_id(auth: &Auth, parts: &mut Parts) -> String {
+    if let Some(principal) = auth.identify(parts) {
+        let client = format!("sub:{}", principal.subject);
+        parts.extensions.insert(principal);
+        return client;
+    }
+    match parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+#[instrument(name = "limit", target = "api::limit", skip_all)]
+pub async fn middleware(
+    State(limiter): State<Arc<Limiter>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let (mut parts, body) = request.into_parts();
+    let client = client_id(&limiter.auth, &mut parts);
+    let request = Request::from_parts(parts, body);
+    let mut permit = limiter.acquire_connection(&client)?;
+    if request.uri().path().ends_with(STREAM_SUFFIX) {
+        limiter.acquire_stream(&mut permit, &client)?;
+    } else {
+        limiter.check_rate(&client)?;
+    }
+    let response = next.run(request).await;
+    Ok(response.map(|inner| {
+        Body::new(PermitBody {
+            inner,
+            _permit: permit,
+        })
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Limiter, Rejection, middleware};
+    use crate::{
+        auth::{ApiKeys, Auth},
+        config::LimitConfig,
+        error::Error,
+        test::{get_router_with, test_config},
+    };
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode, header::RETRY_AFTER},
+        middleware::from_fn_with_state,
+        routing::get,
+    };
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn rest_routes_are_rate_limited() {
+        let mut config = test_config();
+        config.limits.requests_per_minute = 2;
+        let router = get_router_with(config);
+        let request = || {
+            Request::builder()
+                .uri("/health")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for _ in 0..2 {
+            let response = router.clone().oneshot(request()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = router.oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "30");
+    }
+
+    #[test]
+    fn streams_are_capped_per_client_until_released() {
+        let limiter = Arc::new(Limiter::new(LimitConfig {
+            max_streams_per_client: 1,
+            ..LimitConfig::default()
+        }));
+        let mut first = limiter.acquire_connection("key:a").unwrap();
+        limiter.acquire_stream(&mut first, "key:a").unwrap();
+
+        let mut second = limiter.acquire_connection("key:a").unwrap();
+        let rejected = limiter.acquire_stream(&mut second, "key:a");
+        assert!(matches!(rejected, Err(Error::TooManyRequests { .. })));
+        let mut other = limiter.acquire_connection("key:b").unwrap();
+        assert!(limiter.acquire_stream(&mut other, "key:b").is_ok());
+
+        drop(first);
+        assert!(limiter.acquire_stream(&mut second, "key:a").is_ok());
+        let metrics = limiter.render_metrics();
+        let line = format!(
+            "aggregator_rejections_total{{reason=\"{}\"}} 1",
+            Rejection::Streams.as_str()
+        );
+        assert!(metrics.contains(&line));
+    }
+
+    #[tokio::test]
+    async fn made_up_keys_share_the_callers_limits() {
+        let auth = Auth::default().with(
+            ApiKeys::from_json(r#"[{"name": "ui", "key": "ui-key", "services": ["*"]}]"#).unwrap(),
+        );
+        let limiter = Arc::new(
+            Limiter::new(LimitConfig {
+                max_streams_per_client: 1,
+                requests_per_minute: 2,
+                ..LimitConfig::default()
+            })
+            .with_auth(auth),
+        );
+        let router = Router::new()
+            .route("/health", get(|| async { "OK" }))
+            .route("/sse", get(|| async { "stream" }))
+            .layer(from_fn_with_state(limiter, middleware));
+        let request = |uri: &str, key: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("x-api-key", key)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for key in ["bogus-0", "bogus-1"] {
+            let response = router
+                .clone()
+                .oneshot(request("/health", key))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = router
+            .clone()
+            .oneshot(request("/health", "bogus-2"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let response = router
+            .clone()
+            .oneshot(request("/health", "ui-key"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let open = router
+            .clone()
+            .oneshot(request("/sse", "bogus-3"))
+            .await
+            .unwrap();
+        assert_eq!(open.status(), StatusCode::OK);
+        let response = router.oneshot(request("/sse", "bogus-4")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        drop(open);
+    }
+}
@@ -1,6 +1,9 @@
-use aggregator::{ApiDoc, router};
+use aggregator::{ApiDoc, Config, router};
 use anyhow::Result;
-use std::{env, net::Ipv4Addr};
+use std::{
+    env,
+    net::{Ipv4Addr, SocketAddr},
+};
 use tokio::net::TcpListener;
 use tracing::{debug, info, instrument};
 use tracing_subscriber::{
@@ -25,8 +28,9 @@ async fn main() -> Result<()> {
     debug!("starting service on: {}", port);
     let port = port.parse::<u16>()?;
     let listener = TcpListener::bind((Ipv4Addr::new(0, 0, 0, 0), port)).await?;
+    let config = Config::from_env()?;
     let (router, api) = OpenApiRouter::with_openapi(ApiDoc::openapi())
-        .merge(router())
+        .merge(router(config)?)
         .split_for_parts();
     let app = router.merge(SwaggerUi::new("/swagger").url("/api-docs/openapi.json", api));
     info!("swagger ui hosted on: http://localhost:{}/swagger", port);
@@ -42,6 +46,10 @@ async fn main() -> Result<()> {
         "--------------------🚀🚀🎆{}:{}@{}🎆🚀🚀--------------------\n",
         "social-aggregator", name, version
     );
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }
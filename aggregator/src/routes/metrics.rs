@@ -0,0 +1,19 @@
+use crate::AppState;
+use axum::{extract::State, http::header::CONTENT_TYPE, response::IntoResponse};
+use tracing::instrument;
+
+#[utoipa::path(get,
+               path = "/metrics",
+               tags = ["Internal", "Operations"],
+               operation_id = "metrics",
+               responses(
+                   (status = OK, body = String, description = "Connection usage and rejection counters", content_type = "text/plain")
+               )
+)]
+#[instrument(name = "metrics", target = "api::metrics", skip_all)]
+pub async fn route(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.limiter.render_metrics(),
+    )
+}
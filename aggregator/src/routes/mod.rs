@@ -1,10 +1,18 @@
 use crate::error::Error;
 
 pub mod health;
+pub mod metrics;
 pub mod sse;
+pub mod stats;
+pub mod subscriptions;
+pub mod trends;
 
 pub use health::route as health;
+pub use metrics::route as metrics;
 pub use sse::route as sse;
+pub use stats::route as stats;
+pub use subscriptions::route as subscription_sse;
+pub use trends::route as trends;
 
 pub async fn not_found() -> Error {
     Error::NotFound
@@ -1,69 +1,57 @@
-use crate::AppState;
+use crate::{AppState, auth::Principal, error::Error, feed};
 use axum::{
+    Extension,
     extract::State,
     response::sse::{Event, KeepAlive, Sse},
 };
 use axum_extra::TypedHeader;
-use futures_util::stream::{self, Stream, StreamExt};
+use futures_util::stream::{Stream, StreamExt, select};
 use headers::{Header, HeaderName, HeaderValue};
-use prost::Message;
-use proto_definitions::v1::PostBatch;
-use std::{convert::Infallible, env};
-use tracing::{error, instrument};
+use std::convert::Infallible;
+use tracing::instrument;
 
 #[utoipa::path(get,
                path = "/sse",
                tags = ["External"],
                operation_id = "sse",
+               params(
+                   ("Last-Event-ID" = Option<String>, Header, description = "Resume after this event id when Redis streams are enabled.")
+               ),
                responses(
-                   (status = OK, body = String,  description = "A stream of Server-Sent Events (SSE).", content_type = "text/event-stream")
-               )
+                   (status = OK, body = String,  description = "A stream of Server-Sent Events (SSE): post batches as unnamed events, plus periodic `trends` events.", content_type = "text/event-stream"),
+                   (status = UNAUTHORIZED, description = "Missing or invalid credentials."),
+                   (status = FORBIDDEN, description = "The credentials do not grant access to any service."),
+                   (status = SERVICE_UNAVAILABLE, description = "Redis is unreachable, retry after the `Retry-After` interval.")
+               ),
+               security(("api_key" = []), ("api_key_query" = []), ("bearer" = []))
 )]
-#[instrument(name = "sse", target = "api::sse")]
+#[instrument(name = "sse", target = "api::sse", skip(principal), fields(subject = %principal.subject))]
 pub async fn route(
     State(state): State<AppState>,
-    TypedHeader(last_event_id): TypedHeader<LastEventId>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let redis_channel = env::var("REDIS_CHANNEL").unwrap_or_else(|_| "posts.live".to_string());
-    let mut pubsub = state
-        .redis_client
-        .get_async_pubsub()
-        .await
-        .expect("Failed to get redis pubsub connection");
-    pubsub
-        .subscribe(&redis_channel)
-        .await
-        .expect("Failed to subscribe to channel");
-
-    let stream = stream::unfold(pubsub, |mut pubsub| async {
-        loop {
-            let msg = pubsub.on_message().next().await?;
-            let payload: Vec<u8> = match msg.get_payload() {
-                Ok(payload) => payload,
-                Err(e) => {
-                    error!("Failed to get payload from Redis message: {}", e);
-                    continue;
-                }
-            };
-            let post_batch = match PostBatch::decode(payload.as_slice()) {
-                Ok(batch) => batch,
-                Err(e) => {
-                    error!("Failed to decode Protobuf message: {}", e);
-                    continue;
-                }
-            };
-            let json_payload = match serde_json::to_string(&post_batch) {
-                Ok(json) => json,
-                Err(e) => {
-                    error!("Failed to serialize PostBatch to JSON: {}", e);
-                    continue;
-                }
-            };
-            let event = Event::default().data(json_payload);
-            return Some((Ok(event), pubsub));
-        }
-    });
-    Sse::new(stream).keep_alive(KeepAlive::default())
+    Extension(principal): Extension<Principal>,
+    last_event_id: Option<TypedHeader<LastEventId>>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Error> {
+    if principal.scopes.is_empty() {
+        return Err(Error::Forbidden(format!(
+            "`{}` may not stream any service",
+            principal.subject
+        )));
+    }
+    let last_event_id = last_event_id.map(|TypedHeader(LastEventId(id))| id);
+    let trend_events =
+        (state.trends.interval_secs > 0).then(|| state.trend_events.subscribe(&principal.scopes));
+    let stream = feed::open(
+        state.redis_client,
+        &state.transport,
+        principal.scopes,
+        last_event_id,
+    )
+    .await?;
+    let stream = match trend_events {
+        Some(trend_events) => select(stream, trend_events).boxed(),
+        None => stream,
+    };
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
 }
 
 static LAST_EVENT_ID: HeaderName = HeaderName::from_static("last-event-id");
@@ -96,23 +84,21 @@ impl Header for LastEventId {
 
 #[cfg(test)]
 mod test {
-    // use super::route;
-    // use crate::api::test::get_response_body;
-    // use axum::http::StatusCode;
-    // use axum::response::IntoResponse;
-    // use serde_json::json;
+    use crate::test::{get_response_body, get_router};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header::RETRY_AFTER},
+    };
+    use tower::ServiceExt;
 
-    // #[tokio::test]
-    // async fn health_gives_ok() {
-    //     let response = route().await;
-    //     let response = response.into_response();
-    //     assert_eq!(response.status(), StatusCode::OK);
-    //     let body = get_response_body(response).await;
-    //     assert_eq!(
-    //         body,
-    //         json!({"data": {"message": "ok."}, "status": "success"})
-    //     );
-    // }
-    //
-    //
+    #[tokio::test]
+    async fn sse_without_redis_is_unavailable() {
+        let request = Request::builder().uri("/sse").body(Body::empty()).unwrap();
+
+        let response = get_router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "5");
+        let body = get_response_body(response).await;
+        assert!(body["message"].is_string());
+    }
 }
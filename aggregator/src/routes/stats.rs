@@ -0,0 +1,268 @@
+use crate::{
+    AppState,
+    auth::{Principal, Scopes},
+    error::Error,
+    json::ValidJson,
+};
+use axum::{
+    Extension,
+    extract::{Query, State},
+};
+use proto_definitions::v1::Service;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
+
+/// Bucket granularity, matching the resolutions the social-consumer `stats`
+/// stage writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    #[default]
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    fn as_str(self) -> &'static str {
+        match self {
+            Resolution::Minute => "minute",
+            Resolution::Hour => "hour",
+            Resolution::Day => "day",
+        }
+    }
+
+    fn width_secs(self) -> u64 {
+        match self {
+            Resolution::Minute => 60,
+            Resolution::Hour => 3600,
+            Resolution::Day => 24 * 3600,
+        }
+    }
+
+    /// Longest range served, bounded by how long the consumer keeps buckets.
+    fn max_buckets(self) -> u64 {
+        match self {
+            Resolution::Minute => 24 * 60,
+            Resolution::Hour => 31 * 24,
+            Resolution::Day => 366,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsQuery {
+    /// `minute` (default), `hour` or `day`.
+    resolution: Option<Resolution>,
+    /// How far back to go, e.g. `90m`, `24h` or `7d`; defaults to `1h`.
+    /// Capped at 24h of minutes, 31 days of hours or 366 days.
+    range: Option<String>,
+    /// Restrict counts to one service such as `mastodon`.
+    service: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct Bucket {
+    /// Start of the bucket in seconds since the Unix epoch.
+    pub start: u64,
+    pub total: u64,
+    pub services: BTreeMap<String, u64>,
+    /// Keyed by ISO 639-3 code, `und` when detection was not confident.
+    pub languages: BTreeMap<String, u64>,
+    /// Keyed by the host the post originated from.
+    pub instances: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StatsResponse {
+    pub resolution: Resolution,
+    /// Oldest first, including empty buckets.
+    pub buckets: Vec<Bucket>,
+}
+
+#[utoipa::path(get,
+               path = "/stats",
+               tags = ["External"],
+               operation_id = "stats",
+               params(StatsQuery),
+               responses(
+                   (status = OK, body = StatsResponse, description = "Ingested post counts per time bucket", content_type = "application/json"),
+                   (status = BAD_REQUEST, description = "The range could not be parsed or the service is not recognised."),
+                   (status = UNAUTHORIZED, description = "Missing or invalid credentials."),
+                   (status = FORBIDDEN, description = "The credentials do not grant access to the requested service."),
+                   (status = SERVICE_UNAVAILABLE, description = "Redis is unreachable, retry after the `Retry-After` interval.")
+               ),
+               security(("api_key" = []), ("api_key_query" = []), ("bearer" = []))
+)]
+#[instrument(name = "stats", target = "api::stats", skip(state, principal), fields(subject = %principal.subject))]
+pub async fn route(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<StatsQuery>,
+) -> Result<ValidJson<StatsResponse>, Error> {
+    let resolution = query.resolution.unwrap_or_default();
+    let range = parse_range(query.range.as_deref().unwrap_or("1h"))?;
+    let services = match &query.service {
+        Some(name) => {
+            let service = Service::from_str_name(&name.to_uppercase())
+                .ok_or_else(|| Error::BadRequest(format!("unknown service `{name}`")))?;
+            if !principal.scopes.allows(service as i32) {
+                return Err(Error::Forbidden(format!(
+                    "`{}` may not read `{name}`",
+                    principal.subject
+                )));
+            }
+            Some(HashSet::from([name.to_lowercase()]))
+        }
+        None => match &principal.scopes {
+            Scopes::All => None,
+            Scopes::Services(services) => Some(
+                services
+                    .iter()
+                    .filter_map(|service| Service::try_from(*service).ok())
+                    .map(|service| service.as_str_name().to_lowercase())
+                    .collect(),
+            ),
+        },
+    };
+
+    let width = resolution.width_secs();
+    let count = range.div_ceil(width).clamp(1, resolution.max_buckets());
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let starts: Vec<u64> = (0..count)
+        .rev()
+        .map(|ago| (now / width - ago) * width)
+        .collect();
+
+    let mut pipe = redis::pipe();
+    for start in &starts {
+        pipe.hgetall(format!(
+            "{}:{}:{start}",
+            state.stats.prefix,
+            resolution.as_str()
+        ));
+    }
+    let mut conn = state
+        .redis_client
+        .get_multiplexed_async_connection()
+        .await?;
+    let hashes: Vec<HashMap<String, u64>> = pipe.query_async(&mut conn).await?;
+    let buckets = starts
+        .into_iter()
+        .zip(hashes)
+        .map(|(start, fields)| bucket(start, fields, services.as_ref()))
+        .collect();
+    Ok(ValidJson(StatsResponse {
+        resolution,
+        buckets,
+    }))
+}
+
+/// Parses `<n>m`, `<n>h` or `<n>d` into seconds.
+fn parse_range(range: &str) -> Result<u64, Error> {
+    let invalid = || Error::BadRequest(format!("invalid range `{range}`, expected e.g. `24h`"));
+    let unit = match range.chars().last() {
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 24 * 3600,
+        _ => return Err(invalid()),
+    };
+    let amount: u64 = range[..range.len() - 1].parse().map_err(|_| invalid())?;
+    if amount == 0 {
+        return Err(invalid());
+    }
+    Ok(amount.saturating_mul(unit))
+}
+
+/// Folds `{service}:...` fields into a bucket, skipping services outside
+/// `services` when given.
+fn bucket(start: u64, fields: HashMap<String, u64>, services: Option<&HashSet<String>>) -> Bucket {
+    let mut bucket = Bucket {
+        start,
+        ..Default::default()
+    };
+    for (field, count) in fields {
+        let Some((service, rest)) = field.split_once(':') else {
+            continue;
+        };
+        if services.is_some_and(|services| !services.contains(service)) {
+            continue;
+        }
+        let (target, name) = match rest.split_once(':') {
+            None if rest == "total" => {
+                bucket.total += count;
+                (&mut bucket.services, service)
+            }
+            Some(("language", code)) => (&mut bucket.languages, code),
+            Some(("instance", host)) => (&mut bucket.instances, host),
+            _ => continue,
+        };
+        *target.entry(name.to_string()).or_default() += count;
+    }
+    bucket
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bucket, parse_range};
+    use crate::test::get_router;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use std::collections::{HashMap, HashSet};
+    use tower::ServiceExt;
+
+    #[test]
+    fn buckets_sum_only_visible_services() {
+        let fields = HashMap::from([
+            ("mastodon:total".to_string(), 5),
+            ("mastodon:language:eng".to_string(), 3),
+            ("mastodon:language:deu".to_string(), 2),
+            ("mastodon:instance:fosstodon.org".to_string(), 5),
+            ("x:total".to_string(), 7),
+            ("x:language:eng".to_string(), 7),
+        ]);
+
+        let all = bucket(60, fields.clone(), None);
+        assert_eq!(all.total, 12);
+        assert_eq!(all.services["x"], 7);
+        assert_eq!(all.languages["eng"], 10);
+
+        let mastodon = HashSet::from(["mastodon".to_string()]);
+        let scoped = bucket(60, fields, Some(&mastodon));
+        assert_eq!(scoped.total, 5);
+        assert!(!scoped.services.contains_key("x"));
+        assert_eq!(scoped.languages["eng"], 3);
+        assert_eq!(scoped.instances["fosstodon.org"], 5);
+    }
+
+    #[tokio::test]
+    async fn invalid_range_is_a_bad_request() {
+        assert_eq!(parse_range("7d").unwrap(), 7 * 24 * 3600);
+        assert!(parse_range("0h").is_err());
+
+        let request = Request::builder()
+            .uri("/stats?range=forever")
+            .body(Body::empty())
+            .unwrap();
+        let response = get_router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let request = Request::builder()
+            .uri("/stats?service=myspace")
+            .body(Body::empty())
+            .unwrap();
+        let response = get_router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
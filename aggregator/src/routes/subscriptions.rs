@@ -0,0 +1,76 @@
+use crate::{AppState, auth::Principal, error::Error, feed::pubsub};
+use axum::{
+    Extension,
+    extract::{Path, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::stream::Stream;
+use std::convert::Infallible;
+use tracing::instrument;
+
+#[utoipa::path(get,
+               path = "/subscriptions/{id}/sse",
+               tags = ["External"],
+               operation_id = "subscriptionSse",
+               params(
+                   ("id" = String, Path, description = "Subscription rule id as configured in the social-consumer.")
+               ),
+               responses(
+                   (status = OK, body = String, description = "Posts matching the rule as Server-Sent Events (SSE).", content_type = "text/event-stream"),
+                   (status = BAD_REQUEST, description = "The id is not a valid rule id."),
+                   (status = UNAUTHORIZED, description = "Missing or invalid credentials."),
+                   (status = FORBIDDEN, description = "The credentials do not grant access to any service."),
+                   (status = SERVICE_UNAVAILABLE, description = "Redis is unreachable, retry after the `Retry-After` interval.")
+               ),
+               security(("api_key" = []), ("api_key_query" = []), ("bearer" = []))
+)]
+#[instrument(name = "subscription_sse", target = "api::subscriptions", skip(state, principal), fields(subject = %principal.subject))]
+pub async fn route(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Error> {
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error::BadRequest(format!("invalid subscription id `{id}`")));
+    }
+    if principal.scopes.is_empty() {
+        return Err(Error::Forbidden(format!(
+            "`{}` may not stream any service",
+            principal.subject
+        )));
+    }
+    let channel = format!("{}.{id}", state.subscriptions.channel_prefix);
+    let stream = pubsub::open(state.redis_client, channel, principal.scopes).await?;
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::get_router;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn subscription_ids_are_validated() {
+        let request = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = get_router()
+            .oneshot(request("/subscriptions/a*b/sse"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = get_router()
+            .oneshot(request("/subscriptions/rust-news/sse"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
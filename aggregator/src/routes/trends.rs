@@ -0,0 +1,116 @@
+use crate::{
+    AppState,
+    auth::Principal,
+    error::Error,
+    json::ValidJson,
+    trends::{self, Sort, Trends, Window},
+};
+use axum::{
+    Extension,
+    extract::{Query, State},
+};
+use proto_definitions::v1::Service;
+use serde::Deserialize;
+use tracing::instrument;
+use utoipa::IntoParams;
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TrendsQuery {
+    /// `5m`, `1h` or `24h`, defaults to `1h`.
+    window: Option<Window>,
+    /// Restrict counts to one service such as `mastodon`.
+    service: Option<String>,
+    /// Entries per kind, at most 100.
+    limit: Option<usize>,
+    /// `count` (default) or `velocity`.
+    sort: Option<Sort>,
+}
+
+#[utoipa::path(get,
+               path = "/trends",
+               tags = ["External"],
+               operation_id = "trends",
+               params(TrendsQuery),
+               responses(
+                   (status = OK, body = Trends, description = "Top hashtags, links and terms in the window", content_type = "application/json"),
+                   (status = BAD_REQUEST, description = "The window, sort or service is not recognised."),
+                   (status = UNAUTHORIZED, description = "Missing or invalid credentials."),
+                   (status = FORBIDDEN, description = "The credentials do not grant access to the requested service."),
+                   (status = SERVICE_UNAVAILABLE, description = "Redis is unreachable, retry after the `Retry-After` interval.")
+               ),
+               security(("api_key" = []), ("api_key_query" = []), ("bearer" = []))
+)]
+#[instrument(name = "trends", target = "api::trends", skip(state, principal), fields(subject = %principal.subject))]
+pub async fn route(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<TrendsQuery>,
+) -> Result<ValidJson<Trends>, Error> {
+    let scopes = match &query.service {
+        Some(name) => {
+            let service = Service::from_str_name(&name.to_uppercase())
+                .ok_or_else(|| Error::BadRequest(format!("unknown service `{name}`")))?;
+            if !principal.scopes.allows(service as i32) {
+                return Err(Error::Forbidden(format!(
+                    "`{}` may not read `{name}`",
+                    principal.subject
+                )));
+            }
+            vec![name.to_lowercase()]
+        }
+        None => trends::key_scopes(&principal.scopes),
+    };
+    let mut conn = state
+        .redis_client
+        .get_multiplexed_async_connection()
+        .await?;
+    let trends = trends::snapshot(
+        &mut conn,
+        &state.trends.prefix,
+        query.window.unwrap_or(Window::Hour),
+        &scopes,
+        query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT),
+        query.sort.unwrap_or_default(),
+    )
+    .await?;
+    Ok(ValidJson(trends))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::{get_response_body, get_router};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn trends_validate_query_before_redis() {
+        let request = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = get_router()
+            .oneshot(request("/trends?window=2d"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = get_router()
+            .oneshot(request("/trends?service=myspace"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = get_response_body(response).await;
+        assert!(body.to_string().contains("unknown service `myspace`"));
+
+        let response = get_router()
+            .oneshot(request("/trends?window=5m&sort=velocity"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,430 @@
+use crate::{auth::Scopes, config::TrendsConfig, feed::EventStream};
+use axum::response::sse::Event;
+use futures_util::stream::{self, StreamExt};
+use proto_definitions::v1::Service;
+use redis::{RedisResult, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::watch,
+    time::{MissedTickBehavior, interval},
+};
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+/// Window and size of the snapshot pushed to SSE clients.
+const SSE_WINDOW: Window = Window::FiveMinutes;
+const SSE_LIMIT: usize = 10;
+/// How long the summed windows outlive the request that stored them.
+const UNION_TTL_SECS: i64 = 10;
+/// Velocity is ranked among this many times `limit` of the values that gained
+/// the most mentions, rather than over the whole vocabulary.
+const VELOCITY_CANDIDATES: usize = 10;
+/// A term must at least double and be seen this often to count as rising.
+const RISING_MIN_COUNT: u64 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub enum Window {
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "24h")]
+    Day,
+}
+
+impl Window {
+    /// Bucket granularity as written by the social-consumer `trends` stage,
+    /// seconds per bucket and buckets per window.
+    fn buckets(self) -> (&'static str, u64, u64) {
+        match self {
+            Window::FiveMinutes => ("m", 60, 5),
+            Window::Hour => ("m", 60, 60),
+            Window::Day => ("h", 3600, 24),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Hashtag,
+    Link,
+    Term,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Hashtag => "hashtag",
+            Kind::Link => "link",
+            Kind::Term => "term",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    /// Most mentioned in the window first.
+    #[default]
+    Count,
+    /// Fastest growing compared with the previous window first, among the
+    /// values gaining the most mentions.
+    Velocity,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Rising,
+    Steady,
+    Falling,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct Trend {
+    pub value: String,
+    /// Mentions in the requested window.
+    pub count: u64,
+    /// Mentions in the window of the same length just before it.
+    pub previous: u64,
+    /// Relative change from `previous` to `count`; 1.0 means it doubled.
+    pub velocity: f64,
+    pub direction: Direction,
+}
+
+impl Trend {
+    fn new(value: String, count: u64, previous: u64) -> Self {
+        let velocity = (count as f64 - previous as f64) / previous.max(1) as f64;
+        let direction = if velocity >= 1.0 && count >= RISING_MIN_COUNT {
+            Direction::Rising
+        } else if velocity <= -0.5 {
+            Direction::Falling
+        } else {
+            Direction::Steady
+        };
+        Trend {
+            value,
+            count,
+            previous,
+            velocity,
+            direction,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Trends {
+    pub window: Window,
+    pub hashtags: Vec<Trend>,
+    pub links: Vec<Trend>,
+    pub terms: Vec<Trend>,
+}
+
+/// The key scopes covering `scopes`: the overall counts, or the per-service
+/// counts that Redis sums for us, sorted so equal scopes share summed keys.
+pub fn key_scopes(scopes: &Scopes) -> Vec<String> {
+    match scopes {
+        Scopes::All => vec!["all".to_string()],
+        Scopes::Services(services) => {
+            let mut names: Vec<String> = services
+                .iter()
+                .filter_map(|service| Service::try_from(*service).ok())
+                .map(|service| service.as_str_name().to_lowercase())
+                .collect();
+            names.sort();
+            names
+        }
+    }
+}
+
+pub async fn snapshot(
+    conn: &mut MultiplexedConnection,
+    prefix: &str,
+    window: Window,
+    scopes: &[String],
+    limit: usize,
+    sort: Sort,
+) -> RedisResult<Trends> {
+    Ok(Trends {
+        window,
+        hashtags: top(conn, prefix, Kind::Hashtag, window, scopes, limit, sort).await?,
+        links: top(conn, prefix, Kind::Link, window, scopes, limit, sort).await?,
+        terms: top(conn, prefix, Kind::Term, window, scopes, limit, sort).await?,
+    })
+}
+
+/// Sums the window's buckets into short-lived sorted sets in Redis and reads
+/// back only the candidates for the top `limit`, so the vocabulary never
+/// leaves Redis.
+async fn top(
+    conn: &mut MultiplexedConnection,
+    prefix: &str,
+    kind: Kind,
+    window: Window,
+    scopes: &[String],
+    limit: usize,
+    sort: Sort,
+) -> RedisResult<Vec<Trend>> {
+    if scopes.is_empty() || limit == 0 {
+        return Ok(Vec::new());
+    }
+    let (granularity, bucket_secs, buckets) = window.buckets();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / bucket_secs;
+    let keys = |from: u64, to: u64| -> Vec<String> {
+        scopes
+            .iter()
+            .flat_map(|scope| {
+                (from..to).map(move |offset| {
+                    format!(
+                        "{prefix}:{}:{scope}:{granularity}:{}",
+                        kind.as_str(),
+                        now.saturating_sub(offset)
+                    )
+                })
+            })
+            .collect()
+    };
+    let union_key = |part: &str| {
+        format!(
+            "{prefix}:union:{}:{}:{granularity}:{now}:{buckets}:{part}",
+            kind.as_str(),
+            scopes.join(",")
+        )
+    };
+    let (current, previous) = (union_key("current"), union_key("previous"));
+
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .zunionstore(&current, keys(0, buckets))
+        .ignore()
+        .expire(&current, UNION_TTL_SECS)
+        .ignore()
+        .zunionstore(&previous, keys(buckets, 2 * buckets))
+        .ignore()
+        .expire(&previous, UNION_TTL_SECS)
+        .ignore();
+    let (ranked, candidates) = match sort {
+        Sort::Count => (current.clone(), limit),
+        Sort::Velocity => {
+            let growth = union_key("growth");
+            pipe.zunionstore_weights(&growth, &[(&current, 1), (&previous, -1)])
+                .ignore()
+                .expire(&growth, UNION_TTL_SECS)
+                .ignore();
+            (growth, limit * VELOCITY_CANDIDATES)
+        }
+    };
+    pipe.zrevrange(&ranked, 0, candidates as isize - 1);
+    let (values,): (Vec<String>,) = pipe.query_async(conn).await?;
+    if values.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let (counts, previous_counts): (Vec<Option<f64>>, Vec<Option<f64>>) = redis::pipe()
+        .zscore_multiple(&current, &values)
+        .zscore_multiple(&previous, &values)
+        .query_async(conn)
+        .await?;
+    let previous = values
+        .iter()
+        .cloned()
+        .zip(previous_counts)
+        .filter_map(|(value, count)| Some((value, count?)))
+        .collect();
+    let current = values
+        .into_iter()
+        .zip(counts)
+        .filter_map(|(value, count)| Some((value, count?)))
+        .collect();
+    Ok(rank(current, previous, limit, sort))
+}
+
+fn rank(
+    current: Vec<(String, f64)>,
+    previous: HashMap<String, f64>,
+    limit: usize,
+    sort: Sort,
+) -> Vec<Trend> {
+    let mut trends: Vec<Trend> = current
+        .into_iter()
+        .map(|(value, count)| {
+            let previous = previous.get(&value).copied().unwrap_or_default();
+            Trend::new(value, count as u64, previous as u64)
+        })
+        .collect();
+    trends.sort_by(|a, b| {
+        let by_count = b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value));
+        match sort {
+            Sort::Count => by_count,
+            Sort::Velocity => b
+                .velocity
+                .partial_cmp(&a.velocity)
+                .unwrap_or(Ordering::Equal)
+                .then(by_count),
+        }
+    });
+    trends.truncate(limit);
+    trends
+}
+
+/// The latest `trends` snapshot for one set of scopes, as JSON.
+type Snapshots = Arc<watch::Sender<Option<Arc<str>>>>;
+
+/// Shares `trends` snapshots between SSE clients: one task per set of scopes
+/// reads them every interval, and stops once its last client is gone.
+#[derive(Clone, Debug)]
+pub struct TrendEvents {
+    client: redis::Client,
+    config: TrendsConfig,
+    feeds: Arc<Mutex<HashMap<Vec<String>, Snapshots>>>,
+}
+
+impl TrendEvents {
+    pub fn new(client: redis::Client, config: TrendsConfig) -> Self {
+        TrendEvents {
+            client,
+            config,
+            feeds: Arc::default(),
+        }
+    }
+
+    /// Pushes a `trends` event with the latest snapshot for `scopes`, starting
+    /// with the one already read if there is one.
+    pub fn subscribe(&self, scopes: &Scopes) -> EventStream {
+        let scopes = key_scopes(scopes);
+        let mut feeds = self.feeds.lock().expect("trend feeds lock");
+        let mut receiver = match feeds.get(&scopes) {
+            Some(snapshots) => snapshots.subscribe(),
+            None => {
+                let (snapshots, receiver) = watch::channel(None);
+                let snapshots = Arc::new(snapshots);
+                feeds.insert(scopes.clone(), snapshots.clone());
+                tokio::spawn(self.clone().refresh(scopes, snapshots));
+                receiver
+            }
+        };
+        receiver.mark_changed();
+        stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                receiver.changed().await.ok()?;
+                let json = receiver.borrow_and_update().clone();
+                if let Some(json) = json {
+                    let event = Event::default().event("trends").data(&*json);
+                    return Some((Ok(event), receiver));
+                }
+            }
+        })
+        .boxed()
+    }
+
+    /// Reads a snapshot for `scopes` every interval. Redis errors skip a tick
+    /// rather than ending the feed.
+    async fn refresh(self, scopes: Vec<String>, snapshots: Snapshots) {
+        let mut ticker = interval(Duration::from_secs(self.config.interval_secs));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut conn = None;
+        loop {
+            ticker.tick().await;
+            {
+                let mut feeds = self.feeds.lock().expect("trend feeds lock");
+                if snapshots.receiver_count() == 0 {
+                    feeds.remove(&scopes);
+                    return;
+                }
+            }
+            let connection = match conn.as_mut() {
+                Some(connection) => connection,
+                None => match self.client.get_multiplexed_async_connection().await {
+                    Ok(connection) => conn.insert(connection),
+                    Err(e) => {
+                        warn!("Failed to connect to Redis for trends: {}", e);
+                        continue;
+                    }
+                },
+            };
+            let trends = match snapshot(
+                connection,
+                &self.config.prefix,
+                SSE_WINDOW,
+                &scopes,
+                SSE_LIMIT,
+                Sort::Count,
+            )
+            .await
+            {
+                Ok(trends) => trends,
+                Err(e) => {
+                    warn!("Failed to read trends: {}", e);
+                    conn = None;
+                    continue;
+                }
+            };
+            match serde_json::to_string(&trends) {
+                Ok(json) => {
+                    snapshots.send_replace(Some(json.into()));
+                }
+                Err(e) => error!("Failed to serialize trends: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Direction, Sort, TrendEvents, rank};
+    use crate::{auth::Scopes, config::TrendsConfig};
+    use proto_definitions::v1::Service;
+    use std::{
+        collections::{HashMap, HashSet},
+        time::Duration,
+    };
+
+    #[test]
+    fn trends_rank_by_count_or_velocity() {
+        let current = vec![
+            ("rust".to_string(), 40.0),
+            ("eclipse".to_string(), 12.0),
+            ("coffee".to_string(), 2.0),
+        ];
+        let previous = HashMap::from([("rust".to_string(), 38.0), ("coffee".to_string(), 9.0)]);
+
+        let by_count = rank(current.clone(), previous.clone(), 10, Sort::Count);
+        let values: Vec<_> = by_count.iter().map(|t| t.value.as_str()).collect();
+        assert_eq!(values, ["rust", "eclipse", "coffee"]);
+        let directions: Vec<_> = by_count.iter().map(|t| t.direction).collect();
+        assert_eq!(
+            directions,
+            [Direction::Steady, Direction::Rising, Direction::Falling]
+        );
+
+        let by_velocity = rank(current, previous, 1, Sort::Velocity);
+        assert_eq!(by_velocity[0].value, "eclipse");
+        assert_eq!(by_velocity[0].velocity, 12.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clients_with_the_same_scopes_share_a_feed() {
+        let client = redis::Client::open("redis://127.0.0.1:1").unwrap();
+        let events = TrendEvents::new(client, TrendsConfig::default());
+        let first = events.subscribe(&Scopes::All);
+        let second = events.subscribe(&Scopes::All);
+        let other = events.subscribe(&Scopes::Services(HashSet::from([Service::X as i32])));
+        assert_eq!(events.feeds.lock().unwrap().len(), 2);
+
+        drop((first, second));
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        let feeds = events.feeds.lock().unwrap();
+        assert_eq!(feeds.keys().collect::<Vec<_>>(), [&["x".to_string()]]);
+        drop(other);
+    }
+}
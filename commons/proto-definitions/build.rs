@@ -3,12 +3,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("cargo:rerun-if-changed={}", proto_file);
     prost_build::Config::new()
-        .type_attribute("social.v1.Post", "#[derive(serde::Serialize)]")
+        .type_attribute(
+            "social.v1.Post",
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+        )
         .field_attribute(
             "social.v1.Post.timestamp",
             "#[serde(with = \"crate::prost_timestamp_serde\")]",
         )
-        .type_attribute("social.v1.PostBatch", "#[derive(serde::Serialize)]")
+        .type_attribute(
+            "social.v1.PostBatch",
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+        )
+        .type_attribute(
+            "social.v1.CustomEmoji",
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+        )
+        .type_attribute(
+            "social.v1.Source",
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+        )
         .compile_protos(&[proto_file], &["src/"])?;
     Ok(())
 }
@@ -1,5 +1,6 @@
 use prost::Message;
 use social::v1::Service;
+use std::str::FromStr;
 
 pub mod social {
     pub mod v1 {
@@ -13,16 +14,164 @@ pub use social::v1;
 
 impl PostId for social::v1::Post {
     fn id(&self) -> String {
-        let service_enum = Service::try_from(self.service);
-        let service = match service_enum {
+        format!("{}:{}", self.service_name(), self.id)
+    }
+
+    fn service_name(&self) -> &'static str {
+        match Service::try_from(self.service) {
             Ok(Service::Mastodon) => "mastodon",
             Ok(Service::X) => "x",
             _ => "unknown",
+        }
+    }
+
+    fn author(&self) -> Option<&str> {
+        (!self.author.is_empty()).then_some(self.author.as_str())
+    }
+
+    fn instance(&self) -> Option<&str> {
+        let from_author = self
+            .author
+            .split_once('@')
+            .map(|(_, domain)| domain)
+            .filter(|domain| !domain.is_empty());
+        let from_uri = || {
+            let (_, rest) = self.uri.split_once("://")?;
+            let host = rest.split(['/', '?', '#']).next()?;
+            (!host.is_empty()).then_some(host)
         };
-        format!("{service}:{}", self.id)
+        from_author.or_else(from_uri)
+    }
+
+    fn timestamp_millis(&self) -> Option<i64> {
+        let timestamp = self.timestamp.as_ref()?;
+        Some(timestamp.seconds * 1000 + i64::from(timestamp.nanos) / 1_000_000)
     }
 }
 
+/// Identity and routing metadata of a message produced to Kafka.
+///
+/// Records are keyed according to a [`PartitionKey`], and Kafka sends every
+/// record with the same key to the same partition, in order:
+///
+/// - [`PartitionKey::Id`] (the default) keys by `id()`, `service:id`.
+/// - [`PartitionKey::Service`] keys by `service_name()`, keeping each
+///   service's posts in order at the cost of spreading over few partitions.
+/// - [`PartitionKey::Author`] keys by `author()`, for per-author ordering.
+/// - [`PartitionKey::Instance`] keys by `instance()`, the author's home
+///   server.
+/// - [`PartitionKey::HashedId`] keys by a 64-bit FNV-1a hash of `id()` in
+///   hex, for keys of uniform size.
+///
+/// When a post has no author or instance, `id()` is used instead. The
+/// record timestamp is `timestamp_millis()` when present, so consumers can
+/// seek by the time posts were written rather than produced.
 pub trait PostId: Message {
     fn id(&self) -> String;
+
+    /// Short lowercase name of the post's service.
+    fn service_name(&self) -> &'static str {
+        "unknown"
+    }
+
+    fn author(&self) -> Option<&str> {
+        None
+    }
+
+    /// Domain of the server the post was written on.
+    fn instance(&self) -> Option<&str> {
+        None
+    }
+
+    /// When the post was written, in milliseconds since the Unix epoch.
+    fn timestamp_millis(&self) -> Option<i64> {
+        None
+    }
+}
+
+/// How records are keyed, and with that partitioned; see [`PostId`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PartitionKey {
+    #[default]
+    Id,
+    Service,
+    Author,
+    Instance,
+    HashedId,
+}
+
+impl PartitionKey {
+    pub fn key_for<T: PostId>(self, post: &T) -> String {
+        match self {
+            PartitionKey::Id => post.id(),
+            PartitionKey::Service => post.service_name().to_string(),
+            PartitionKey::Author => post.author().map_or_else(|| post.id(), str::to_string),
+            PartitionKey::Instance => post.instance().map_or_else(|| post.id(), str::to_string),
+            PartitionKey::HashedId => format!("{:016x}", fnv1a(post.id().as_bytes())),
+        }
+    }
+}
+
+impl FromStr for PartitionKey {
+    type Err = String;
+
+    /// `id`, `service`, `author`, `instance` or `hashed-id`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "id" => Ok(PartitionKey::Id),
+            "service" => Ok(PartitionKey::Service),
+            "author" => Ok(PartitionKey::Author),
+            "instance" => Ok(PartitionKey::Instance),
+            "hashed-id" => Ok(PartitionKey::HashedId),
+            _ => Err(format!(
+                "unknown partition key `{value}`, expected `id`, `service`, `author`, \
+                 `instance` or `hashed-id`"
+            )),
+        }
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds unlike `std`'s hashers.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PartitionKey, PostId, social::v1::Post};
+
+    #[test]
+    fn partition_keys_fall_back_to_the_id() {
+        let post = Post {
+            id: "1".to_string(),
+            author: "alice@example.social".to_string(),
+            timestamp: Some(prost_types::Timestamp {
+                seconds: 1_700_000_000,
+                nanos: 250_000_000,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(PartitionKey::Author.key_for(&post), "alice@example.social");
+        assert_eq!(PartitionKey::Instance.key_for(&post), "example.social");
+        assert_eq!(PartitionKey::Service.key_for(&post), "mastodon");
+        assert_eq!(PartitionKey::HashedId.key_for(&post).len(), 16);
+        assert_eq!(post.timestamp_millis(), Some(1_700_000_000_250));
+
+        let local = Post {
+            id: "2".to_string(),
+            author: "bob".to_string(),
+            uri: "https://other.example/@bob/2".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(PartitionKey::Instance.key_for(&local), "other.example");
+        let anonymous = Post {
+            id: "3".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(PartitionKey::Author.key_for(&anonymous), "mastodon:3");
+        assert_eq!(PartitionKey::Instance.key_for(&anonymous), "mastodon:3");
+        assert_eq!("hashed-id".parse(), Ok(PartitionKey::HashedId));
+    }
 }
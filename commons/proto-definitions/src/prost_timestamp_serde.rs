@@ -1,5 +1,5 @@
 use prost_types::Timestamp;
-use serde::{self, Serializer};
+use serde::{self, Deserialize, Deserializer, Serializer, de::Error};
 
 pub fn serialize<S>(timestamp: &Option<Timestamp>, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -10,3 +10,13 @@ where
         None => serializer.serialize_none(),
     }
 }
+
+/// Accepts the RFC 3339 strings written by `serialize`.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Timestamp>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|ts| ts.parse().map_err(D::Error::custom))
+        .transpose()
+}
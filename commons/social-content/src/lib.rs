@@ -0,0 +1,199 @@
+use ammonia::Builder;
+use html2text::render::TrivialDecorator;
+use proto_definitions::social::v1::{CustomEmoji, Post};
+use std::{collections::HashMap, sync::LazyLock};
+use tracing::warn;
+
+/// Wide enough that html2text never wraps a post.
+const RENDER_WIDTH: usize = 10_000;
+
+/// Tags Mastodon emits for status content. Images are never let through;
+/// resolved emoji are added after sanitizing.
+const ALLOWED_TAGS: &[&str] = &[
+    "a",
+    "b",
+    "blockquote",
+    "br",
+    "code",
+    "del",
+    "em",
+    "i",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "s",
+    "span",
+    "strong",
+    "u",
+    "ul",
+];
+
+static SANITIZER: LazyLock<Builder<'static>> = LazyLock::new(|| {
+    let mut builder = Builder::empty();
+    builder
+        .add_tags(ALLOWED_TAGS)
+        .add_tag_attributes("a", ["href"])
+        .add_allowed_classes("a", ["mention", "hashtag", "u-url"])
+        .add_allowed_classes("span", ["invisible", "ellipsis", "h-card"])
+        .add_url_schemes(["http", "https"])
+        .link_rel(Some("nofollow noopener noreferrer"))
+        .strip_comments(true);
+    builder
+});
+
+/// Renders HTML as plain text. Custom emoji stay as `:shortcode:`.
+pub fn to_text(html: &str) -> String {
+    match html2text::config::with_decorator(TrivialDecorator::new())
+        .no_link_wrapping()
+        .string_from_read(html.as_bytes(), RENDER_WIDTH)
+    {
+        Ok(text) => text.trim().to_string(),
+        Err(e) => {
+            warn!("Failed to render HTML as text: {}", e);
+            String::new()
+        }
+    }
+}
+
+/// Cleans HTML against an allow-list, then replaces known `:shortcode:` emoji
+/// with `<img class="emoji">` references.
+pub fn sanitize_html(html: &str, emojis: &[CustomEmoji]) -> String {
+    let clean = SANITIZER.clean(html).to_string();
+    replace_emoji(&clean, emojis)
+}
+
+/// Fills `content_text` and `content_html` from the raw `content` and `emojis`.
+pub fn render(post: &mut Post) {
+    post.content_text = to_text(&post.content);
+    post.content_html = sanitize_html(&post.content, &post.emojis);
+}
+
+fn replace_emoji(html: &str, emojis: &[CustomEmoji]) -> String {
+    if emojis.is_empty() {
+        return html.to_string();
+    }
+    let by_shortcode: HashMap<&str, &CustomEmoji> = emojis
+        .iter()
+        .filter(|emoji| is_web_url(emoji_src(emoji)))
+        .map(|emoji| (emoji.shortcode.as_str(), emoji))
+        .collect();
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    let mut in_tag = false;
+    while let Some(index) = rest.find([':', '<', '>']) {
+        let (before, from) = rest.split_at(index);
+        out.push_str(before);
+        match from.as_bytes()[0] {
+            b'<' => in_tag = true,
+            b'>' => in_tag = false,
+            _ if !in_tag => {
+                if let Some((shortcode, after)) = from[1..].split_once(':')
+                    && let Some(emoji) = by_shortcode.get(shortcode)
+                {
+                    out.push_str(&emoji_img(emoji));
+                    rest = after;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        out.push_str(&from[..1]);
+        rest = &from[1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn emoji_src(emoji: &CustomEmoji) -> &str {
+    if emoji.static_url.is_empty() {
+        &emoji.url
+    } else {
+        &emoji.static_url
+    }
+}
+
+fn is_web_url(url: &str) -> bool {
+    url.split_once("://").is_some_and(|(scheme, _)| {
+        scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+    })
+}
+
+fn emoji_img(emoji: &CustomEmoji) -> String {
+    let src = emoji_src(emoji);
+    let alt = escape_attribute(&format!(":{}:", emoji.shortcode));
+    format!(
+        r#"<img class="emoji" src="{}" alt="{alt}" title="{alt}">"#,
+        escape_attribute(src)
+    )
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{render, sanitize_html};
+    use proto_definitions::social::v1::{CustomEmoji, Post};
+
+    fn blobcat() -> CustomEmoji {
+        CustomEmoji {
+            shortcode: "blobcat".to_string(),
+            url: "https://files.example/blobcat.gif".to_string(),
+            static_url: "https://files.example/blobcat.png".to_string(),
+        }
+    }
+
+    #[test]
+    fn sanitizer_strips_unsafe_markup() {
+        let html = r#"<p onclick="x()">hi <script>alert(1)</script><a href="javascript:alert(1)">a</a> <a href="https://example.com" class="u-url evil">b</a><img src="https://t.example/p.gif"></p>"#;
+        let clean = sanitize_html(html, &[]);
+        assert!(!clean.contains("onclick"));
+        assert!(!clean.contains("script"));
+        assert!(!clean.contains("javascript"));
+        assert!(!clean.contains("evil"));
+        assert!(clean.contains(r#"<a href="https://example.com" class="u-url" rel="nofollow noopener noreferrer">b</a>"#));
+    }
+
+    #[test]
+    fn images_in_content_are_stripped() {
+        let html = r#"<p>a<img src="https://tracker.example/p.gif"> b<img class="emoji" src="http://tracker.example/p.gif" alt=":blobcat:"></p>"#;
+        let clean = sanitize_html(html, &[blobcat()]);
+        assert!(!clean.contains("<img"));
+        assert!(!clean.contains("tracker.example"));
+
+        let data = CustomEmoji {
+            static_url: "data:image/png;base64,AAAA".to_string(),
+            ..blobcat()
+        };
+        assert_eq!(
+            sanitize_html("<p>:blobcat:</p>", &[data]),
+            "<p>:blobcat:</p>"
+        );
+    }
+
+    #[test]
+    fn emoji_shortcodes_become_images_outside_tags() {
+        let mut post = Post {
+            content: r#"<p>hello :blobcat: <a href="https://x.example/:blobcat:">:nope:</a></p>"#
+                .to_string(),
+            emojis: vec![blobcat()],
+            ..Default::default()
+        };
+        render(&mut post);
+        assert_eq!(post.content_text, "hello :blobcat: :nope:");
+        assert!(post.content_html.contains(
+            r#"<img class="emoji" src="https://files.example/blobcat.png" alt=":blobcat:" title=":blobcat:">"#
+        ));
+        assert!(
+            post.content_html
+                .contains(r#"href="https://x.example/:blobcat:""#)
+        );
+        assert!(post.content_html.contains(":nope:"));
+    }
+}
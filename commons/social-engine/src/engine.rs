@@ -1,12 +1,23 @@
-use super::queue::FeederQueue;
+use super::envelope::{DEAD_LETTER_REASON, Envelope, POST_CONTENT_TYPE, PostHeaders};
+use super::lag::{LagMonitor, STATISTICS_INTERVAL_MS};
+use super::queue::{FeederQueue, FeederReceiver, Overflow};
+use super::seek::{SeekContext, StartFrom};
+use super::spool::{Spool, SpoolMonitor, SpooledRecord};
 use crate::error::Error;
+use futures_util::{
+    StreamExt,
+    future::{self, BoxFuture},
+    stream::FuturesOrdered,
+};
 use prost::Message as ProstMessage;
-use proto_definitions::PostId;
+use proto_definitions::{PartitionKey, PostId};
 use rdkafka::{
-    Message,
+    Message, Offset, TopicPartitionList,
     config::ClientConfig,
     consumer::{CommitMode, Consumer, StreamConsumer},
-    producer::{FutureProducer, FutureRecord},
+    error::KafkaError,
+    message::Header,
+    producer::{FutureProducer, FutureRecord, Producer},
 };
 
 use schema_registry_converter::async_impl::proto_raw::{ProtoRawDecoder, ProtoRawEncoder};
@@ -16,7 +27,7 @@ use std::{
     fmt::{self, Debug},
     time::Duration,
 };
-use tokio::sync::mpsc::{Receiver, channel};
+use tokio::time::Instant;
 use tracing::{debug, info, instrument, warn};
 use url::Url;
 
@@ -39,6 +50,7 @@ impl<'a> SocialEngine for SocialDecoder<'a> {}
 pub struct SocialProducer<'a> {
     producer: FutureProducer,
     encoder: ProtoRawEncoder<'a>,
+    max_in_flight: usize,
 }
 
 impl<'a> Debug for SocialProducer<'a> {
@@ -46,19 +58,45 @@ impl<'a> Debug for SocialProducer<'a> {
         f.debug_struct("SocialProducer")
             .field("producer", &"<FutureProducer>")
             .field("encoder", &self.encoder)
+            .field("max_in_flight", &self.max_in_flight)
             .finish()
     }
 }
 
 impl<'a> SocialEngine for SocialProducer<'a> {}
 
+/// Decides from a record's headers alone whether a consumer wants it.
+pub type HeaderFilter = Box<dyn Fn(&PostHeaders) -> bool + Send + Sync>;
+
 pub struct SocialConsumer<'a> {
     decoder: ProtoRawDecoder<'a>,
-    consumer: StreamConsumer,
+    consumer: StreamConsumer<SeekContext>,
+    filter: Option<HeaderFilter>,
 }
 
 impl<'a> SocialEngine for SocialConsumer<'a> {}
 
+/// Schema registry access in both directions, for engines that read posts
+/// and write new ones.
+#[derive(Debug)]
+pub struct SocialCodec<'a> {
+    decoder: ProtoRawDecoder<'a>,
+    encoder: ProtoRawEncoder<'a>,
+}
+
+impl<'a> SocialEngine for SocialCodec<'a> {}
+
+/// A consumer and a transactional producer that commit together.
+pub struct SocialTransformer<'a> {
+    decoder: ProtoRawDecoder<'a>,
+    encoder: ProtoRawEncoder<'a>,
+    consumer: StreamConsumer,
+    producer: FutureProducer,
+    dead_letter: Option<String>,
+}
+
+impl<'a> SocialEngine for SocialTransformer<'a> {}
+
 #[derive(Debug)]
 pub struct Start;
 
@@ -92,42 +130,129 @@ impl SocialEngineBuilder<Start> {
             inner: SocialDecoder { decoder },
         }
     }
+
+    #[instrument(level = "debug")]
+    pub fn codec<'a>(url: Url) -> SocialEngineBuilder<SocialCodec<'a>> {
+        debug!("setting schema registry at: {}", url);
+        let sr_settings = SrSettings::new(url.to_string());
+        SocialEngineBuilder {
+            inner: SocialCodec {
+                decoder: ProtoRawDecoder::new(sr_settings.clone()),
+                encoder: ProtoRawEncoder::new(sr_settings),
+            },
+        }
+    }
+}
+
+/// Throughput settings for `with_producer_tuned`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProducerTuning {
+    /// Records a `MultiSocialProducer` keeps awaiting delivery at once.
+    pub max_in_flight: usize,
+    /// How long librdkafka waits to fill a batch (`linger.ms`).
+    pub linger: Duration,
+    /// Upper bound on a batch in bytes (`batch.size`).
+    pub batch_bytes: u32,
+    /// `none`, `gzip`, `snappy`, `lz4` or `zstd` (`compression.type`).
+    pub compression: String,
+    /// Have the brokers discard duplicates caused by producer retries
+    /// (`enable.idempotence`).
+    pub idempotent: bool,
+}
+
+impl Default for ProducerTuning {
+    fn default() -> Self {
+        ProducerTuning {
+            max_in_flight: 256,
+            linger: Duration::from_millis(5),
+            batch_bytes: 1_000_000,
+            compression: "lz4".to_string(),
+            idempotent: false,
+        }
+    }
 }
 
 impl<'a> SocialEngineBuilder<SocialEncoder<'a>> {
     // TODO: shold take username and password?
-    #[instrument(level = "debug", skip(brokers, self, username, password) err)]
     pub fn with_producer<S: AsRef<str>>(
         self,
         brokers: S,
         username: S,
         password: S,
+    ) -> Result<SocialEngineBuilder<SocialProducer<'a>>, Error> {
+        self.with_producer_tuned(brokers, username, password, &ProducerTuning::default())
+    }
+
+    #[instrument(level = "debug", skip(brokers, self, username, password) err)]
+    pub fn with_producer_tuned<S: AsRef<str>>(
+        self,
+        brokers: S,
+        username: S,
+        password: S,
+        tuning: &ProducerTuning,
     ) -> Result<SocialEngineBuilder<SocialProducer<'a>>, Error> {
         debug!("creating a producer targeted at: {}", brokers.as_ref());
-        let producer: FutureProducer = ClientConfig::new()
-            .set("bootstrap.servers", brokers.as_ref())
-            .set("message.timeout.ms", "5000")
-            .set("sasl.mechanism", "PLAIN")
-            .set("security.protocol", "SASL_PLAINTEXT")
-            .set("sasl.username", username.as_ref())
-            .set("sasl.password", password.as_ref())
-            .create()?;
+        let producer: FutureProducer = producer_config(
+            brokers.as_ref(),
+            username.as_ref(),
+            password.as_ref(),
+            tuning,
+        )
+        .create()?;
 
         let encoder = self.inner.encoder;
         Ok(SocialEngineBuilder {
-            inner: SocialProducer { encoder, producer },
+            inner: SocialProducer {
+                encoder,
+                producer,
+                max_in_flight: tuning.max_in_flight.max(1),
+            },
         })
     }
 }
 
+fn producer_config(
+    brokers: &str,
+    username: &str,
+    password: &str,
+    tuning: &ProducerTuning,
+) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config
+        .set("bootstrap.servers", brokers)
+        .set("message.timeout.ms", "5000")
+        .set("linger.ms", tuning.linger.as_millis().to_string())
+        .set("batch.size", tuning.batch_bytes.to_string())
+        .set("compression.type", &tuning.compression)
+        .set("enable.idempotence", tuning.idempotent.to_string())
+        .set("sasl.mechanism", "PLAIN")
+        .set("security.protocol", "SASL_PLAINTEXT")
+        .set("sasl.username", username)
+        .set("sasl.password", password);
+    config
+}
+
 impl<'a> SocialEngineBuilder<SocialDecoder<'a>> {
-    #[instrument(level = "debug", skip(brokers, username, password, group_id, self) err)]
     pub fn with_consumer<S: AsRef<str>>(
         self,
         brokers: S,
         username: S,
         password: S,
         group_id: S,
+    ) -> Result<SocialEngineBuilder<SocialConsumer<'a>>, Error> {
+        self.with_consumer_from(brokers, username, password, group_id, StartFrom::Committed)
+    }
+
+    /// `with_consumer` reading each partition from `start` the first time it
+    /// is assigned, instead of from the group's committed offsets.
+    #[instrument(level = "debug", skip(brokers, username, password, group_id, self) err)]
+    pub fn with_consumer_from<S: AsRef<str>>(
+        self,
+        brokers: S,
+        username: S,
+        password: S,
+        group_id: S,
+        start: StartFrom,
     ) -> Result<SocialEngineBuilder<SocialConsumer<'a>>, Error> {
         debug!("creating a consumer targeted at: {}", brokers.as_ref());
         let consumer = ClientConfig::new()
@@ -136,33 +261,129 @@ impl<'a> SocialEngineBuilder<SocialDecoder<'a>> {
             .set("session.timeout.ms", "6000")
             .set("enable.auto.commit", "false")
             .set("auto.offset.reset", "earliest")
+            .set("statistics.interval.ms", STATISTICS_INTERVAL_MS.to_string())
             .set("sasl.mechanism", "PLAIN")
             .set("security.protocol", "SASL_PLAINTEXT")
             .set("sasl.username", username.as_ref())
             .set("sasl.password", password.as_ref())
-            .create::<StreamConsumer>()?;
+            .create_with_context(SeekContext::new(start))?;
 
         let decoder = self.inner.decoder;
         Ok(SocialEngineBuilder {
-            inner: SocialConsumer { decoder, consumer },
+            inner: SocialConsumer {
+                decoder,
+                consumer,
+                filter: None,
+            },
         })
     }
 }
 
 impl<'a> SocialEngineBuilder<SocialConsumer<'a>> {
+    /// Skips, without decoding, records whose headers `filter` rejects.
+    /// Skipped records are committed like processed ones.
+    pub fn with_header_filter<P>(mut self, filter: P) -> Self
+    where
+        P: Fn(&PostHeaders) -> bool + Send + Sync + 'static,
+    {
+        self.inner.filter = Some(Box::new(filter));
+        self
+    }
+
     pub fn build(self) -> SocialConsumer<'a> {
         self.inner
     }
 }
 
+impl<'a> SocialEngineBuilder<SocialCodec<'a>> {
+    /// A consumer in `group_id` paired with a producer owning
+    /// `transactional_id`. Only one process may use a transactional id at a
+    /// time; starting another fences the first one off.
+    #[instrument(
+        level = "debug",
+        skip(brokers, username, password, group_id, transactional_id, self),
+        err
+    )]
+    pub fn with_transactions<S: AsRef<str>>(
+        self,
+        brokers: S,
+        username: S,
+        password: S,
+        group_id: S,
+        transactional_id: S,
+    ) -> Result<SocialEngineBuilder<SocialTransformer<'a>>, Error> {
+        debug!(
+            "creating a transactional consumer and producer targeted at: {}",
+            brokers.as_ref()
+        );
+        let consumer = ClientConfig::new()
+            .set("group.id", group_id.as_ref())
+            .set("bootstrap.servers", brokers.as_ref())
+            .set("session.timeout.ms", "6000")
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            // Skip records of aborted upstream transactions.
+            .set("isolation.level", "read_committed")
+            .set("sasl.mechanism", "PLAIN")
+            .set("security.protocol", "SASL_PLAINTEXT")
+            .set("sasl.username", username.as_ref())
+            .set("sasl.password", password.as_ref())
+            .create::<StreamConsumer>()?;
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", brokers.as_ref())
+            .set("transactional.id", transactional_id.as_ref())
+            .set("enable.idempotence", "true")
+            .set("sasl.mechanism", "PLAIN")
+            .set("security.protocol", "SASL_PLAINTEXT")
+            .set("sasl.username", username.as_ref())
+            .set("sasl.password", password.as_ref())
+            .create()?;
+        producer.init_transactions(TRANSACTION_TIMEOUT)?;
+
+        let SocialCodec { decoder, encoder } = self.inner;
+        Ok(SocialEngineBuilder {
+            inner: SocialTransformer {
+                decoder,
+                encoder,
+                consumer,
+                producer,
+                dead_letter: None,
+            },
+        })
+    }
+}
+
+impl<'a> SocialEngineBuilder<SocialTransformer<'a>> {
+    /// Writes inputs that cannot be decoded or transformed to `topic`, as
+    /// they were read, in the transaction that consumes them.
+    pub fn with_dead_letter(mut self, topic: impl Into<String>) -> Self {
+        self.inner.dead_letter = Some(topic.into());
+        self
+    }
+
+    pub fn build(self) -> SocialTransformer<'a> {
+        self.inner
+    }
+}
+
 impl<'a> SocialConsumer<'a> {
+    /// Per-partition lag, refreshed every `STATISTICS_INTERVAL_MS` while
+    /// `run` is consuming.
+    pub fn lag_monitor(&self) -> LagMonitor {
+        self.consumer.context().lag_monitor()
+    }
+
     pub async fn run<T, F, Fut>(self, topics: &[&str], f: F)
     where
         T: Debug + ProstMessage + Default,
-        F: Fn(T) -> Fut,
+        F: Fn(Envelope<T>) -> Fut,
         Fut: Future<Output = Result<(), Error>>,
     {
-        let SocialConsumer { decoder, consumer } = self;
+        let SocialConsumer {
+            decoder,
+            consumer,
+            filter,
+        } = self;
         consumer
             .subscribe(topics)
             .expect("Failed to subscribe to Kafka topics");
@@ -176,32 +397,32 @@ impl<'a> SocialConsumer<'a> {
             match consumer.recv().await {
                 Err(e) => warn!("Kafka error: {}", e),
                 Ok(m) => {
-                    let Some(_) = m.payload() else {
-                        warn!("Received message with empty payload, skipping.");
-                        continue;
-                    };
-                    let decoded_message = match decoder.decode(m.payload()).await {
-                        Ok(msg) => msg,
-                        Err(e) => {
-                            warn!("Schema registry decoding error: {}. Skipping message.", e);
-                            continue;
+                    consumer
+                        .context()
+                        .consumed(m.topic(), m.partition(), m.offset());
+                    let headers = m.headers().map(PostHeaders::from_kafka).unwrap_or_default();
+                    if filter.as_ref().is_some_and(|filter| !filter(&headers)) {
+                        debug!(offset = m.offset(), "Skipping filtered message");
+                        if let Err(e) = consumer.commit_message(&m, CommitMode::Async) {
+                            warn!("Failed to commit offset: {}", e);
                         }
-                    };
-
-                    let Some(decoded_message) = decoded_message else {
-                        warn!("Received message with empty payload, skipping.");
                         continue;
-                    };
-                    let final_message = match T::decode(&*decoded_message.bytes) {
-                        Ok(msg) => msg,
-                        Err(e) => {
-                            warn!("Protobuf decoding error: {}. Skipping message.", e);
-                            continue;
-                        }
+                    }
+                    let Some(final_message) = decode_payload::<T>(&decoder, m.payload()).await
+                    else {
+                        continue;
                     };
 
                     info!("Successfully decoded message: {:?}", final_message);
-                    if let Err(e) = f(final_message).await {
+                    let envelope = Envelope {
+                        topic: m.topic().to_string(),
+                        partition: m.partition(),
+                        offset: m.offset(),
+                        key: m.key().map(|key| String::from_utf8_lossy(key).into_owned()),
+                        headers,
+                        message: final_message,
+                    };
+                    if let Err(e) = f(envelope).await {
                         warn!(
                             "Error processing message: {}. Message will not be committed.",
                             e
@@ -217,13 +438,48 @@ impl<'a> SocialConsumer<'a> {
     }
 }
 
+/// Decodes a schema registry framed protobuf payload, logging and returning
+/// `None` for anything that is not a `T`.
+async fn decode_payload<T>(decoder: &ProtoRawDecoder<'_>, payload: Option<&[u8]>) -> Option<T>
+where
+    T: ProstMessage + Default,
+{
+    let Some(_) = payload else {
+        warn!("Received message with empty payload, skipping.");
+        return None;
+    };
+    let decoded_message = match decoder.decode(payload).await {
+        Ok(msg) => msg,
+        Err(e) => {
+            warn!("Schema registry decoding error: {}. Skipping message.", e);
+            return None;
+        }
+    };
+
+    let Some(decoded_message) = decoded_message else {
+        warn!("Received message with empty payload, skipping.");
+        return None;
+    };
+    match T::decode(&*decoded_message.bytes) {
+        Ok(msg) => Some(msg),
+        Err(e) => {
+            warn!("Protobuf decoding error: {}. Skipping message.", e);
+            None
+        }
+    }
+}
+
 pub struct MultiSocialProducer<'a, T>
 where
     T: Debug + ProstMessage + PostId,
 {
     producer: FutureProducer,
     encoder: ProtoRawEncoder<'a>,
-    recv: Receiver<T>,
+    max_in_flight: usize,
+    recv: FeederReceiver<T>,
+    spool: Option<Spool>,
+    instance: Option<String>,
+    partition_key: PartitionKey,
 }
 
 impl<'a> SocialEngineBuilder<SocialProducer<'a>> {
@@ -235,44 +491,1006 @@ impl<'a> SocialEngineBuilder<SocialProducer<'a>> {
     where
         T: Debug + ProstMessage + PostId,
     {
-        let SocialProducer { producer, encoder } = self.inner;
-        let (tx, rx) = channel::<T>(buffer);
-        (
-            MultiSocialProducer {
-                producer,
-                encoder,
-                recv: rx,
-            },
-            FeederQueue::create(tx),
-        )
+        let (queue, recv) = FeederQueue::bounded(buffer, Overflow::Block);
+        (self.producer_for(recv), queue)
+    }
+
+    /// `build_multi` with a choice of what feeders' `send` does when the
+    /// producer falls behind.
+    pub fn build_multi_with<T>(
+        self,
+        buffer: usize,
+        overflow: Overflow,
+    ) -> Result<(MultiSocialProducer<'a, T>, FeederQueue<T>), Error>
+    where
+        T: Debug + ProstMessage + PostId + Default,
+    {
+        let (queue, recv) = FeederQueue::new(buffer, overflow)?;
+        Ok((self.producer_for(recv), queue))
+    }
+
+    fn producer_for<T>(self, recv: FeederReceiver<T>) -> MultiSocialProducer<'a, T>
+    where
+        T: Debug + ProstMessage + PostId,
+    {
+        let SocialProducer {
+            producer,
+            encoder,
+            max_in_flight,
+        } = self.inner;
+        MultiSocialProducer {
+            producer,
+            encoder,
+            max_in_flight,
+            recv,
+            spool: None,
+            instance: None,
+            partition_key: PartitionKey::default(),
+        }
     }
 }
 
+/// How long a failed delivery waits before the spool is retried, doubling
+/// up to `SPOOL_RETRY_MAX` while Kafka stays unreachable.
+const SPOOL_RETRY_MIN: Duration = Duration::from_secs(1);
+const SPOOL_RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// Records delivered from the spool before new posts are taken off the
+/// queue again.
+const SPOOL_DRAIN_BATCH: usize = 500;
+
 impl<'a, T> MultiSocialProducer<'a, T>
 where
     T: Debug + ProstMessage + PostId,
 {
+    /// Spools records that cannot be delivered instead of failing `run`.
+    pub fn with_spool(mut self, spool: Spool) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
+    /// Names this producer in the `feeder-instance` header of its records.
+    pub fn with_instance(mut self, instance: impl Into<String>) -> Self {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    /// How records are keyed, and so partitioned; `PartitionKey::Id` unless
+    /// set.
+    pub fn with_partition_key(mut self, partition_key: PartitionKey) -> Self {
+        self.partition_key = partition_key;
+        self
+    }
+
+    pub fn spool_monitor(&self) -> Option<SpoolMonitor> {
+        self.spool.as_ref().map(Spool::monitor)
+    }
+
+    /// Sends every post from the queue to `topic` until the feeders are done,
+    /// keeping up to `max_in_flight` records awaiting delivery.
+    ///
+    /// Without a spool the first failed delivery ends the run. With one, the
+    /// failed record and every post after it go to the spool, which is
+    /// delivered in order once Kafka accepts records again. Records already
+    /// in flight when the first failure is seen may be delivered ahead of it.
     pub async fn run(self, topic: &str) -> Result<(), Error> {
         let MultiSocialProducer {
             producer,
             encoder,
-            mut recv,
+            max_in_flight,
+            recv,
+            spool,
+            instance,
+            partition_key,
         } = self;
-        while let Some(post) = recv.recv().await {
-            let mut proto_bytes = Vec::new();
-            post.encode(&mut proto_bytes)?;
-            let subject_strategy = SubjectNameStrategy::TopicNameStrategy(topic.to_string(), false);
-            let payload = encoder
-                .encode(&proto_bytes, "social.v1.Post", subject_strategy)
-                .await?;
-            producer
-                .send(
-                    FutureRecord::to(topic).payload(&payload).key(&post.id()),
-                    Duration::from_secs(5),
-                )
+        let sender = Sender {
+            producer,
+            encoder,
+            topic,
+            instance,
+            framing: None,
+            schema_id: None,
+        };
+        produce(sender, recv, spool, max_in_flight, partition_key).await
+    }
+}
+
+/// The body of `MultiSocialProducer::run`, over any registry and producer.
+async fn produce<T, E, P>(
+    mut sender: Sender<'_, E, P>,
+    mut recv: FeederReceiver<T>,
+    mut spool: Option<Spool>,
+    max_in_flight: usize,
+    partition_key: PartitionKey,
+) -> Result<(), Error>
+where
+    T: Debug + ProstMessage + PostId,
+    E: Frame,
+    P: Produce,
+{
+    let mut in_flight = FuturesOrdered::new();
+    let mut open = true;
+    let mut backoff = SPOOL_RETRY_MIN;
+    let retry = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(retry);
+    while open || !in_flight.is_empty() {
+        let spooling = spool.as_ref().is_some_and(|spool| !spool.is_empty());
+        // While spooling, in-flight records settle before new posts are
+        // taken so that failures land in the spool ahead of them.
+        let accepting =
+            open && in_flight.len() < max_in_flight && (!spooling || in_flight.is_empty());
+        tokio::select! {
+            post = recv.recv(), if accepting => {
+                let Some(post) = post else {
+                    open = false;
+                    continue;
+                };
+                let record = SpooledRecord {
+                    key: partition_key.key_for(&post),
+                    headers: PostHeaders::ingested(post.service_name()),
+                    timestamp: post.timestamp_millis(),
+                    value: post.encode_to_vec(),
+                };
+                match &mut spool {
+                    Some(spool) if spooling => {
+                        spool.push(&record);
+                    }
+                    _ => in_flight.push_back(sender.enqueue(record).await),
+                }
+            }
+            Some((record, delivery)) = in_flight.next() => {
+                let Err(e) = delivery else { continue };
+                warn!(key = %record.key, "Delivery failed: {}", e);
+                let Some(spool) = &mut spool else {
+                    return Err(e);
+                };
+                if !spooling {
+                    warn!(retry_in = ?backoff, "Spooling records until Kafka recovers");
+                    retry.as_mut().reset(Instant::now() + backoff);
+                }
+                spool.push(&record);
+            }
+            () = &mut retry, if spooling && in_flight.is_empty() => {
+                let spool = spool.as_mut().expect("spooling implies a spool");
+                match sender.drain(spool, SPOOL_DRAIN_BATCH, max_in_flight).await {
+                    Ok(()) => {
+                        if spool.is_empty() {
+                            info!("Spool drained, delivering directly again");
+                        }
+                        backoff = SPOOL_RETRY_MIN;
+                        retry.as_mut().reset(Instant::now());
+                    }
+                    Err(e) => {
+                        backoff = (backoff * 2).min(SPOOL_RETRY_MAX);
+                        warn!(retry_in = ?backoff, pending = spool.stats().pending, "Spool delivery failed: {}", e);
+                        retry.as_mut().reset(Instant::now() + backoff);
+                    }
+                }
+            }
+        }
+    }
+
+    // The feeders are done; deliver what is left while Kafka is up and
+    // keep the rest on disk for the next run.
+    if let Some(spool) = &mut spool
+        && let Err(e) = sender.drain(spool, usize::MAX, max_in_flight).await
+    {
+        warn!(
+            pending = spool.stats().pending,
+            "Leaving records in the spool: {}", e
+        );
+    }
+    Ok(())
+}
+
+type Delivery = BoxFuture<'static, (SpooledRecord, Result<(), Error>)>;
+
+/// Schema registry framing of records; the registry itself outside of
+/// tests.
+trait Frame {
+    async fn frame(&self, proto_bytes: &[u8], topic: &str) -> Result<Vec<u8>, Error>;
+}
+
+impl Frame for ProtoRawEncoder<'_> {
+    async fn frame(&self, proto_bytes: &[u8], topic: &str) -> Result<Vec<u8>, Error> {
+        let subject_strategy = SubjectNameStrategy::TopicNameStrategy(topic.to_string(), false);
+        self.encode(proto_bytes, "social.v1.Post", subject_strategy)
+            .await
+            .map_err(|e| {
+                // Failed lookups are cached too; clear them so the next
+                // record asks the registry again.
+                self.remove_errors_from_cache();
+                e.into()
+            })
+    }
+}
+
+/// Hands records to Kafka; librdkafka's producer outside of tests.
+trait Produce {
+    /// Queues `record`, returning a future that resolves once the broker
+    /// acknowledges it.
+    fn produce(&self, record: FutureRecord<'_, str, [u8]>) -> Result<Acked, Error>;
+}
+
+type Acked = BoxFuture<'static, Result<(), Error>>;
+
+impl Produce for FutureProducer {
+    fn produce(&self, record: FutureRecord<'_, str, [u8]>) -> Result<Acked, Error> {
+        let delivery = self.send_result(record).map_err(|(e, _)| e)?;
+        Ok(Box::pin(async move {
+            match delivery.await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err((e, _))) => Err(e.into()),
+                Err(_) => Err(KafkaError::Canceled.into()),
+            }
+        }))
+    }
+}
+
+struct Sender<'t, E, P> {
+    producer: P,
+    encoder: E,
+    topic: &'t str,
+    instance: Option<String>,
+    /// Magic byte, schema id and message index the registry framing puts in
+    /// front of every record, learned from the first successful encode.
+    framing: Option<Vec<u8>>,
+    schema_id: Option<u32>,
+}
+
+impl<E: Frame, P: Produce> Sender<'_, E, P> {
+    async fn encode(&mut self, proto_bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        if let Some(framing) = &self.framing {
+            return Ok([framing.as_slice(), proto_bytes].concat());
+        }
+        let payload = self.encoder.frame(proto_bytes, self.topic).await?;
+        let framing = &payload[..payload.len() - proto_bytes.len()];
+        self.schema_id = framing
+            .get(1..5)
+            .map(|id| u32::from_be_bytes(id.try_into().expect("4 bytes")));
+        self.framing = Some(framing.to_vec());
+        Ok(payload)
+    }
+
+    /// Encodes `record` and hands it to librdkafka, returning a future that
+    /// resolves once the broker acknowledges it.
+    async fn enqueue(&mut self, record: SpooledRecord) -> Delivery {
+        let payload = match self.encode(&record.value).await {
+            Ok(payload) => payload,
+            Err(e) => return Box::pin(future::ready((record, Err(e)))),
+        };
+        let headers = PostHeaders {
+            feeder_instance: self.instance.clone(),
+            schema_id: self.schema_id,
+            content_type: Some(POST_CONTENT_TYPE.to_string()),
+            ..record.headers.clone()
+        };
+        let mut kafka_record = FutureRecord::to(self.topic)
+            .payload(payload.as_slice())
+            .key(record.key.as_str())
+            .headers(headers.to_kafka());
+        // Kafka rejects timestamps before the epoch; those get producer time.
+        if let Some(timestamp) = record.timestamp.filter(|&ts| ts >= 0) {
+            kafka_record = kafka_record.timestamp(timestamp);
+        }
+        match self.producer.produce(kafka_record) {
+            Ok(acked) => Box::pin(async move { (record, acked.await) }),
+            Err(e) => Box::pin(future::ready((record, Err(e)))),
+        }
+    }
+
+    /// Delivers up to `limit` spooled records, oldest first, keeping up to
+    /// `max_in_flight` of them awaiting acknowledgement. Records leave the
+    /// spool in order as their deliveries complete; the first failure stops
+    /// the drain with it and everything after it still spooled.
+    async fn drain(
+        &mut self,
+        spool: &mut Spool,
+        limit: usize,
+        max_in_flight: usize,
+    ) -> Result<(), Error> {
+        let mut in_flight = FuturesOrdered::new();
+        let mut sent = 0;
+        loop {
+            while sent < limit
+                && in_flight.len() < max_in_flight
+                && let Some(record) = spool.get(in_flight.len())
+            {
+                let record = record.clone();
+                in_flight.push_back(self.enqueue(record).await);
+                sent += 1;
+            }
+            let Some((_, delivery)) = in_flight.next().await else {
+                return Ok(());
+            };
+            delivery?;
+            spool.pop();
+        }
+    }
+}
+
+/// Inputs read into one transaction before it is committed.
+const TRANSACTION_MAX_INPUTS: usize = 500;
+
+/// How long a transaction stays open waiting for more inputs.
+const TRANSACTION_LINGER: Duration = Duration::from_millis(100);
+
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pause after an aborted transaction before its inputs are read again.
+const TRANSACTION_RETRY: Duration = Duration::from_secs(1);
+
+impl<'a> SocialTransformer<'a> {
+    /// Reads posts from `inputs`, passes each through `f` and writes what it
+    /// returns to `output` exactly once: a batch's outputs and the input
+    /// offsets they came from are committed in one transaction.
+    ///
+    /// An input that cannot be decoded, or for which `f` fails, goes to the
+    /// dead-letter topic when one is set. Otherwise its batch is aborted and
+    /// read again from the last committed offsets, as is a batch whose
+    /// outputs cannot be delivered; an input that never transforms holds the
+    /// transformer at that point rather than being lost. Returns on fatal
+    /// errors, such as another process taking over the transactional id.
+    pub async fn run<I, O, F, Fut>(self, inputs: &[&str], output: &str, f: F) -> Result<(), Error>
+    where
+        I: Debug + ProstMessage + Default,
+        O: ProstMessage + PostId,
+        F: Fn(I) -> Fut,
+        Fut: Future<Output = Result<Vec<O>, Error>>,
+    {
+        let SocialTransformer {
+            decoder,
+            encoder,
+            consumer,
+            producer,
+            dead_letter,
+        } = self;
+        consumer.subscribe(inputs)?;
+        info!(?inputs, output, ?dead_letter, "Transformer started");
+        let mut sender = Sender {
+            producer: producer.clone(),
+            encoder,
+            topic: output,
+            instance: None,
+            framing: None,
+            schema_id: None,
+        };
+
+        loop {
+            let batch = read_batch(
+                || consumer.recv(),
+                TRANSACTION_MAX_INPUTS,
+                TRANSACTION_LINGER,
+            )
+            .await;
+            producer.begin_transaction()?;
+            let mut deliveries = Vec::new();
+            let mut outcome = Ok(());
+            for message in &batch {
+                let headers = message
+                    .headers()
+                    .map(PostHeaders::from_kafka)
+                    .unwrap_or_default();
+                let input = decode_payload::<I>(&decoder, message.payload()).await;
+                match transform(input, headers.trace_id.clone(), &f).await {
+                    Ok(records) => {
+                        for record in records {
+                            deliveries.push(sender.enqueue(record).await);
+                        }
+                    }
+                    Err(e) => match &dead_letter {
+                        Some(topic) => {
+                            warn!(
+                                input = message.topic(),
+                                partition = message.partition(),
+                                offset = message.offset(),
+                                "Dead-lettering input: {}",
+                                e
+                            );
+                            deliveries.push(dead_letter_input(
+                                &sender.producer,
+                                topic,
+                                message,
+                                headers,
+                                &e,
+                            ));
+                        }
+                        None => {
+                            warn!(
+                                input = message.topic(),
+                                partition = message.partition(),
+                                offset = message.offset(),
+                                "Failed to transform input: {}",
+                                e
+                            );
+                            outcome = Err(e);
+                            break;
+                        }
+                    },
+                }
+            }
+
+            let (read, written) = (batch.len(), deliveries.len());
+            for (record, delivery) in future::join_all(deliveries).await {
+                if let Err(e) = delivery {
+                    warn!(key = %record.key, "Delivery failed: {}", e);
+                    outcome = outcome.and(Err(e));
+                }
+            }
+            let outcome = match outcome {
+                Ok(()) => commit_transaction(&consumer, &producer).await,
+                Err(e) => Err(e),
+            };
+            match outcome {
+                Ok(()) => debug!(read, written, "Committed transaction"),
+                Err(e) if is_fatal(&e) => return Err(e),
+                Err(e) => {
+                    warn!(read, retry_in = ?TRANSACTION_RETRY, "Aborting transaction: {}", e);
+                    blocking(&producer, |producer| {
+                        producer.abort_transaction(TRANSACTION_TIMEOUT)
+                    })
+                    .await?;
+                    rewind(&consumer)?;
+                    tokio::time::sleep(TRANSACTION_RETRY).await;
+                }
+            }
+        }
+    }
+}
+
+/// Reads one transaction's inputs: waits as long as it takes for the first,
+/// then up to `linger` for more, stopping at `max`. Read errors are logged
+/// and skipped.
+async fn read_batch<M, R, Fut>(mut recv: R, max: usize, linger: Duration) -> Vec<M>
+where
+    R: FnMut() -> Fut,
+    Fut: Future<Output = Result<M, KafkaError>>,
+{
+    let mut batch = Vec::new();
+    let mut deadline = None;
+    while batch.len() < max {
+        let message = match deadline {
+            None => recv().await,
+            Some(deadline) => match tokio::time::timeout_at(deadline, recv()).await {
+                Ok(message) => message,
+                Err(_) => break,
+            },
+        };
+        match message {
+            Ok(message) => {
+                deadline.get_or_insert_with(|| Instant::now() + linger);
+                batch.push(message);
+            }
+            Err(e) => warn!("Kafka error: {}", e),
+        }
+    }
+    batch
+}
+
+/// The output records for one input: what `f` makes of it, keyed by post id
+/// and carrying the input's trace id. `None` is an input that could not be
+/// decoded.
+async fn transform<I, O, F, Fut>(
+    input: Option<I>,
+    trace_id: Option<String>,
+    f: &F,
+) -> Result<Vec<SpooledRecord>, Error>
+where
+    O: ProstMessage + PostId,
+    F: Fn(I) -> Fut,
+    Fut: Future<Output = Result<Vec<O>, Error>>,
+{
+    let input = input.ok_or_else(|| Error::Generic("input could not be decoded".to_string()))?;
+    let outputs = f(input).await?;
+    Ok(outputs
+        .into_iter()
+        .map(|output| {
+            let ingested = PostHeaders::ingested(output.service_name());
+            SpooledRecord {
+                key: output.id(),
+                headers: PostHeaders {
+                    trace_id: trace_id.clone().or(ingested.trace_id),
+                    ..ingested
+                },
+                timestamp: output.timestamp_millis(),
+                value: output.encode_to_vec(),
+            }
+        })
+        .collect())
+}
+
+/// Writes `message` unchanged to `topic`, with why it could not be
+/// transformed in the `dead-letter-reason` header.
+fn dead_letter_input<P: Produce>(
+    producer: &P,
+    topic: &str,
+    message: &impl Message,
+    headers: PostHeaders,
+    reason: &Error,
+) -> Delivery {
+    let record = SpooledRecord {
+        key: message
+            .key()
+            .map(|key| String::from_utf8_lossy(key).into_owned())
+            .unwrap_or_default(),
+        headers,
+        timestamp: message.timestamp().to_millis(),
+        value: message.payload().unwrap_or_default().to_vec(),
+    };
+    let reason = reason.to_string();
+    let mut kafka_record = FutureRecord::to(topic)
+        .payload(record.value.as_slice())
+        .key(record.key.as_str())
+        .headers(record.headers.to_kafka().insert(Header {
+            key: DEAD_LETTER_REASON,
+            value: Some(reason.as_str()),
+        }));
+    if let Some(timestamp) = record.timestamp {
+        kafka_record = kafka_record.timestamp(timestamp);
+    }
+    match producer.produce(kafka_record) {
+        Ok(acked) => Box::pin(async move { (record, acked.await) }),
+        Err(e) => Box::pin(future::ready((record, Err(e)))),
+    }
+}
+
+/// Runs one of rdkafka's blocking producer calls off the async runtime.
+async fn blocking<R, F>(producer: &FutureProducer, f: F) -> Result<R, Error>
+where
+    R: Send + 'static,
+    F: FnOnce(&FutureProducer) -> Result<R, KafkaError> + Send + 'static,
+{
+    let producer = producer.clone();
+    tokio::task::spawn_blocking(move || f(&producer))
+        .await
+        .map_err(|e| Error::Generic(e.to_string()))?
+        .map_err(Error::from)
+}
+
+/// Adds the consumer's position to the open transaction and commits it.
+async fn commit_transaction(
+    consumer: &StreamConsumer,
+    producer: &FutureProducer,
+) -> Result<(), Error> {
+    let offsets = consumer.position()?;
+    let metadata = consumer
+        .group_metadata()
+        .ok_or_else(|| Error::Generic("consumer has no group metadata".to_string()))?;
+    blocking(producer, move |producer| {
+        producer.send_offsets_to_transaction(&offsets, &metadata, TRANSACTION_TIMEOUT)?;
+        producer.commit_transaction(TRANSACTION_TIMEOUT)
+    })
+    .await
+}
+
+/// Moves the consumer back to its committed offsets so an aborted batch is
+/// read again.
+fn rewind(consumer: &StreamConsumer) -> Result<(), Error> {
+    let committed = consumer.committed(TRANSACTION_TIMEOUT)?;
+    consumer.seek_partitions(rewind_offsets(&committed)?, TRANSACTION_TIMEOUT)?;
+    Ok(())
+}
+
+/// Committed offsets as they are, and the start of partitions nothing was
+/// committed for.
+fn rewind_offsets(committed: &TopicPartitionList) -> Result<TopicPartitionList, Error> {
+    let mut offsets = TopicPartitionList::new();
+    for elem in committed.elements() {
+        let offset = match elem.offset() {
+            offset @ Offset::Offset(_) => offset,
+            _ => Offset::Beginning,
+        };
+        offsets.add_partition_offset(elem.topic(), elem.partition(), offset)?;
+    }
+    Ok(offsets)
+}
+
+fn is_fatal(e: &Error) -> bool {
+    matches!(e, Error::Producer(KafkaError::Transaction(e)) if e.is_fatal())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Acked, Frame, Produce, ProducerTuning, Sender, SocialEngineBuilder, dead_letter_input,
+        is_fatal, produce, producer_config, read_batch, rewind_offsets, transform,
+    };
+    use crate::{
+        envelope::{DEAD_LETTER_REASON, PostHeaders},
+        error::Error,
+        queue::{FeederQueue, FeederReceiver, Overflow},
+        spool::{Spool, SpooledRecord},
+    };
+    use prost::Message;
+    use proto_definitions::{PartitionKey, social::v1::Post};
+    use rdkafka::{
+        Offset, Timestamp, TopicPartitionList,
+        error::KafkaError,
+        message::{Headers, OwnedMessage},
+        producer::FutureRecord,
+    };
+    use std::{
+        collections::{HashSet, VecDeque},
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+    use url::Url;
+
+    /// Frames records with schema id 42, counting registry lookups.
+    #[derive(Clone, Default)]
+    struct MockRegistry {
+        lookups: Arc<AtomicUsize>,
+        fail_first: bool,
+    }
+
+    impl Frame for MockRegistry {
+        async fn frame(&self, proto_bytes: &[u8], _topic: &str) -> Result<Vec<u8>, Error> {
+            if self.lookups.fetch_add(1, Ordering::SeqCst) == 0 && self.fail_first {
+                return Err(Error::Generic("registry unavailable".to_string()));
+            }
+            Ok([&[0, 0, 0, 0, 42, 0][..], proto_bytes].concat())
+        }
+    }
+
+    #[derive(Debug)]
+    struct Produced {
+        topic: String,
+        key: String,
+        headers: PostHeaders,
+        dead_letter_reason: Option<String>,
+        payload: Vec<u8>,
+    }
+
+    #[derive(Debug, Default)]
+    struct Broker {
+        in_flight: usize,
+        max_in_flight: usize,
+        produced: Vec<Produced>,
+    }
+
+    /// Acknowledges records 10ms after they are produced, failing the one
+    /// keyed `rejected`.
+    #[derive(Clone, Default)]
+    struct MockBroker {
+        broker: Arc<Mutex<Broker>>,
+        rejected: Option<String>,
+    }
+
+    impl Produce for MockBroker {
+        fn produce(&self, record: FutureRecord<'_, str, [u8]>) -> Result<Acked, Error> {
+            let key = record.key.unwrap().to_string();
+            let headers = record.headers.as_ref().unwrap();
+            let dead_letter_reason = headers
+                .iter()
+                .find(|header| header.key == DEAD_LETTER_REASON)
+                .and_then(|header| header.value)
+                .map(|value| String::from_utf8_lossy(value).into_owned());
+            let mut broker = self.broker.lock().unwrap();
+            broker.in_flight += 1;
+            broker.max_in_flight = broker.max_in_flight.max(broker.in_flight);
+            broker.produced.push(Produced {
+                topic: record.topic.to_string(),
+                key: key.clone(),
+                headers: PostHeaders::from_kafka(headers),
+                dead_letter_reason,
+                payload: record.payload.unwrap().to_vec(),
+            });
+            let rejected = self.rejected.as_ref() == Some(&key);
+            let broker = self.broker.clone();
+            Ok(Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                broker.lock().unwrap().in_flight -= 1;
+                if rejected {
+                    return Err(Error::Generic(format!("broker rejected {key}")));
+                }
+                Ok(())
+            }))
+        }
+    }
+
+    async fn queued(posts: usize) -> FeederReceiver<Post> {
+        let (queue, recv) = FeederQueue::bounded(posts, Overflow::Block);
+        for id in 1..=posts {
+            let post = Post {
+                id: id.to_string(),
+                ..Default::default()
+            };
+            queue.send(post).await.unwrap();
+        }
+        recv
+    }
+
+    fn sender(
+        registry: &MockRegistry,
+        broker: &MockBroker,
+    ) -> Sender<'static, MockRegistry, MockBroker> {
+        Sender {
+            producer: broker.clone(),
+            encoder: registry.clone(),
+            topic: "posts",
+            instance: None,
+            framing: None,
+            schema_id: None,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_window_caps_records_awaiting_delivery() {
+        let (registry, broker) = (MockRegistry::default(), MockBroker::default());
+        let sender = sender(&registry, &broker);
+        produce(sender, queued(20).await, None, 4, PartitionKey::Id)
+            .await
+            .unwrap();
+
+        let broker = broker.broker.lock().unwrap();
+        assert_eq!(broker.max_in_flight, 4);
+        let keys: Vec<_> = broker.produced.iter().map(|p| p.key.as_str()).collect();
+        let expected: Vec<_> = (1..=20).map(|id| format!("mastodon:{id}")).collect();
+        assert_eq!(keys, expected);
+        assert!(
+            broker
+                .produced
+                .iter()
+                .all(|p| p.headers.schema_id == Some(42))
+        );
+        // Framing is learned from the first record; the rest never ask.
+        assert_eq!(registry.lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_deliveries_are_reported() {
+        let registry = MockRegistry::default();
+        let broker = MockBroker {
+            rejected: Some("mastodon:3".to_string()),
+            ..Default::default()
+        };
+        let result = produce(
+            sender(&registry, &broker),
+            queued(10).await,
+            None,
+            4,
+            PartitionKey::Id,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Generic(e)) if e == "broker rejected mastodon:3"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_failed_registry_lookup_is_retried_from_the_spool() {
+        let path = std::env::temp_dir().join(format!("producer-retry-{}.log", std::process::id()));
+        let registry = MockRegistry {
+            fail_first: true,
+            ..Default::default()
+        };
+        let broker = MockBroker::default();
+        let spool = Spool::open(&path, 1024 * 1024).unwrap();
+        let monitor = spool.monitor();
+        produce(
+            sender(&registry, &broker),
+            queued(5).await,
+            Some(spool),
+            4,
+            PartitionKey::Id,
+        )
+        .await
+        .unwrap();
+
+        let broker = broker.broker.lock().unwrap();
+        let keys: HashSet<_> = broker.produced.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys.len(), 5);
+        assert_eq!(monitor.stats().pending, 0);
+        // The failed lookup was not cached: one more ask, then framing is known.
+        assert_eq!(registry.lookups.load(Ordering::SeqCst), 2);
+        std::fs::remove_file(format!("{}.offset", path.display())).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spooled_records_drain_through_the_window() {
+        let path = std::env::temp_dir().join(format!("producer-drain-{}.log", std::process::id()));
+        let registry = MockRegistry::default();
+        let broker = MockBroker {
+            rejected: Some("mastodon:15".to_string()),
+            ..Default::default()
+        };
+        let mut spool = Spool::open(&path, 1024 * 1024).unwrap();
+        for id in 1..=20 {
+            let post = Post {
+                id: id.to_string(),
+                ..Default::default()
+            };
+            spool.push(&SpooledRecord {
+                key: PartitionKey::Id.key_for(&post),
+                headers: PostHeaders::default(),
+                timestamp: None,
+                value: post.encode_to_vec(),
+            });
+        }
+
+        let mut sender = sender(&registry, &broker);
+        sender.drain(&mut spool, 10, 4).await.unwrap();
+        assert_eq!(spool.front().unwrap().key, "mastodon:11");
+        assert!(sender.drain(&mut spool, usize::MAX, 4).await.is_err());
+        // Everything from the rejected record on stays spooled, in order.
+        assert_eq!(spool.stats().pending, 6);
+        assert_eq!(spool.front().unwrap().key, "mastodon:15");
+
+        let broker = broker.broker.lock().unwrap();
+        assert_eq!(broker.max_in_flight, 4);
+        let keys: Vec<_> = broker.produced.iter().map(|p| p.key.as_str()).collect();
+        let expected: Vec<_> = (1..=18).map(|id| format!("mastodon:{id}")).collect();
+        assert_eq!(keys, expected);
+        drop(spool);
+        std::fs::remove_file(format!("{}.offset", path.display())).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn undeliverable_posts_are_spooled_in_order() {
+        let path = std::env::temp_dir().join(format!("producer-run-{}.log", std::process::id()));
+        // Nothing listens on port 1, so neither the registry nor Kafka is up.
+        let (producer, queue) =
+            SocialEngineBuilder::encoder(Url::parse("http://127.0.0.1:1").unwrap())
+                .with_producer("127.0.0.1:1", "user", "password")
+                .unwrap()
+                .build_multi::<Post>(8);
+        let producer = producer.with_spool(Spool::open(&path, 1024 * 1024).unwrap());
+        let monitor = producer.spool_monitor().unwrap();
+        for id in 1..=3 {
+            queue
+                .send(Post {
+                    id: id.to_string(),
+                    ..Default::default()
+                })
                 .await
-                .map_err(|(err, _)| err)?;
+                .unwrap();
+        }
+        drop(queue);
+        producer.run("posts").await.unwrap();
+        let stats = monitor.stats();
+        assert_eq!((stats.pending, stats.spooled, stats.dropped), (3, 3, 0));
+
+        let mut spool = Spool::open(&path, 1024 * 1024).unwrap();
+        let mut keys = Vec::new();
+        while let Some(record) = spool.front() {
+            keys.push(record.key.clone());
+            spool.pop();
         }
-        Ok(())
+        assert_eq!(keys, ["mastodon:1", "mastodon:2", "mastodon:3"]);
+        drop(spool);
+        std::fs::remove_file(format!("{}.offset", path.display())).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batches_linger_after_their_first_input_up_to_a_limit() {
+        let ms = Duration::from_millis;
+        let mut arrivals = VecDeque::from([
+            // The first input is waited for however long it takes.
+            (Duration::from_secs(10), Ok(1)),
+            (ms(50), Ok(2)),
+            (ms(10), Err(KafkaError::Canceled)),
+            (ms(30), Ok(3)),
+            // Past the 100ms linger.
+            (ms(200), Ok(4)),
+        ]);
+        let recv = move || {
+            let (delay, input) = arrivals.pop_front().unwrap();
+            async move {
+                tokio::time::sleep(delay).await;
+                input
+            }
+        };
+        assert_eq!(read_batch(recv, 10, ms(100)).await, [1, 2, 3]);
+
+        let mut inputs = 0..;
+        let recv = move || {
+            let input = inputs.next().unwrap();
+            async move { Ok(input) }
+        };
+        assert_eq!(read_batch(recv, 3, ms(100)).await, [0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn failed_inputs_produce_no_records() {
+        let f = |post: Post| async move {
+            if post.id == "bad" {
+                return Err(Error::Generic("cannot transform".to_string()));
+            }
+            let copy = Post {
+                id: format!("{}-copy", post.id),
+                ..post.clone()
+            };
+            Ok(vec![post, copy])
+        };
+        let post = |id: &str| {
+            Some(Post {
+                id: id.to_string(),
+                ..Default::default()
+            })
+        };
+
+        let records = transform(post("1"), Some("trace".to_string()), &f)
+            .await
+            .unwrap();
+        let keys: Vec<_> = records.iter().map(|record| record.key.as_str()).collect();
+        assert_eq!(keys, ["mastodon:1", "mastodon:1-copy"]);
+        assert!(
+            records
+                .iter()
+                .all(|record| record.headers.trace_id.as_deref() == Some("trace"))
+        );
+        let records = transform(post("2"), None, &f).await.unwrap();
+        assert_eq!(records[0].headers.trace_id.as_ref().unwrap().len(), 32);
+
+        assert!(transform(post("bad"), None, &f).await.is_err());
+        assert!(transform(None, None, &f).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dead_letters_keep_the_input_and_say_why() {
+        let broker = MockBroker::default();
+        let headers = PostHeaders {
+            trace_id: Some("trace".to_string()),
+            ..Default::default()
+        };
+        let message = OwnedMessage::new(
+            Some(b"not a post".to_vec()),
+            Some(b"mastodon:9".to_vec()),
+            "posts".to_string(),
+            Timestamp::CreateTime(1_700_000_000_000),
+            0,
+            41,
+            Some(headers.to_kafka()),
+        );
+        let reason = Error::Generic("input could not be decoded".to_string());
+        let (record, delivery) =
+            dead_letter_input(&broker, "posts.dead", &message, headers.clone(), &reason).await;
+        assert!(delivery.is_ok());
+        assert_eq!(record.key, "mastodon:9");
+
+        let broker = broker.broker.lock().unwrap();
+        let produced = &broker.produced[0];
+        assert_eq!(produced.topic, "posts.dead");
+        assert_eq!(produced.payload, b"not a post");
+        assert_eq!(produced.headers, headers);
+        assert_eq!(
+            produced.dead_letter_reason.as_deref(),
+            Some("input could not be decoded")
+        );
+    }
+
+    #[test]
+    fn aborted_batches_rewind_to_committed_offsets() {
+        let mut committed = TopicPartitionList::new();
+        committed
+            .add_partition_offset("posts", 0, Offset::Offset(7))
+            .unwrap();
+        committed
+            .add_partition_offset("posts", 1, Offset::Invalid)
+            .unwrap();
+        let offsets = rewind_offsets(&committed).unwrap();
+        let offset = |partition| offsets.find_partition("posts", partition).unwrap().offset();
+        assert_eq!(offset(0), Offset::Offset(7));
+        assert_eq!(offset(1), Offset::Beginning);
+
+        assert!(!is_fatal(&Error::Producer(KafkaError::Canceled)));
+        assert!(!is_fatal(&Error::Generic("timed out".to_string())));
+    }
+
+    #[test]
+    fn idempotence_is_passed_to_librdkafka() {
+        let tuning = ProducerTuning {
+            idempotent: true,
+            ..Default::default()
+        };
+        let config = producer_config("kafka:9092", "user", "password", &tuning);
+        assert_eq!(config.get("enable.idempotence"), Some("true"));
+        let config = producer_config("kafka:9092", "user", "password", &ProducerTuning::default());
+        assert_eq!(config.get("enable.idempotence"), Some("false"));
+        assert_eq!(config.get("compression.type"), Some("lz4"));
     }
 }
@@ -0,0 +1,142 @@
+//! Post metadata carried in Kafka record headers, and the envelope consumers
+//! receive it in.
+//!
+//! Headers let consumers route or filter records without going to the
+//! schema registry or decoding the payload.
+
+use rdkafka::message::{Header, Headers, OwnedHeaders};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SERVICE: &str = "service";
+pub const FEEDER_INSTANCE: &str = "feeder-instance";
+pub const INGESTED_AT: &str = "ingested-at";
+pub const SCHEMA_ID: &str = "schema-id";
+pub const CONTENT_TYPE: &str = "content-type";
+pub const TRACE_ID: &str = "trace-id";
+/// Why a transformer moved a record to its dead-letter topic.
+pub const DEAD_LETTER_REASON: &str = "dead-letter-reason";
+
+/// Content type of every post record, as accepted by the ingest endpoint.
+pub const POST_CONTENT_TYPE: &str = "application/x-protobuf;messageType=social.v1.Post";
+
+/// The standard headers of a post record. Every field is optional on the
+/// way in, since records may come from older or foreign producers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostHeaders {
+    /// The post's service, e.g. `mastodon`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// The producing process, when it was given a name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feeder_instance: Option<String>,
+    /// Milliseconds since the Unix epoch when the producer took the post.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingested_at: Option<i64>,
+    /// Schema registry id of the schema version the payload is framed with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// 32 lowercase hex digits, kept when a post is transformed into others.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+}
+
+impl PostHeaders {
+    /// Headers for a post of `service` taken by the producer just now, with
+    /// a fresh trace id.
+    pub fn ingested(service: &str) -> Self {
+        PostHeaders {
+            service: Some(service.to_string()),
+            ingested_at: Some(now_millis()),
+            trace_id: Some(new_trace_id()),
+            ..Default::default()
+        }
+    }
+
+    pub fn to_kafka(&self) -> OwnedHeaders {
+        let schema_id = self.schema_id.map(|id| id.to_string());
+        let ingested_at = self.ingested_at.map(|at| at.to_string());
+        [
+            (SERVICE, self.service.as_deref()),
+            (FEEDER_INSTANCE, self.feeder_instance.as_deref()),
+            (INGESTED_AT, ingested_at.as_deref()),
+            (SCHEMA_ID, schema_id.as_deref()),
+            (CONTENT_TYPE, self.content_type.as_deref()),
+            (TRACE_ID, self.trace_id.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|value| (key, value)))
+        .fold(OwnedHeaders::new(), |headers, (key, value)| {
+            headers.insert(Header {
+                key,
+                value: Some(value),
+            })
+        })
+    }
+
+    /// Reads the standard headers, ignoring unknown ones and values that
+    /// do not parse.
+    pub fn from_kafka<H: Headers>(headers: &H) -> Self {
+        let mut parsed = PostHeaders::default();
+        for header in headers.iter() {
+            let Some(value) = header.value.and_then(|v| std::str::from_utf8(v).ok()) else {
+                continue;
+            };
+            match header.key {
+                SERVICE => parsed.service = Some(value.to_string()),
+                FEEDER_INSTANCE => parsed.feeder_instance = Some(value.to_string()),
+                INGESTED_AT => parsed.ingested_at = value.parse().ok(),
+                SCHEMA_ID => parsed.schema_id = value.parse().ok(),
+                CONTENT_TYPE => parsed.content_type = Some(value.to_string()),
+                TRACE_ID => parsed.trace_id = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        parsed
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64)
+}
+
+fn new_trace_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// A consumed post with the record it arrived in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope<T> {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<String>,
+    pub headers: PostHeaders,
+    pub message: T,
+}
+
+#[cfg(test)]
+mod test {
+    use super::PostHeaders;
+
+    #[test]
+    fn headers_round_trip_through_kafka() {
+        let headers = PostHeaders {
+            feeder_instance: Some("feeder-0".to_string()),
+            schema_id: Some(7),
+            content_type: Some(super::POST_CONTENT_TYPE.to_string()),
+            ..PostHeaders::ingested("mastodon")
+        };
+        assert_eq!(headers.trace_id.as_ref().unwrap().len(), 32);
+        assert_eq!(PostHeaders::from_kafka(&headers.to_kafka()), headers);
+        assert_eq!(
+            PostHeaders::from_kafka(&PostHeaders::default().to_kafka()),
+            PostHeaders::default()
+        );
+    }
+}
@@ -17,6 +17,9 @@ pub enum Error {
     #[error(transparent)]
     ServiceRegistry(#[from] SRCError),
 
+    #[error("Capture error: {0}")]
+    Capture(String),
+
     #[error("{0}")]
     Generic(String),
 }
@@ -0,0 +1,84 @@
+//! Per-partition consumer lag, taken from librdkafka's periodic statistics.
+
+use rdkafka::statistics::Statistics;
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+
+/// How often librdkafka reports statistics, and so how fresh lag is.
+pub const STATISTICS_INTERVAL_MS: u32 = 5000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PartitionLag {
+    pub topic: String,
+    pub partition: i32,
+    /// Next offset to read according to the group's last commit.
+    pub committed: Option<i64>,
+    /// Next offset the application will be handed.
+    pub position: Option<i64>,
+    pub high_watermark: Option<i64>,
+    /// Records between the committed offset and the high watermark.
+    pub lag: Option<i64>,
+}
+
+/// Lag of every partition assigned to a consumer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LagReport {
+    pub partitions: Vec<PartitionLag>,
+    /// Sum of the known per-partition lags.
+    pub total_lag: i64,
+}
+
+impl LagReport {
+    pub fn new(mut partitions: Vec<PartitionLag>) -> Self {
+        partitions.sort_by(|a, b| (&a.topic, a.partition).cmp(&(&b.topic, b.partition)));
+        let total_lag = partitions.iter().filter_map(|p| p.lag).sum();
+        LagReport {
+            partitions,
+            total_lag,
+        }
+    }
+
+    fn from_statistics(statistics: &Statistics) -> Self {
+        let known = |offset: i64| (offset >= 0).then_some(offset);
+        let partitions = statistics
+            .topics
+            .iter()
+            .flat_map(|(topic, stats)| {
+                stats
+                    .partitions
+                    .values()
+                    // -1 is librdkafka's placeholder for unassigned messages.
+                    .filter(|p| p.partition >= 0 && p.desired)
+                    .map(move |p| {
+                        let committed = known(p.committed_offset);
+                        let high_watermark = known(p.hi_offset);
+                        PartitionLag {
+                            topic: topic.clone(),
+                            partition: p.partition,
+                            committed,
+                            position: known(p.app_offset),
+                            high_watermark,
+                            lag: known(p.consumer_lag)
+                                .or_else(|| Some((high_watermark? - committed?).max(0))),
+                        }
+                    })
+            })
+            .collect();
+        LagReport::new(partitions)
+    }
+}
+
+/// The latest `LagReport` of a consumer, readable from other tasks.
+#[derive(Debug, Clone, Default)]
+pub struct LagMonitor(Arc<RwLock<LagReport>>);
+
+impl LagMonitor {
+    /// Empty until the consumer's first statistics arrive.
+    pub fn report(&self) -> LagReport {
+        self.0.read().expect("lag lock").clone()
+    }
+
+    pub(crate) fn update(&self, statistics: &Statistics) {
+        *self.0.write().expect("lag lock") = LagReport::from_statistics(statistics);
+    }
+}
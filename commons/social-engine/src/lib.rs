@@ -1,6 +1,12 @@
+pub mod capture;
 pub mod engine;
+pub mod envelope;
 pub mod error;
+pub mod lag;
 pub mod queue;
+pub mod seek;
+pub mod spool;
+pub mod stage;
 
 use prost::Message;
 use queue::FeederQueue;
@@ -8,5 +14,10 @@ use std::fmt::Debug;
 
 pub trait SocialFeeder {
     type Message: Message + Debug;
-    fn stream(self, queue: FeederQueue<Self::Message>) -> impl Future<Output = ()>;
+    /// Feeds `queue` until the source ends. An error says why it stopped,
+    /// so supervisors can report the cause rather than just the exit.
+    fn stream(
+        self,
+        queue: FeederQueue<Self::Message>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
 }
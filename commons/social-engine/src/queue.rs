@@ -1,29 +1,568 @@
 use crate::error::Error;
-use std::fmt::Debug;
-use tokio::sync::mpsc::Sender;
-use tracing::instrument;
+use prost::Message as ProstMessage;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug},
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        Notify,
+        mpsc::error::{SendTimeoutError, TrySendError},
+    },
+    time::{Instant, sleep_until},
+};
+use tracing::{instrument, warn};
 
-#[derive(Debug, Clone)]
+/// What `FeederQueue::send` does when the queue is full.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wait for room, stalling the feeder and its upstream connection.
+    Block,
+    /// Evict the oldest queued message to make room.
+    DropOldest,
+    /// Discard the message being sent.
+    DropNewest,
+    /// Append to a file and hand messages back once the queue drains,
+    /// keeping their order.
+    Spill(PathBuf),
+}
+
+impl FromStr for Overflow {
+    type Err = Error;
+
+    /// `block`, `drop-oldest`, `drop-newest` or `spill:<path>`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "block" => Ok(Overflow::Block),
+            "drop-oldest" => Ok(Overflow::DropOldest),
+            "drop-newest" => Ok(Overflow::DropNewest),
+            _ => match value.strip_prefix("spill:") {
+                Some(path) if !path.is_empty() => Ok(Overflow::Spill(PathBuf::from(path))),
+                _ => Err(Error::Generic(format!(
+                    "unknown overflow policy `{value}`, expected `block`, `drop-oldest`, \
+                     `drop-newest` or `spill:<path>`"
+                ))),
+            },
+        }
+    }
+}
+
+/// Point-in-time counters for a queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct QueueStats {
+    pub capacity: usize,
+    /// Messages held in memory.
+    pub queued: usize,
+    /// Messages waiting in the spill file.
+    pub spilled_pending: u64,
+    /// Messages discarded by `DropOldest`, `DropNewest` or a failed spill.
+    pub dropped: u64,
+    /// Messages ever written to the spill file.
+    pub spilled: u64,
+}
+
+/// Length-delimited messages appended at the end of a file and read from the
+/// front. The file is truncated whenever it has been read to the end.
+struct Spill<T> {
+    file: File,
+    read_pos: u64,
+    write_pos: u64,
+    pending: u64,
+    encode: fn(&T) -> Vec<u8>,
+    decode: fn(&[u8]) -> Option<T>,
+}
+
+impl<T> Spill<T> {
+    fn push(&mut self, message: &T) -> std::io::Result<()> {
+        let bytes = (self.encode)(message);
+        self.file.seek(SeekFrom::Start(self.write_pos))?;
+        self.file.write_all(&(bytes.len() as u64).to_le_bytes())?;
+        self.file.write_all(&bytes)?;
+        self.write_pos += 8 + bytes.len() as u64;
+        self.pending += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        while self.pending > 0 {
+            let read = (|| -> std::io::Result<Vec<u8>> {
+                self.file.seek(SeekFrom::Start(self.read_pos))?;
+                let mut len = [0u8; 8];
+                self.file.read_exact(&mut len)?;
+                let mut bytes = vec![0u8; u64::from_le_bytes(len) as usize];
+                self.file.read_exact(&mut bytes)?;
+                Ok(bytes)
+            })();
+            self.pending -= 1;
+            let message = match read {
+                Ok(bytes) => {
+                    self.read_pos += 8 + bytes.len() as u64;
+                    (self.decode)(&bytes)
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to read spilled message, discarding the spill: {}",
+                        e
+                    );
+                    self.pending = 0;
+                    None
+                }
+            };
+            if self.pending == 0 {
+                self.reset();
+            }
+            if message.is_some() {
+                return message;
+            }
+        }
+        None
+    }
+
+    fn reset(&mut self) {
+        self.read_pos = 0;
+        self.write_pos = 0;
+        if let Err(e) = self.file.set_len(0) {
+            warn!("Failed to truncate spill file: {}", e);
+        }
+    }
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    spill: Option<Spill<T>>,
+    receiver_closed: bool,
+}
+
+impl<T> State<T> {
+    fn spilled_pending(&self) -> u64 {
+        self.spill.as_ref().map_or(0, |spill| spill.pending)
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.items
+            .pop_front()
+            .or_else(|| self.spill.as_mut().and_then(Spill::pop))
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    overflow: Overflow,
+    readable: Notify,
+    writable: Notify,
+    senders: AtomicUsize,
+    dropped: AtomicU64,
+    spilled: AtomicU64,
+}
+
+impl<T> Shared<T> {
+    fn stats(&self) -> QueueStats {
+        let state = self.state.lock().unwrap();
+        QueueStats {
+            capacity: self.capacity,
+            queued: state.items.len(),
+            spilled_pending: state.spilled_pending(),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            spilled: self.spilled.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Sending half handed to feeders. Clones share one bounded queue; the
+/// receiver sees the end of the stream once every clone is dropped.
 pub struct FeederQueue<T>
 where
     T: Debug,
 {
-    tx: Sender<T>,
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Debug> Debug for FeederQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FeederQueue")
+            .field("overflow", &self.shared.overflow)
+            .field("stats", &self.shared.stats())
+            .finish()
+    }
+}
+
+impl<T: Debug> Clone for FeederQueue<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        FeederQueue {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: Debug> Drop for FeederQueue<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.readable.notify_one();
+        }
+    }
 }
 
 impl<T> FeederQueue<T>
 where
     T: Debug,
 {
-    pub fn create(tx: Sender<T>) -> Self {
-        Self { tx }
+    /// A queue holding up to `capacity` messages in memory. `Overflow::Spill`
+    /// needs messages that can be encoded; use `spilling` for it.
+    pub fn bounded(capacity: usize, overflow: Overflow) -> (Self, FeederReceiver<T>) {
+        assert!(
+            !matches!(overflow, Overflow::Spill(_)),
+            "use FeederQueue::spilling for Overflow::Spill"
+        );
+        Self::with_spill(capacity, overflow, None)
+    }
+
+    fn with_spill(
+        capacity: usize,
+        overflow: Overflow,
+        spill: Option<Spill<T>>,
+    ) -> (Self, FeederReceiver<T>) {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                items: VecDeque::new(),
+                spill,
+                receiver_closed: false,
+            }),
+            capacity: capacity.max(1),
+            overflow,
+            readable: Notify::new(),
+            writable: Notify::new(),
+            senders: AtomicUsize::new(1),
+            dropped: AtomicU64::new(0),
+            spilled: AtomicU64::new(0),
+        });
+        (
+            FeederQueue {
+                shared: shared.clone(),
+            },
+            FeederReceiver { shared },
+        )
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.shared.stats()
+    }
+
+    /// A handle for reading the counters that does not keep the queue open.
+    pub fn monitor(&self) -> QueueMonitor
+    where
+        T: Send + 'static,
+    {
+        QueueMonitor(self.shared.clone())
     }
 
+    /// Sends according to the queue's `Overflow` policy. Only `Block` waits.
     #[instrument(level = "info", err)]
     pub async fn send(&self, message: T) -> Result<(), Error> {
-        self.tx
-            .send(message)
+        let closed = || Error::FeederSend("receiver dropped".to_string());
+        let message = match self.shared.overflow {
+            Overflow::Block => {
+                return self.send_until(message, None).await.map_err(|_| closed());
+            }
+            _ => match self.try_send(message) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Closed(_)) => return Err(closed()),
+                Err(TrySendError::Full(message)) => message,
+            },
+        };
+        let mut state = self.shared.state.lock().unwrap();
+        match &self.shared.overflow {
+            Overflow::DropOldest => {
+                state.items.pop_front();
+                state.items.push_back(message);
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Overflow::Spill(_) => {
+                let spill = state.spill.as_mut().expect("spilling queues have a spill");
+                match spill.push(&message) {
+                    Ok(()) => {
+                        self.shared.spilled.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        warn!("Failed to spill message, dropping it: {}", e);
+                        self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            Overflow::DropNewest | Overflow::Block => {
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        drop(state);
+        self.shared.readable.notify_one();
+        Ok(())
+    }
+
+    /// Queues `message` only if there is room right now, ignoring the
+    /// overflow policy.
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.receiver_closed {
+            return Err(TrySendError::Closed(message));
+        }
+        // Once spilling, new messages queue behind the spill to keep order.
+        if state.items.len() >= self.shared.capacity || state.spilled_pending() > 0 {
+            return Err(TrySendError::Full(message));
+        }
+        state.items.push_back(message);
+        drop(state);
+        self.shared.readable.notify_one();
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for room, ignoring the overflow policy.
+    pub async fn send_timeout(
+        &self,
+        message: T,
+        timeout: Duration,
+    ) -> Result<(), SendTimeoutError<T>> {
+        self.send_until(message, Some(Instant::now() + timeout))
             .await
-            .map_err(|err| Error::FeederSend(err.to_string()))
+    }
+
+    async fn send_until(
+        &self,
+        mut message: T,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendTimeoutError<T>> {
+        loop {
+            let writable = self.shared.writable.notified();
+            message = match self.try_send(message) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Closed(message)) => {
+                    return Err(SendTimeoutError::Closed(message));
+                }
+                Err(TrySendError::Full(message)) => message,
+            };
+            match deadline {
+                Some(deadline) => tokio::select! {
+                    _ = writable => {}
+                    _ = sleep_until(deadline) => return Err(SendTimeoutError::Timeout(message)),
+                },
+                None => writable.await,
+            }
+        }
+    }
+}
+
+impl<T> FeederQueue<T>
+where
+    T: Debug + ProstMessage + Default,
+{
+    /// A queue that spills to `path` once `capacity` messages are waiting.
+    /// The file is created or truncated.
+    pub fn spilling(capacity: usize, path: &Path) -> Result<(Self, FeederReceiver<T>), Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| {
+                Error::Generic(format!("failed to open spill file {}: {e}", path.display()))
+            })?;
+        let spill = Spill {
+            file,
+            read_pos: 0,
+            write_pos: 0,
+            pending: 0,
+            encode: |message: &T| message.encode_to_vec(),
+            decode: |bytes: &[u8]| match T::decode(bytes) {
+                Ok(message) => Some(message),
+                Err(e) => {
+                    warn!("Skipping undecodable spilled message: {}", e);
+                    None
+                }
+            },
+        };
+        Ok(Self::with_spill(
+            capacity,
+            Overflow::Spill(path.to_path_buf()),
+            Some(spill),
+        ))
+    }
+
+    /// `bounded` for every policy, `spilling` for `Overflow::Spill`.
+    pub fn new(capacity: usize, overflow: Overflow) -> Result<(Self, FeederReceiver<T>), Error> {
+        match overflow {
+            Overflow::Spill(path) => Self::spilling(capacity, &path),
+            overflow => Ok(Self::bounded(capacity, overflow)),
+        }
+    }
+}
+
+/// Reads a queue's counters without holding it open.
+#[derive(Clone)]
+pub struct QueueMonitor(Arc<dyn Monitored + Send + Sync>);
+
+trait Monitored {
+    fn stats(&self) -> QueueStats;
+}
+
+impl<T: Send> Monitored for Shared<T> {
+    fn stats(&self) -> QueueStats {
+        Shared::stats(self)
+    }
+}
+
+impl QueueMonitor {
+    pub fn stats(&self) -> QueueStats {
+        self.0.stats()
+    }
+}
+
+impl Debug for QueueMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("QueueMonitor").field(&self.stats()).finish()
+    }
+}
+
+/// Receiving half, drained by the producer.
+pub struct FeederReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Debug for FeederReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FeederReceiver")
+            .field("stats", &self.shared.stats())
+            .finish()
+    }
+}
+
+impl<T> FeederReceiver<T> {
+    /// The next message, or `None` once every sender is gone and the queue,
+    /// spill included, is empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            let readable = self.shared.readable.notified();
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some(message) = state.pop() {
+                    drop(state);
+                    self.shared.writable.notify_one();
+                    return Some(message);
+                }
+                if self.shared.senders.load(Ordering::Acquire) == 0 {
+                    return None;
+                }
+            }
+            readable.await;
+        }
+    }
+}
+
+impl<T> FeederReceiver<T> {
+    /// The next message if one is ready, without waiting.
+    pub fn try_recv(&mut self) -> Option<T> {
+        let message = self.shared.state.lock().unwrap().pop();
+        if message.is_some() {
+            self.shared.writable.notify_one();
+        }
+        message
+    }
+}
+
+impl<T> Drop for FeederReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receiver_closed = true;
+        self.shared.writable.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FeederQueue, Overflow};
+    use proto_definitions::social::v1::Post;
+    use std::time::Duration;
+    use tokio::sync::mpsc::error::{SendTimeoutError, TrySendError};
+
+    fn post(id: u32) -> Post {
+        Post {
+            id: id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    async fn drain(mut rx: super::FeederReceiver<Post>) -> Vec<String> {
+        let mut ids = Vec::new();
+        while let Some(post) = rx.recv().await {
+            ids.push(post.id);
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn overflow_policies_drop_or_spill_in_order() {
+        let (queue, rx) = FeederQueue::bounded(2, Overflow::DropOldest);
+        for id in 1..=4 {
+            queue.send(post(id)).await.unwrap();
+        }
+        assert_eq!(queue.stats().dropped, 2);
+        drop(queue);
+        assert_eq!(drain(rx).await, ["3", "4"]);
+
+        let (queue, rx) = FeederQueue::bounded(2, Overflow::DropNewest);
+        for id in 1..=4 {
+            queue.send(post(id)).await.unwrap();
+        }
+        assert_eq!(queue.stats().dropped, 2);
+        drop(queue);
+        assert_eq!(drain(rx).await, ["1", "2"]);
+
+        let path = std::env::temp_dir().join(format!("feeder-spill-{}.bin", std::process::id()));
+        let (queue, mut rx) = FeederQueue::spilling(2, &path).unwrap();
+        for id in 1..=4 {
+            queue.send(post(id)).await.unwrap();
+        }
+        assert_eq!(rx.recv().await.unwrap().id, "1");
+        // Room in memory, but 5 must wait behind the spilled 3 and 4.
+        queue.send(post(5)).await.unwrap();
+        let stats = queue.stats();
+        assert_eq!(
+            (stats.spilled, stats.spilled_pending, stats.dropped),
+            (3, 3, 0)
+        );
+        drop(queue);
+        assert_eq!(drain(rx).await, ["2", "3", "4", "5"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn try_send_and_send_timeout_hand_the_message_back() {
+        let (queue, mut rx) = FeederQueue::bounded(1, Overflow::Block);
+        queue.try_send(post(1)).unwrap();
+        assert!(matches!(queue.try_send(post(2)), Err(TrySendError::Full(p)) if p.id == "2"));
+        assert!(matches!(
+            queue.send_timeout(post(2), Duration::from_secs(1)).await,
+            Err(SendTimeoutError::Timeout(p)) if p.id == "2"
+        ));
+
+        let sender = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.send(post(2)).await }
+        });
+        assert_eq!(rx.recv().await.unwrap().id, "1");
+        sender.await.unwrap().unwrap();
+        assert_eq!(rx.recv().await.unwrap().id, "2");
+
+        drop(rx);
+        assert!(queue.send(post(3)).await.is_err());
     }
 }
@@ -0,0 +1,454 @@
+//! Where a `SocialConsumer` starts reading its partitions.
+//!
+//! `SeekContext` is also where the consumer's statistics, and with them its
+//! lag, arrive.
+//!
+//! Start offsets are applied the first time a partition is assigned to the
+//! consumer, inside the rebalance callback, so they work with consumer
+//! groups and with partitions that have nothing new to read. Later
+//! assignments of the same partition resume from committed offsets.
+//!
+//! The rebalance callback runs on the consumer's polling thread, and looking
+//! offsets up by time or fetching watermarks are blocking broker round trips.
+//! All of them together are bounded by `SEEK_BUDGET`; partitions left over
+//! when it runs out still start where asked, but their catch-up is not
+//! logged.
+
+use crate::lag::LagMonitor;
+use rdkafka::{
+    ClientContext, Offset, Statistics, TopicPartitionList,
+    consumer::{BaseConsumer, Consumer, ConsumerContext, RebalanceProtocol},
+    error::KafkaResult,
+    types::RDKafkaRespErr,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info, warn};
+
+/// Total time one rebalance may spend in broker lookups.
+const SEEK_BUDGET: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StartFrom {
+    /// The group's committed offsets, or the earliest where none exist.
+    #[default]
+    Committed,
+    Earliest,
+    Latest,
+    /// The first record at or after this many milliseconds since the Unix
+    /// epoch.
+    Timestamp(i64),
+    /// Replay this far back from when the consumer is created, then keep
+    /// consuming live records.
+    Since(Duration),
+    /// Explicit `(topic, partition, offset)` positions. Partitions not
+    /// listed start from committed offsets.
+    Offsets(Vec<(String, i32, i64)>),
+}
+
+impl StartFrom {
+    /// `Since` as a `Timestamp` relative to now; other variants unchanged.
+    pub(crate) fn resolve(self) -> Self {
+        match self {
+            StartFrom::Since(window) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                StartFrom::Timestamp(now.saturating_sub(window).as_millis() as i64)
+            }
+            start => start,
+        }
+    }
+}
+
+impl FromStr for StartFrom {
+    type Err = String;
+
+    /// `committed`, `earliest`, `latest`, `timestamp:<millis>`,
+    /// `since:<duration>` such as `since:6h`, or
+    /// `offsets:<topic>:<partition>:<offset>,...`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "committed" => return Ok(StartFrom::Committed),
+            "earliest" => return Ok(StartFrom::Earliest),
+            "latest" => return Ok(StartFrom::Latest),
+            _ => {}
+        }
+        let invalid = || {
+            format!(
+                "invalid start `{value}`, expected `committed`, `earliest`, `latest`, \
+                 `timestamp:<millis>`, `since:<duration>` or \
+                 `offsets:<topic>:<partition>:<offset>,...`"
+            )
+        };
+        let (kind, arg) = value.split_once(':').ok_or_else(invalid)?;
+        match kind {
+            "timestamp" => arg.parse().map(StartFrom::Timestamp).map_err(|_| invalid()),
+            "since" => parse_duration(arg)
+                .map(StartFrom::Since)
+                .ok_or_else(invalid),
+            "offsets" => arg
+                .split(',')
+                .map(|position| {
+                    let mut parts = position.trim().rsplitn(3, ':');
+                    let offset = parts.next()?.parse().ok()?;
+                    let partition = parts.next()?.parse().ok()?;
+                    let topic = parts.next().filter(|topic| !topic.is_empty())?;
+                    Some((topic.to_string(), partition, offset))
+                })
+                .collect::<Option<Vec<_>>>()
+                .map(StartFrom::Offsets)
+                .ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A number followed by `s`, `m`, `h` or `d`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let unit = match value.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    let count: u64 = value[..value.len() - 1].parse().ok()?;
+    Some(Duration::from_secs(count.checked_mul(unit)?))
+}
+
+/// Positions partitions on first assignment and tracks how far each one has
+/// to be read before the replay is caught up.
+#[derive(Debug)]
+pub struct SeekContext {
+    start: StartFrom,
+    positioned: Mutex<HashSet<(String, i32)>>,
+    /// High watermark at assignment of every partition still replaying, by
+    /// topic and partition.
+    replaying: Mutex<HashMap<String, HashMap<i32, i64>>>,
+    /// How many partitions `replaying` holds, so live records skip the lock.
+    replay_count: AtomicUsize,
+    lag: LagMonitor,
+}
+
+impl SeekContext {
+    pub(crate) fn new(start: StartFrom) -> Self {
+        SeekContext {
+            start: start.resolve(),
+            positioned: Mutex::default(),
+            replaying: Mutex::default(),
+            replay_count: AtomicUsize::new(0),
+            lag: LagMonitor::default(),
+        }
+    }
+
+    pub(crate) fn lag_monitor(&self) -> LagMonitor {
+        self.lag.clone()
+    }
+
+    /// Notes that the record at `offset` was read; logs when that catches
+    /// the partition up with where it ended when the replay began.
+    pub(crate) fn consumed(&self, topic: &str, partition: i32, offset: i64) {
+        if self.replay_count.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let mut replaying = self.replaying.lock().expect("replay lock");
+        let Some(partitions) = replaying.get_mut(topic) else {
+            return;
+        };
+        if partitions
+            .get(&partition)
+            .is_some_and(|&high| offset + 1 >= high)
+        {
+            partitions.remove(&partition);
+            if partitions.is_empty() {
+                replaying.remove(topic);
+            }
+            info!(topic, partition, "Replay caught up, partition is live");
+            if self.replay_count.fetch_sub(1, Ordering::Release) == 1 {
+                info!("Replay finished, consuming live records");
+            }
+        }
+    }
+
+    /// Stops tracking the replay of every partition in `tpl`.
+    fn forget(&self, tpl: &TopicPartitionList) {
+        let mut replaying = self.replaying.lock().expect("replay lock");
+        for elem in tpl.elements() {
+            if let Some(partitions) = replaying.get_mut(elem.topic())
+                && partitions.remove(&elem.partition()).is_some()
+            {
+                self.replay_count.fetch_sub(1, Ordering::Release);
+                if partitions.is_empty() {
+                    replaying.remove(elem.topic());
+                }
+            }
+        }
+    }
+
+    /// Sets the start offset of every partition in `tpl` assigned for the
+    /// first time.
+    fn position(&self, consumer: &BaseConsumer<Self>, tpl: &mut TopicPartitionList) {
+        if self.start == StartFrom::Committed {
+            return;
+        }
+        let mut positioned = self.positioned.lock().expect("positioned lock");
+        let fresh: Vec<(String, i32)> = tpl
+            .elements()
+            .iter()
+            .map(|elem| (elem.topic().to_string(), elem.partition()))
+            .filter(|partition| !positioned.contains(partition))
+            .collect();
+        if fresh.is_empty() {
+            return;
+        }
+
+        let deadline = Instant::now() + SEEK_BUDGET;
+        let remaining = || deadline.saturating_duration_since(Instant::now());
+        let offsets = start_offsets(&self.start, &fresh, |query| {
+            consumer.offsets_for_times(query, remaining())
+        });
+
+        let mut replaying = self.replaying.lock().expect("replay lock");
+        for (topic, partition, offset) in offsets {
+            if let Err(e) = tpl.set_partition_offset(&topic, partition, offset) {
+                warn!(%topic, partition, "Failed to set start offset: {}", e);
+                continue;
+            }
+            info!(%topic, partition, ?offset, "Starting partition");
+            if remaining().is_zero() {
+                warn!(%topic, partition, "Out of time to fetch watermarks, not tracking replay");
+                continue;
+            }
+            if let Ok(watermarks) = consumer.fetch_watermarks(&topic, partition, remaining())
+                && let Some(high) = replay_end(offset, watermarks)
+                && replaying
+                    .entry(topic)
+                    .or_default()
+                    .insert(partition, high)
+                    .is_none()
+            {
+                self.replay_count.fetch_add(1, Ordering::Release);
+            }
+        }
+        positioned.extend(fresh);
+    }
+}
+
+/// The offset each of the `fresh` partitions starts from under `start`;
+/// partitions left out start from committed offsets. `lookup` resolves
+/// timestamps to offsets.
+fn start_offsets(
+    start: &StartFrom,
+    fresh: &[(String, i32)],
+    lookup: impl FnOnce(TopicPartitionList) -> KafkaResult<TopicPartitionList>,
+) -> Vec<(String, i32, Offset)> {
+    match start {
+        StartFrom::Committed | StartFrom::Since(_) => Vec::new(),
+        StartFrom::Earliest => fresh
+            .iter()
+            .map(|(topic, partition)| (topic.clone(), *partition, Offset::Beginning))
+            .collect(),
+        StartFrom::Latest => fresh
+            .iter()
+            .map(|(topic, partition)| (topic.clone(), *partition, Offset::End))
+            .collect(),
+        StartFrom::Timestamp(millis) => {
+            let mut query = TopicPartitionList::new();
+            for (topic, partition) in fresh {
+                let _ = query.add_partition_offset(topic, *partition, Offset::Offset(*millis));
+            }
+            match lookup(query) {
+                Ok(found) => found
+                    .elements()
+                    .iter()
+                    .map(|elem| (elem.topic().to_string(), elem.partition(), elem.offset()))
+                    .collect(),
+                Err(e) => {
+                    error!(
+                        "Failed to look up offsets by time, using committed ones: {}",
+                        e
+                    );
+                    Vec::new()
+                }
+            }
+        }
+        StartFrom::Offsets(explicit) => explicit
+            .iter()
+            .filter(|(topic, partition, _)| {
+                fresh.iter().any(|(fresh_topic, fresh_partition)| {
+                    fresh_topic == topic && fresh_partition == partition
+                })
+            })
+            .map(|(topic, partition, offset)| (topic.clone(), *partition, Offset::Offset(*offset)))
+            .collect(),
+    }
+}
+
+/// The high watermark a partition starting at `offset` has to be read up
+/// to, or `None` when there is nothing to replay.
+fn replay_end(offset: Offset, (low, high): (i64, i64)) -> Option<i64> {
+    let behind = match offset {
+        Offset::Beginning => low < high,
+        Offset::Offset(offset) => offset < high,
+        _ => false,
+    };
+    behind.then_some(high)
+}
+
+impl ClientContext for SeekContext {
+    fn stats(&self, statistics: Statistics) {
+        self.lag.update(&statistics);
+    }
+}
+
+impl ConsumerContext for SeekContext {
+    /// The default strategy, with start offsets filled in before assigning.
+    fn rebalance(
+        &self,
+        consumer: &BaseConsumer<Self>,
+        err: RDKafkaRespErr,
+        tpl: &mut TopicPartitionList,
+    ) {
+        let cooperative = matches!(
+            consumer.rebalance_protocol(),
+            RebalanceProtocol::Cooperative
+        );
+        let result = match err {
+            RDKafkaRespErr::RD_KAFKA_RESP_ERR__ASSIGN_PARTITIONS => {
+                self.position(consumer, tpl);
+                if cooperative {
+                    consumer.incremental_assign(tpl)
+                } else {
+                    consumer.assign(tpl)
+                }
+            }
+            RDKafkaRespErr::RD_KAFKA_RESP_ERR__REVOKE_PARTITIONS => {
+                self.forget(tpl);
+                if cooperative {
+                    consumer.incremental_unassign(tpl)
+                } else {
+                    consumer.unassign()
+                }
+            }
+            err => {
+                error!("Error rebalancing: {:?}", err);
+                consumer.unassign()
+            }
+        };
+        if let Err(e) = result {
+            error!("Failed to apply rebalance: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SeekContext, StartFrom, replay_end, start_offsets};
+    use rdkafka::{Offset, TopicPartitionList, error::KafkaError};
+    use std::{collections::HashMap, sync::atomic::Ordering, time::Duration};
+
+    #[test]
+    fn start_positions_parse() {
+        assert_eq!("latest".parse(), Ok(StartFrom::Latest));
+        assert_eq!(
+            "timestamp:1700000000000".parse(),
+            Ok(StartFrom::Timestamp(1_700_000_000_000))
+        );
+        assert_eq!(
+            "since:6h".parse(),
+            Ok(StartFrom::Since(Duration::from_secs(6 * 60 * 60)))
+        );
+        assert_eq!(
+            "offsets:raw-posts:0:1200, raw.posts:1:7".parse(),
+            Ok(StartFrom::Offsets(vec![
+                ("raw-posts".to_string(), 0, 1200),
+                ("raw.posts".to_string(), 1, 7)
+            ]))
+        );
+        assert!("since:6".parse::<StartFrom>().is_err());
+        assert!("offsets:0:1200".parse::<StartFrom>().is_err());
+
+        let StartFrom::Timestamp(millis) = StartFrom::Since(Duration::from_secs(60)).resolve()
+        else {
+            panic!("since resolves to a timestamp");
+        };
+        assert!(millis > 0);
+    }
+
+    #[test]
+    fn fresh_partitions_start_where_asked() {
+        let fresh = vec![("posts".to_string(), 0), ("posts".to_string(), 1)];
+        let no_lookup = |_| panic!("no lookup by time");
+
+        assert!(start_offsets(&StartFrom::Committed, &fresh, no_lookup).is_empty());
+        assert_eq!(
+            start_offsets(&StartFrom::Latest, &fresh, no_lookup),
+            [
+                ("posts".to_string(), 0, Offset::End),
+                ("posts".to_string(), 1, Offset::End)
+            ]
+        );
+        let explicit = StartFrom::Offsets(vec![
+            ("posts".to_string(), 1, 40),
+            ("posts".to_string(), 2, 90),
+        ]);
+        assert_eq!(
+            start_offsets(&explicit, &fresh, no_lookup),
+            [("posts".to_string(), 1, Offset::Offset(40))]
+        );
+
+        let by_time = start_offsets(&StartFrom::Timestamp(1_700_000_000_000), &fresh, |query| {
+            assert_eq!(query.count(), 2);
+            let mut found = TopicPartitionList::new();
+            found.add_partition_offset("posts", 0, Offset::Offset(12))?;
+            found.add_partition_offset("posts", 1, Offset::End)?;
+            Ok(found)
+        });
+        assert_eq!(
+            by_time,
+            [
+                ("posts".to_string(), 0, Offset::Offset(12)),
+                ("posts".to_string(), 1, Offset::End)
+            ]
+        );
+        let failed = start_offsets(&StartFrom::Timestamp(0), &fresh, |_| {
+            Err(KafkaError::Canceled)
+        });
+        assert!(failed.is_empty());
+
+        assert_eq!(replay_end(Offset::Offset(12), (0, 20)), Some(20));
+        assert_eq!(replay_end(Offset::Offset(20), (0, 20)), None);
+        assert_eq!(replay_end(Offset::Beginning, (5, 5)), None);
+        assert_eq!(replay_end(Offset::End, (0, 20)), None);
+    }
+
+    #[test]
+    fn partitions_stop_replaying_once_caught_up_or_revoked() {
+        let seek = SeekContext::new(StartFrom::Earliest);
+        seek.consumed("posts", 0, 3);
+
+        *seek.replaying.lock().unwrap() =
+            HashMap::from([("posts".to_string(), HashMap::from([(0, 10), (1, 10)]))]);
+        seek.replay_count.store(2, Ordering::Release);
+        seek.consumed("posts", 0, 8);
+        seek.consumed("other", 0, 20);
+        assert_eq!(seek.replay_count.load(Ordering::Acquire), 2);
+        seek.consumed("posts", 0, 9);
+        assert_eq!(seek.replay_count.load(Ordering::Acquire), 1);
+
+        let mut revoked = TopicPartitionList::new();
+        revoked.add_partition("posts", 1);
+        revoked.add_partition("posts", 0);
+        seek.forget(&revoked);
+        assert_eq!(seek.replay_count.load(Ordering::Acquire), 0);
+        assert!(seek.replaying.lock().unwrap().is_empty());
+    }
+}
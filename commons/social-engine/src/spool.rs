@@ -0,0 +1,493 @@
+//! Append-only disk log of records the producer could not deliver.
+//!
+//! Records are appended at the end of the log and delivered from the front.
+//! The position of the first undelivered record is kept in a sidecar
+//! `<log>.offset` file, so a restarted producer resumes where it stopped.
+//! Both files are truncated once the log has been drained.
+
+use crate::{envelope::PostHeaders, error::Error};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tracing::{info, warn};
+
+/// Lengths of the key, the headers and the value, and the timestamp, ahead
+/// of every record.
+const HEADER_BYTES: u64 = 4 + 4 + 8 + 8;
+
+/// Stands in for a record without a timestamp.
+const NO_TIMESTAMP: i64 = i64::MIN;
+
+/// Largest key or value accepted when reading the log back, to stop a
+/// corrupt length from allocating unbounded memory.
+const MAX_FIELD_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpooledRecord {
+    pub key: String,
+    /// Set when the post was taken, so a delivery from the spool carries
+    /// its original ingest time and trace id.
+    pub headers: PostHeaders,
+    /// Record timestamp in milliseconds since the Unix epoch; the producer's
+    /// clock when `None`.
+    pub timestamp: Option<i64>,
+    /// The protobuf-encoded message, before schema registry framing.
+    pub value: Vec<u8>,
+}
+
+/// Point-in-time counters for a spool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SpoolStats {
+    /// Records waiting to be delivered.
+    pub pending: u64,
+    pub pending_bytes: u64,
+    pub max_bytes: u64,
+    /// Records ever written to the log.
+    pub spooled: u64,
+    /// Records delivered from the log.
+    pub delivered: u64,
+    /// Records discarded because the log was full or unwritable.
+    pub dropped: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    pending: AtomicU64,
+    pending_bytes: AtomicU64,
+    max_bytes: AtomicU64,
+    spooled: AtomicU64,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Counters {
+    fn stats(&self) -> SpoolStats {
+        SpoolStats {
+            pending: self.pending.load(Ordering::Relaxed),
+            pending_bytes: self.pending_bytes.load(Ordering::Relaxed),
+            max_bytes: self.max_bytes.load(Ordering::Relaxed),
+            spooled: self.spooled.load(Ordering::Relaxed),
+            delivered: self.delivered.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub struct Spool {
+    path: PathBuf,
+    log: File,
+    offset: File,
+    read_pos: u64,
+    write_pos: u64,
+    pending: u64,
+    /// Records from `read_pos` on that have been read so far, each with
+    /// where the next one starts.
+    read: VecDeque<(SpooledRecord, u64)>,
+    /// Set while pushes are being dropped, to warn once per episode.
+    full: bool,
+    max_bytes: u64,
+    counters: Arc<Counters>,
+}
+
+impl fmt::Debug for Spool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spool")
+            .field("path", &self.path)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl Spool {
+    /// Opens the log at `path`, creating it if needed, and picks up any
+    /// records a previous run left behind. A record cut short by a crash is
+    /// discarded.
+    pub fn open(path: &Path, max_bytes: u64) -> Result<Self, Error> {
+        let open_error = |path: &Path, e: io::Error| {
+            Error::Generic(format!("failed to open spool {}: {e}", path.display()))
+        };
+        let offset_path = Self::offset_path(path);
+        let open = |path: &Path| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+                .map_err(|e| open_error(path, e))
+        };
+        let log = open(path)?;
+        let mut offset = open(&offset_path)?;
+
+        let mut read_pos = [0u8; 8];
+        let read_pos = match offset.read_exact(&mut read_pos) {
+            Ok(()) => u64::from_le_bytes(read_pos),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+            Err(e) => return Err(open_error(&offset_path, e)),
+        };
+
+        let mut spool = Spool {
+            path: path.to_path_buf(),
+            log,
+            offset,
+            read_pos,
+            write_pos: read_pos,
+            pending: 0,
+            read: VecDeque::new(),
+            full: false,
+            max_bytes,
+            counters: Arc::default(),
+        };
+        spool.recover().map_err(|e| open_error(path, e))?;
+        spool.counters.max_bytes.store(max_bytes, Ordering::Relaxed);
+        if spool.pending > 0 {
+            info!(
+                path = %path.display(),
+                pending = spool.pending,
+                "Resuming delivery of spooled records"
+            );
+        }
+        Ok(spool)
+    }
+
+    fn offset_path(path: &Path) -> PathBuf {
+        let mut offset = path.as_os_str().to_owned();
+        offset.push(".offset");
+        PathBuf::from(offset)
+    }
+
+    /// Counts the records after `read_pos` and cuts off a torn tail.
+    fn recover(&mut self) -> io::Result<()> {
+        let len = self.log.metadata()?.len();
+        if self.read_pos > len {
+            warn!(
+                "Spool offset is past the end of {}, discarding the spool",
+                self.path.display()
+            );
+            self.read_pos = len;
+        }
+        self.write_pos = self.read_pos;
+        while let Some(end) = self.read_at(self.write_pos)?.map(|(_, end)| end) {
+            self.write_pos = end;
+            self.pending += 1;
+        }
+        if self.write_pos < len {
+            warn!(
+                "Discarding {} bytes of incomplete records at the end of {}",
+                len - self.write_pos,
+                self.path.display()
+            );
+        }
+        if self.pending == 0 {
+            self.reset();
+        } else {
+            self.log.set_len(self.write_pos)?;
+        }
+        self.sync_counters();
+        Ok(())
+    }
+
+    /// The record starting at `pos` and the position after it, or `None`
+    /// at the end of the log or at an incomplete record.
+    fn read_at(&mut self, pos: u64) -> io::Result<Option<(SpooledRecord, u64)>> {
+        let len = self.log.metadata()?.len();
+        if pos + HEADER_BYTES > len {
+            return Ok(None);
+        }
+        self.log.seek(SeekFrom::Start(pos))?;
+        let mut key_len = [0u8; 4];
+        self.log.read_exact(&mut key_len)?;
+        let mut headers_len = [0u8; 4];
+        self.log.read_exact(&mut headers_len)?;
+        let mut value_len = [0u8; 8];
+        self.log.read_exact(&mut value_len)?;
+        let mut timestamp = [0u8; 8];
+        self.log.read_exact(&mut timestamp)?;
+        let timestamp = Some(i64::from_le_bytes(timestamp)).filter(|&ts| ts != NO_TIMESTAMP);
+        let key_len = u32::from_le_bytes(key_len) as u64;
+        let headers_len = u32::from_le_bytes(headers_len) as u64;
+        let value_len = u64::from_le_bytes(value_len);
+        if [key_len, headers_len, value_len]
+            .iter()
+            .any(|&len| len > MAX_FIELD_BYTES)
+        {
+            return Ok(None);
+        }
+        let end = pos + HEADER_BYTES + key_len + headers_len + value_len;
+        if end > len {
+            return Ok(None);
+        }
+        let mut key = vec![0u8; key_len as usize];
+        self.log.read_exact(&mut key)?;
+        let mut headers = vec![0u8; headers_len as usize];
+        self.log.read_exact(&mut headers)?;
+        let mut value = vec![0u8; value_len as usize];
+        self.log.read_exact(&mut value)?;
+        let (Ok(key), Ok(headers)) = (String::from_utf8(key), serde_json::from_slice(&headers))
+        else {
+            return Ok(None);
+        };
+        Ok(Some((
+            SpooledRecord {
+                key,
+                headers,
+                timestamp,
+                value,
+            },
+            end,
+        )))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending == 0
+    }
+
+    pub fn stats(&self) -> SpoolStats {
+        self.counters.stats()
+    }
+
+    /// A handle for reading the counters from elsewhere.
+    pub fn monitor(&self) -> SpoolMonitor {
+        SpoolMonitor(self.counters.clone())
+    }
+
+    /// Appends a record. Returns `false` and counts it as dropped when the
+    /// log would grow past `max_bytes` or cannot be written.
+    pub fn push(&mut self, record: &SpooledRecord) -> bool {
+        let SpooledRecord {
+            key,
+            headers,
+            timestamp,
+            value,
+        } = record;
+        let headers = serde_json::to_vec(headers).expect("headers serialize to JSON");
+        let size = HEADER_BYTES + key.len() as u64 + headers.len() as u64 + value.len() as u64;
+        if self.write_pos - self.read_pos + size > self.max_bytes {
+            if !self.full {
+                warn!(
+                    max_bytes = self.max_bytes,
+                    "Spool {} is full, dropping records until it drains",
+                    self.path.display()
+                );
+                self.full = true;
+            }
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let written = (|| -> io::Result<()> {
+            self.log.seek(SeekFrom::Start(self.write_pos))?;
+            self.log.write_all(&(key.len() as u32).to_le_bytes())?;
+            self.log.write_all(&(headers.len() as u32).to_le_bytes())?;
+            self.log.write_all(&(value.len() as u64).to_le_bytes())?;
+            self.log
+                .write_all(&timestamp.unwrap_or(NO_TIMESTAMP).to_le_bytes())?;
+            self.log.write_all(key.as_bytes())?;
+            self.log.write_all(&headers)?;
+            self.log.write_all(value)
+        })();
+        if let Err(e) = written {
+            warn!("Failed to spool record, dropping it: {}", e);
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        self.full = false;
+        self.write_pos += size;
+        self.pending += 1;
+        self.counters.spooled.fetch_add(1, Ordering::Relaxed);
+        self.sync_counters();
+        true
+    }
+
+    /// The oldest undelivered record. It stays in the log until `pop`.
+    pub fn front(&mut self) -> Option<&SpooledRecord> {
+        self.get(0)
+    }
+
+    /// The undelivered record `index` places behind the oldest, so several
+    /// can be in flight at once. A record that cannot be read ends the log
+    /// there until it reaches the front, where it is discarded with
+    /// everything after it.
+    pub fn get(&mut self, index: usize) -> Option<&SpooledRecord> {
+        while self.read.len() <= index && (self.read.len() as u64) < self.pending {
+            let pos = self.read.back().map_or(self.read_pos, |(_, end)| *end);
+            let at_front = self.read.is_empty();
+            match self.read_at(pos) {
+                Ok(Some(record)) => self.read.push_back(record),
+                Ok(None) | Err(_) if !at_front => return None,
+                Ok(None) => {
+                    warn!(
+                        "Spool {} is corrupt, discarding {} records",
+                        self.path.display(),
+                        self.pending
+                    );
+                    self.discard();
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to read spool {}, discarding {} records: {}",
+                        self.path.display(),
+                        self.pending,
+                        e
+                    );
+                    self.discard();
+                }
+            }
+        }
+        self.read.get(index).map(|(record, _)| record)
+    }
+
+    /// Marks the oldest record, the one returned by `front`, as delivered.
+    pub fn pop(&mut self) {
+        let Some((_, end)) = self.read.pop_front() else {
+            return;
+        };
+        self.read_pos = end;
+        self.pending -= 1;
+        self.counters.delivered.fetch_add(1, Ordering::Relaxed);
+        if self.pending == 0 {
+            self.reset();
+        } else if let Err(e) = self.save_offset() {
+            warn!("Failed to save spool offset: {}", e);
+        }
+        self.sync_counters();
+    }
+
+    fn discard(&mut self) {
+        self.counters
+            .dropped
+            .fetch_add(self.pending, Ordering::Relaxed);
+        self.reset();
+        self.sync_counters();
+    }
+
+    fn reset(&mut self) {
+        self.read_pos = 0;
+        self.write_pos = 0;
+        self.pending = 0;
+        self.read.clear();
+        if let Err(e) = self.log.set_len(0).and_then(|()| self.save_offset()) {
+            warn!("Failed to truncate spool: {}", e);
+        }
+    }
+
+    fn save_offset(&mut self) -> io::Result<()> {
+        self.offset.seek(SeekFrom::Start(0))?;
+        self.offset.write_all(&self.read_pos.to_le_bytes())
+    }
+
+    fn sync_counters(&self) {
+        self.counters.pending.store(self.pending, Ordering::Relaxed);
+        self.counters
+            .pending_bytes
+            .store(self.write_pos - self.read_pos, Ordering::Relaxed);
+    }
+}
+
+/// Read-only view of a spool's counters.
+#[derive(Debug, Clone)]
+pub struct SpoolMonitor(Arc<Counters>);
+
+impl SpoolMonitor {
+    pub fn stats(&self) -> SpoolStats {
+        self.0.stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Spool, SpooledRecord};
+    use crate::envelope::PostHeaders;
+
+    fn record(key: &str, value: &[u8]) -> SpooledRecord {
+        SpooledRecord {
+            key: key.to_string(),
+            headers: PostHeaders::default(),
+            timestamp: key.parse().ok(),
+            value: value.to_vec(),
+        }
+    }
+
+    #[test]
+    fn records_survive_a_reopen_and_drain_in_order() {
+        let path = std::env::temp_dir().join(format!("producer-spool-{}.log", std::process::id()));
+        let mut spool = Spool::open(&path, 100).unwrap();
+        assert!(spool.push(&record("1", b"one")));
+        assert!(spool.push(&record("2", b"two")));
+        assert!(spool.push(&record("3", b"three")));
+        // 3 records of 30 bytes or so fill the 100 byte budget.
+        assert!(!spool.push(&record("4", b"four-is-too-many")));
+        assert_eq!(spool.front().unwrap().key, "1");
+        spool.pop();
+        drop(spool);
+
+        let mut spool = Spool::open(&path, 100).unwrap();
+        let stats = spool.stats();
+        assert_eq!((stats.pending, stats.spooled, stats.dropped), (2, 0, 0));
+        assert!(spool.push(&record("5", b"five")));
+        let mut drained = Vec::new();
+        while let Some(record) = spool.front() {
+            drained.push(record.clone());
+            spool.pop();
+        }
+        assert_eq!(
+            drained,
+            [
+                record("2", b"two"),
+                record("3", b"three"),
+                record("5", b"five")
+            ]
+        );
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        assert_eq!(spool.stats().pending_bytes, 0);
+
+        // A record cut short by a crash is dropped on the next open.
+        assert!(spool.push(&record("6", b"six")));
+        drop(spool);
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(15).unwrap();
+        let mut spool = Spool::open(&path, 100).unwrap();
+        assert!(spool.is_empty());
+        assert!(spool.front().is_none());
+
+        drop(spool);
+        std::fs::remove_file(Spool::offset_path(&path)).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn records_behind_the_front_are_read_ahead() {
+        let path = std::env::temp_dir().join(format!("producer-ahead-{}.log", std::process::id()));
+        let mut spool = Spool::open(&path, 1024).unwrap();
+        for key in ["1", "2", "3"] {
+            assert!(spool.push(&record(key, b"value")));
+        }
+        assert_eq!(spool.get(1).unwrap().key, "2");
+        assert_eq!(spool.front().unwrap().key, "1");
+
+        // The third record is cut short behind two good ones.
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 2).unwrap();
+        assert!(spool.get(2).is_none());
+        assert!(spool.get(3).is_none());
+        assert_eq!(spool.stats().pending, 3);
+        spool.pop();
+        spool.pop();
+        assert!(spool.front().is_none());
+        let stats = spool.stats();
+        assert_eq!((stats.pending, stats.delivered, stats.dropped), (0, 2, 1));
+
+        drop(spool);
+        std::fs::remove_file(Spool::offset_path(&path)).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
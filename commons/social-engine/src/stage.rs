@@ -0,0 +1,179 @@
+use std::{
+    fmt::{self, Debug},
+    pin::Pin,
+};
+use tracing::instrument;
+
+pub type StageFuture<'a, T> = Pin<Box<dyn Future<Output = Vec<T>> + Send + 'a>>;
+
+/// One step of a processing pipeline. Returning no messages filters the input
+/// out, one message maps it and several fan it out.
+pub trait Stage<T>: Debug + Send + Sync {
+    fn name(&self) -> &str;
+    fn process(&self, message: T) -> StageFuture<'_, T>;
+}
+
+/// An ordered chain of stages, each fed the output of the previous one.
+#[derive(Debug)]
+pub struct Pipeline<T> {
+    stages: Vec<Box<dyn Stage<T>>>,
+}
+
+impl<T> Default for Pipeline<T> {
+    fn default() -> Self {
+        Pipeline { stages: Vec::new() }
+    }
+}
+
+impl<T> Pipeline<T>
+where
+    T: Send,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<S>(mut self, stage: S) -> Self
+    where
+        S: Stage<T> + 'static,
+    {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    pub fn push(&mut self, stage: Box<dyn Stage<T>>) {
+        self.stages.push(stage);
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    pub async fn process(&self, message: T) -> Vec<T> {
+        let mut messages = vec![message];
+        for stage in &self.stages {
+            let mut next = Vec::with_capacity(messages.len());
+            for message in messages {
+                next.extend(stage.process(message).await);
+            }
+            messages = next;
+            if messages.is_empty() {
+                break;
+            }
+        }
+        messages
+    }
+}
+
+/// Adapts a synchronous `T -> T` closure into a stage.
+pub struct Map<F> {
+    name: String,
+    f: F,
+}
+
+/// Adapts a synchronous `&T -> bool` closure into a stage that drops
+/// messages for which it returns `false`.
+pub struct Filter<F> {
+    name: String,
+    f: F,
+}
+
+/// Adapts a synchronous `T -> Vec<T>` closure into a stage.
+pub struct FlatMap<F> {
+    name: String,
+    f: F,
+}
+
+pub fn map<T, F>(name: &str, f: F) -> Map<F>
+where
+    F: Fn(T) -> T + Send + Sync,
+{
+    Map {
+        name: name.to_string(),
+        f,
+    }
+}
+
+pub fn filter<T, F>(name: &str, f: F) -> Filter<F>
+where
+    F: Fn(&T) -> bool + Send + Sync,
+{
+    Filter {
+        name: name.to_string(),
+        f,
+    }
+}
+
+pub fn flat_map<T, F>(name: &str, f: F) -> FlatMap<F>
+where
+    F: Fn(T) -> Vec<T> + Send + Sync,
+{
+    FlatMap {
+        name: name.to_string(),
+        f,
+    }
+}
+
+impl<F> Debug for Map<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Map").field("name", &self.name).finish()
+    }
+}
+
+impl<F> Debug for Filter<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Filter").field("name", &self.name).finish()
+    }
+}
+
+impl<F> Debug for FlatMap<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FlatMap").field("name", &self.name).finish()
+    }
+}
+
+impl<T, F> Stage<T> for Map<F>
+where
+    T: Send + 'static,
+    F: Fn(T) -> T + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process(&self, message: T) -> StageFuture<'_, T> {
+        let mapped = (self.f)(message);
+        Box::pin(async move { vec![mapped] })
+    }
+}
+
+impl<T, F> Stage<T> for Filter<F>
+where
+    T: Send + 'static,
+    F: Fn(&T) -> bool + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process(&self, message: T) -> StageFuture<'_, T> {
+        let keep = (self.f)(&message);
+        Box::pin(async move { if keep { vec![message] } else { Vec::new() } })
+    }
+}
+
+impl<T, F> Stage<T> for FlatMap<F>
+where
+    T: Send + 'static,
+    F: Fn(T) -> Vec<T> + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process(&self, message: T) -> StageFuture<'_, T> {
+        let messages = (self.f)(message);
+        Box::pin(async move { messages })
+    }
+}
@@ -0,0 +1,138 @@
+//! Pieces of the `bench` binary: a minimal Server-Sent Events decoder and
+//! latency percentiles.
+
+use std::{fmt, time::Duration};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// `None` for unnamed events, which carry post batches.
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// Splits a `text/event-stream` body into events as chunks arrive.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: String,
+}
+
+impl SseDecoder {
+    pub fn feed(&mut self, chunk: &str) -> Vec<SseEvent> {
+        self.buffer.push_str(&chunk.replace('\r', ""));
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.find("\n\n") {
+            let block: String = self.buffer.drain(..end + 2).collect();
+            let mut event = SseEvent::default();
+            let mut data = Vec::new();
+            for line in block.lines() {
+                let (field, value) = line.split_once(':').unwrap_or((line, ""));
+                let value = value.strip_prefix(' ').unwrap_or(value);
+                match field {
+                    "event" => event.event = Some(value.to_string()),
+                    "data" => data.push(value),
+                    _ => {}
+                }
+            }
+            // Comment-only blocks such as keep-alives carry no data.
+            if !data.is_empty() {
+                event.data = data.join("\n");
+                events.push(event);
+            }
+        }
+        events
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LatencyReport {
+    pub count: usize,
+    pub elapsed: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub max: Duration,
+}
+
+impl LatencyReport {
+    /// `None` when no samples were collected.
+    pub fn from_samples(mut samples: Vec<Duration>, elapsed: Duration) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        // Nearest rank, in per mille to stay clear of float rounding.
+        let percentile = |per_mille: usize| {
+            let rank = (samples.len() * per_mille).div_ceil(1000);
+            samples[rank.clamp(1, samples.len()) - 1]
+        };
+        Some(LatencyReport {
+            count: samples.len(),
+            elapsed,
+            p50: percentile(500),
+            p90: percentile(900),
+            p99: percentile(990),
+            p999: percentile(999),
+            max: samples[samples.len() - 1],
+        })
+    }
+
+    pub fn throughput(&self) -> f64 {
+        self.count as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} posts in {:.1?} ({:.1} posts/s)",
+            self.count,
+            self.elapsed,
+            self.throughput()
+        )?;
+        write!(
+            f,
+            "latency p50 {:.1?}  p90 {:.1?}  p99 {:.1?}  p99.9 {:.1?}  max {:.1?}",
+            self.p50, self.p90, self.p99, self.p999, self.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LatencyReport, SseDecoder, SseEvent};
+    use std::time::Duration;
+
+    #[test]
+    fn events_are_split_across_chunks() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.feed("data: {\"posts\"").is_empty());
+        let events = decoder.feed(": []}\r\n\r\n:keep-alive\n\nevent: trends\ndata: {}\n\n");
+        assert_eq!(
+            events,
+            [
+                SseEvent {
+                    event: None,
+                    data: "{\"posts\": []}".to_string()
+                },
+                SseEvent {
+                    event: Some("trends".to_string()),
+                    data: "{}".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let samples = (1..=1000).map(Duration::from_millis).collect();
+        let report = LatencyReport::from_samples(samples, Duration::from_secs(10)).unwrap();
+        assert_eq!(report.p50, Duration::from_millis(500));
+        assert_eq!(report.p99, Duration::from_millis(990));
+        assert_eq!(report.p999, Duration::from_millis(999));
+        assert_eq!(report.max, Duration::from_millis(1000));
+        assert_eq!(report.throughput(), 100.0);
+        assert!(LatencyReport::from_samples(Vec::new(), Duration::from_secs(1)).is_none());
+    }
+}
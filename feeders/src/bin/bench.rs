@@ -0,0 +1,91 @@
+//! Measures end-to-end latency from the `synthetic` feeder to an SSE client.
+//!
+//! Run the feeders with `FEEDER=synthetic`, then point this at the
+//! aggregator. Latency is the time between a post's emit timestamp and its
+//! arrival here, so run both on hosts with synchronised clocks.
+
+use anyhow::{Context, Result};
+use feeders::{
+    bench::{LatencyReport, SseDecoder},
+    socials::synthetic::SYNTHETIC_TAG,
+};
+use futures_util::StreamExt;
+use proto_definitions::social::v1::PostBatch;
+use std::{
+    env,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::{Instant, timeout_at};
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let url = env::var("BENCH_SSE_URL").unwrap_or_else(|_| "http://localhost:8080/sse".to_string());
+    // Unset for an aggregator running with authentication disabled.
+    let api_key = env::var("BENCH_API_KEY").ok();
+    let duration = env::var("BENCH_DURATION_SECS")
+        .unwrap_or_else(|_| "60".to_string())
+        .parse()
+        .map(Duration::from_secs)
+        .context("BENCH_DURATION_SECS must be a number of seconds")?;
+
+    let mut request = reqwest::Client::new().get(&url);
+    if let Some(api_key) = api_key {
+        request = request.header("x-api-key", api_key);
+    }
+    let response = request.send().await?.error_for_status()?;
+    info!(%url, ?duration, "Connected, measuring synthetic posts");
+
+    let start = Instant::now();
+    let deadline = start + duration;
+    let mut body = response.bytes_stream();
+    let mut decoder = SseDecoder::default();
+    let mut samples = Vec::new();
+    let mut skewed = 0usize;
+    while let Ok(Some(chunk)) = timeout_at(deadline, body.next()).await {
+        let chunk = chunk?;
+        let received = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        for event in decoder.feed(&String::from_utf8_lossy(&chunk)) {
+            if event.event.is_some() {
+                continue;
+            }
+            let batch: PostBatch = match serde_json::from_str(&event.data) {
+                Ok(batch) => batch,
+                Err(e) => {
+                    warn!("Skipping undecodable event: {}", e);
+                    continue;
+                }
+            };
+            for post in batch.posts {
+                let Some(emitted) = post
+                    .timestamp
+                    .filter(|_| post.tags.iter().any(|tag| tag == SYNTHETIC_TAG))
+                else {
+                    continue;
+                };
+                let emitted = Duration::new(emitted.seconds as u64, emitted.nanos as u32);
+                match received.checked_sub(emitted) {
+                    Some(latency) => samples.push(latency),
+                    None => skewed += 1,
+                }
+            }
+        }
+    }
+
+    if skewed > 0 {
+        warn!(
+            skewed,
+            "Posts arrived before they were emitted; check clock sync"
+        );
+    }
+    match LatencyReport::from_samples(samples, start.elapsed()) {
+        Some(report) => println!("{report}"),
+        None => println!("No synthetic posts received from {url}"),
+    }
+    Ok(())
+}
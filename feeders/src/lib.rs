@@ -1,3 +1,5 @@
+pub mod bench;
 pub mod error;
+pub mod registry;
 
 pub mod socials;
@@ -1,8 +1,15 @@
 use anyhow::Result;
-use feeders::socials::Mastodon;
-use social_engine::{SocialFeeder, engine::SocialEngineBuilder};
+use feeders::registry::{Registry, Supervision, status_router};
+use proto_definitions::PartitionKey;
+use social_engine::{
+    engine::{ProducerTuning, SocialEngineBuilder},
+    queue::Overflow,
+    spool::Spool,
+};
 use std::env;
-use tracing::{info, instrument};
+use std::{path::Path, time::Duration};
+use tokio::net::TcpListener;
+use tracing::{error, info, instrument};
 use tracing_subscriber::{
     EnvFilter, Layer,
     fmt::{self, format::FmtSpan},
@@ -22,12 +29,6 @@ async fn main() -> Result<()> {
 
     info!("🚀 Starting up the social media feeder service...");
 
-    let mastodon_url_str =
-        env::var("MASTODON_URL").expect("Missing required environment variable: MASTODON_URL");
-    let mastodon_url = Url::parse(&mastodon_url_str)?;
-    let mastodon_token = env::var("MASTODON_ACCESS_TOKEN")
-        .expect("Missing required environment variable: MASTODON_ACCESS_TOKEN");
-
     let schema_registry_url_str = env::var("SCHEMA_REGISTRY_URL")
         .expect("Missing required environment variable: SCHEMA_REGISTRY_URL");
     let schema_registry_url = Url::parse(&schema_registry_url_str)?;
@@ -41,21 +42,85 @@ async fn main() -> Result<()> {
     let kafka_password =
         env::var("KAFKA_PASSWORD").expect("Missing required environment variable: KAFKA_PASSWORD");
 
-    info!(url = %mastodon_url, "Initializing Mastodon feeder client...");
-    let mastodon_feeder = Mastodon::new(mastodon_url, mastodon_token)?;
-
     info!(brokers = %kafka_brokers, "Initializing Kafka producer with schema registry...");
+    // FEEDER_QUEUE_OVERFLOW: `block`, `drop-oldest`, `drop-newest` or `spill:<path>`
+    let queue_size = env::var("FEEDER_QUEUE_SIZE")
+        .unwrap_or_else(|_| "100".to_string())
+        .parse()?;
+    let overflow: Overflow = env::var("FEEDER_QUEUE_OVERFLOW")
+        .unwrap_or_else(|_| "block".to_string())
+        .parse()?;
+    let defaults = ProducerTuning::default();
+    let tuning = ProducerTuning {
+        max_in_flight: match env::var("PRODUCER_MAX_IN_FLIGHT") {
+            Ok(value) => value.parse()?,
+            Err(_) => defaults.max_in_flight,
+        },
+        linger: match env::var("PRODUCER_LINGER_MS") {
+            Ok(value) => Duration::from_millis(value.parse()?),
+            Err(_) => defaults.linger,
+        },
+        batch_bytes: match env::var("PRODUCER_BATCH_BYTES") {
+            Ok(value) => value.parse()?,
+            Err(_) => defaults.batch_bytes,
+        },
+        compression: env::var("PRODUCER_COMPRESSION").unwrap_or(defaults.compression),
+        idempotent: match env::var("PRODUCER_IDEMPOTENT") {
+            Ok(value) => value.parse()?,
+            Err(_) => defaults.idempotent,
+        },
+    };
     let (producer, queue) = SocialEngineBuilder::encoder(schema_registry_url)
-        .with_producer(&kafka_brokers, &kafka_username, &kafka_password)?
-        .build_multi(100);
+        .with_producer_tuned(&kafka_brokers, &kafka_username, &kafka_password, &tuning)?
+        .build_multi_with(queue_size, overflow)?;
+    let queue_monitor = queue.monitor();
+
+    // PRODUCER_SPOOL_FILE: keep records on disk while Kafka is unreachable
+    let producer = match env::var("PRODUCER_SPOOL_FILE") {
+        Ok(path) => {
+            let max_bytes = env::var("PRODUCER_SPOOL_MAX_BYTES")
+                .unwrap_or_else(|_| (1024 * 1024 * 1024).to_string())
+                .parse()?;
+            info!(%path, max_bytes, "Spooling undeliverable records to disk");
+            producer.with_spool(Spool::open(Path::new(&path), max_bytes)?)
+        }
+        Err(_) => producer,
+    };
+    let spool_monitor = producer.spool_monitor();
+    // PRODUCER_PARTITION_KEY: `id`, `service`, `author`, `instance` or `hashed-id`
+    let partition_key: PartitionKey = env::var("PRODUCER_PARTITION_KEY")
+        .unwrap_or_else(|_| "id".to_string())
+        .parse()
+        .map_err(anyhow::Error::msg)?;
+    let producer = producer.with_partition_key(partition_key);
+    // FEEDER_INSTANCE: name of this process in the `feeder-instance` header
+    let producer = match env::var("FEEDER_INSTANCE").or_else(|_| env::var("HOSTNAME")) {
+        Ok(instance) => producer.with_instance(instance),
+        Err(_) => producer,
+    };
 
     info!(topic = %kafka_topic, "Starting feeder and producer tasks. Streaming live posts...");
 
-    let producer_task = producer.run(&kafka_topic);
-    let feeder_task = mastodon_feeder.stream(queue);
+    // FEEDERS, e.g. `mastodon,ingest`
+    let enabled = env::var("FEEDERS").unwrap_or_else(|_| "mastodon".to_string());
+    let enabled: Vec<&str> = enabled.split(',').map(str::trim).collect();
+    let supervisor = Registry::builtin().supervise(&enabled, queue, Supervision::from_env()?)?;
+    info!(feeders = ?enabled, "Feeders started");
+
+    if let Ok(addr) = env::var("FEEDER_STATUS_ADDR") {
+        let listener = TcpListener::bind(&addr).await?;
+        let router = status_router(supervisor.statuses(), queue_monitor, spool_monitor);
+        info!(%addr, "Serving feeder status on GET /status");
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                error!("Status server stopped: {}", e);
+            }
+        });
+    }
 
+    let producer_task = producer.run(&kafka_topic);
     tokio::try_join!(producer_task, async {
-        feeder_task.await;
+        supervisor.wait().await;
         Ok(())
     })?;
     info!("✅ Service shutting down cleanly.");
@@ -0,0 +1,542 @@
+use crate::socials::{Ingest, Mastodon, Replay, Synthetic};
+use anyhow::{Context, bail};
+use axum::{Json, Router, extract::State as AxumState, routing::get};
+use futures_util::future::BoxFuture;
+use proto_definitions::social::v1::Post;
+use serde::Serialize;
+use social_engine::{
+    SocialFeeder,
+    queue::{FeederQueue, QueueMonitor, QueueStats},
+    spool::{SpoolMonitor, SpoolStats},
+};
+use std::{
+    collections::BTreeMap,
+    env,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    task::JoinSet,
+    time::{Instant, sleep},
+};
+use tracing::{info, warn};
+
+/// How failed feeders are restarted.
+#[derive(Debug, Clone, Copy)]
+pub struct Supervision {
+    /// First restart delay, doubled on each consecutive failure.
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+    /// A run lasting this long counts as healthy and resets the backoff.
+    pub stable_after: Duration,
+    /// Consecutive failures that open the circuit; 0 never opens it.
+    pub circuit_failures: u32,
+    /// How long an open circuit waits before the next single attempt.
+    pub circuit_cooldown: Duration,
+}
+
+impl Default for Supervision {
+    fn default() -> Self {
+        Supervision {
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            stable_after: Duration::from_secs(60),
+            circuit_failures: 5,
+            circuit_cooldown: Duration::from_secs(300),
+        }
+    }
+}
+
+impl Supervision {
+    /// Reads `FEEDER_BACKOFF_MIN_SECS`, `FEEDER_BACKOFF_MAX_SECS`,
+    /// `FEEDER_STABLE_AFTER_SECS`, `FEEDER_CIRCUIT_FAILURES` and
+    /// `FEEDER_CIRCUIT_COOLDOWN_SECS`.
+    pub fn from_env() -> anyhow::Result<Self> {
+        fn parse<T>(name: &str, default: T) -> anyhow::Result<T>
+        where
+            T: FromStr,
+            T::Err: std::error::Error + Send + Sync + 'static,
+        {
+            match env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .with_context(|| format!("{name} has an invalid value `{value}`")),
+                Err(_) => Ok(default),
+            }
+        }
+        let defaults = Supervision::default();
+        let secs =
+            |name: &str, default: Duration| parse(name, default.as_secs()).map(Duration::from_secs);
+        Ok(Supervision {
+            min_backoff: secs("FEEDER_BACKOFF_MIN_SECS", defaults.min_backoff)?,
+            max_backoff: secs("FEEDER_BACKOFF_MAX_SECS", defaults.max_backoff)?,
+            stable_after: secs("FEEDER_STABLE_AFTER_SECS", defaults.stable_after)?,
+            circuit_failures: parse("FEEDER_CIRCUIT_FAILURES", defaults.circuit_failures)?,
+            circuit_cooldown: secs("FEEDER_CIRCUIT_COOLDOWN_SECS", defaults.circuit_cooldown)?,
+        })
+    }
+}
+
+/// Consecutive failures of one feeder. Once the circuit opens, each further
+/// attempt is a single probe after the cooldown; a quick failure keeps it
+/// open, a stable run closes it.
+#[derive(Debug)]
+struct Breaker {
+    supervision: Supervision,
+    failures: u32,
+}
+
+impl Breaker {
+    fn new(supervision: Supervision) -> Self {
+        Breaker {
+            supervision,
+            failures: 0,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.supervision.circuit_failures > 0 && self.failures >= self.supervision.circuit_failures
+    }
+
+    /// Records a run that ended after `ran` and returns the wait before the
+    /// next one.
+    fn failed(&mut self, ran: Duration) -> Duration {
+        if ran >= self.supervision.stable_after {
+            self.failures = 0;
+        }
+        self.failures = self.failures.saturating_add(1);
+        if self.is_open() {
+            return self.supervision.circuit_cooldown;
+        }
+        let doublings = (self.failures - 1).min(31);
+        self.supervision
+            .min_backoff
+            .saturating_mul(1 << doublings)
+            .min(self.supervision.max_backoff)
+    }
+}
+
+/// An environment variable a feeder reads at start-up.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ConfigVar {
+    pub name: &'static str,
+    pub required: bool,
+    pub default: Option<&'static str>,
+    pub description: &'static str,
+}
+
+/// What to do when a feeder's stream returns on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restart {
+    /// Live sources: ending means the connection dropped.
+    Always,
+    /// Finite sources such as a capture: ending means the work is done.
+    Never,
+}
+
+/// A feeder the binary can start by name.
+pub trait Registered: SocialFeeder<Message = Post> + Sized + Send + 'static {
+    const NAME: &'static str;
+    const CONFIG: &'static [ConfigVar];
+    const RESTART: Restart = Restart::Always;
+
+    fn from_env() -> anyhow::Result<Self>;
+}
+
+type Start = fn(FeederQueue<Post>) -> anyhow::Result<BoxFuture<'static, anyhow::Result<()>>>;
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    name: &'static str,
+    config: &'static [ConfigVar],
+    restart: Restart,
+    start: Start,
+}
+
+fn start<F: Registered>(
+    queue: FeederQueue<Post>,
+) -> anyhow::Result<BoxFuture<'static, anyhow::Result<()>>> {
+    let missing: Vec<&str> = F::CONFIG
+        .iter()
+        .filter(|var| var.required && env::var(var.name).is_err())
+        .map(|var| var.name)
+        .collect();
+    if !missing.is_empty() {
+        bail!("`{}` needs {}", F::NAME, missing.join(", "));
+    }
+    let feeder = F::from_env().with_context(|| format!("failed to configure `{}`", F::NAME))?;
+    Ok(Box::pin(feeder.stream(queue)))
+}
+
+#[derive(Debug, Default)]
+pub struct Registry {
+    entries: Vec<Entry>,
+}
+
+impl Registry {
+    /// Every feeder shipped with this crate.
+    pub fn builtin() -> Self {
+        Registry::default()
+            .register::<Mastodon>()
+            .register::<Ingest>()
+            .register::<Replay>()
+            .register::<Synthetic>()
+    }
+
+    pub fn register<F: Registered>(mut self) -> Self {
+        self.entries.push(Entry {
+            name: F::NAME,
+            config: F::CONFIG,
+            restart: F::RESTART,
+            start: start::<F>,
+        });
+        self
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.entries.iter().map(|entry| entry.name)
+    }
+
+    pub fn config(&self, name: &str) -> Option<&'static [ConfigVar]> {
+        self.entry(name).map(|entry| entry.config)
+    }
+
+    fn entry(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Starts each of `enabled` as its own task feeding `queue`. Every feeder
+    /// is configured up front, so a missing variable fails here rather than
+    /// in a restart loop.
+    pub fn supervise(
+        &self,
+        enabled: &[&str],
+        queue: FeederQueue<Post>,
+        supervision: Supervision,
+    ) -> anyhow::Result<Supervisor> {
+        if enabled.is_empty() {
+            bail!("no feeders enabled");
+        }
+        let mut first_runs = Vec::with_capacity(enabled.len());
+        for name in enabled {
+            let entry = *self.entry(name).with_context(|| {
+                let known: Vec<_> = self.names().collect();
+                format!(
+                    "unknown feeder `{name}`, expected one of {}",
+                    known.join(", ")
+                )
+            })?;
+            first_runs.push((entry, (entry.start)(queue.clone())?));
+        }
+
+        let statuses = Statuses::default();
+        let mut tasks = JoinSet::new();
+        for (entry, first_run) in first_runs {
+            statuses.update(entry.name, |status| status.state = State::Starting);
+            tasks.spawn(run(
+                entry,
+                first_run,
+                queue.clone(),
+                statuses.clone(),
+                Breaker::new(supervision),
+            ));
+        }
+        Ok(Supervisor { statuses, tasks })
+    }
+}
+
+/// Runs one feeder until it finishes for good. Panics and unexpected exits
+/// restart only this feeder; the shared queue, and with it the producer,
+/// stays open throughout.
+async fn run(
+    entry: Entry,
+    first_run: BoxFuture<'static, anyhow::Result<()>>,
+    queue: FeederQueue<Post>,
+    statuses: Statuses,
+    mut breaker: Breaker,
+) {
+    let mut next_run = Some(first_run);
+    loop {
+        let started_at = Instant::now();
+        let started = match next_run.take() {
+            Some(stream) => Ok(stream),
+            None => (entry.start)(queue.clone()),
+        };
+        let error = match started {
+            Ok(stream) => {
+                statuses.update(entry.name, |status| status.state = State::Running);
+                match tokio::spawn(stream).await {
+                    Ok(Ok(())) if entry.restart == Restart::Never => {
+                        statuses.update(entry.name, |status| status.state = State::Finished);
+                        return;
+                    }
+                    Ok(Ok(())) => "stream ended".to_string(),
+                    Ok(Err(e)) => format!("{e:#}"),
+                    Err(e) => format!("feeder task failed: {e}"),
+                }
+            }
+            Err(e) => format!("{e:#}"),
+        };
+        let delay = breaker.failed(started_at.elapsed());
+        let state = if breaker.is_open() {
+            State::CircuitOpen
+        } else {
+            State::Restarting
+        };
+        warn!(
+            feeder = entry.name,
+            failures = breaker.failures,
+            ?state,
+            "Restarting in {:?}: {}",
+            delay,
+            error
+        );
+        statuses.update(entry.name, |status| {
+            status.state = state;
+            status.restarts += 1;
+            status.consecutive_failures = breaker.failures;
+            status.last_error = Some(error);
+            status.retry_at = Some(status.since + delay.as_secs());
+        });
+        sleep(delay).await;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    #[default]
+    Starting,
+    Running,
+    /// Waiting out the backoff before the next attempt.
+    Restarting,
+    /// Failing persistently; retried once per cooldown.
+    CircuitOpen,
+    Finished,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FeederStatus {
+    pub state: State,
+    pub restarts: u32,
+    /// Failures since the last stable run.
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    /// When `state` was entered, in seconds since the Unix epoch.
+    pub since: u64,
+    /// When the next attempt is due while restarting.
+    pub retry_at: Option<u64>,
+}
+
+/// Shared, per-feeder status.
+#[derive(Debug, Clone, Default)]
+pub struct Statuses(Arc<RwLock<BTreeMap<&'static str, FeederStatus>>>);
+
+impl Statuses {
+    pub fn snapshot(&self) -> BTreeMap<&'static str, FeederStatus> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn update(&self, name: &'static str, change: impl FnOnce(&mut FeederStatus)) {
+        let mut statuses = self.0.write().unwrap();
+        let status = statuses.entry(name).or_default();
+        status.since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        status.retry_at = None;
+        change(status);
+        info!(feeder = name, state = ?status.state, restarts = status.restarts, "feeder status");
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    feeders: BTreeMap<&'static str, FeederStatus>,
+    queue: QueueStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spool: Option<SpoolStats>,
+}
+
+#[derive(Debug, Clone)]
+struct StatusState {
+    statuses: Statuses,
+    queue: QueueMonitor,
+    spool: Option<SpoolMonitor>,
+}
+
+/// `GET /status` with every feeder's `FeederStatus`, keyed by name, the
+/// shared queue's counters and, when the producer spools, the spool's.
+pub fn status_router(
+    statuses: Statuses,
+    queue: QueueMonitor,
+    spool: Option<SpoolMonitor>,
+) -> Router {
+    Router::new()
+        .route(
+            "/status",
+            get(|AxumState(state): AxumState<StatusState>| async move {
+                Json(StatusResponse {
+                    feeders: state.statuses.snapshot(),
+                    queue: state.queue.stats(),
+                    spool: state.spool.as_ref().map(SpoolMonitor::stats),
+                })
+            }),
+        )
+        .with_state(StatusState {
+            statuses,
+            queue,
+            spool,
+        })
+}
+
+#[derive(Debug)]
+pub struct Supervisor {
+    statuses: Statuses,
+    tasks: JoinSet<()>,
+}
+
+impl Supervisor {
+    pub fn statuses(&self) -> Statuses {
+        self.statuses.clone()
+    }
+
+    /// Resolves once every feeder has finished for good.
+    pub async fn wait(mut self) {
+        while self.tasks.join_next().await.is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Breaker, Registered, Registry, Restart, State, Supervision};
+    use proto_definitions::social::v1::Post;
+    use social_engine::{
+        SocialFeeder,
+        queue::{FeederQueue, Overflow},
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    static CRASHES: AtomicUsize = AtomicUsize::new(0);
+
+    /// Panics on its first run, then sends one post.
+    struct Flaky;
+
+    impl SocialFeeder for Flaky {
+        type Message = Post;
+
+        async fn stream(self, queue: FeederQueue<Post>) -> anyhow::Result<()> {
+            if CRASHES.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first run crashes");
+            }
+            queue.send(Post::default()).await.unwrap();
+            Ok(())
+        }
+    }
+
+    impl Registered for Flaky {
+        const NAME: &'static str = "flaky";
+        const CONFIG: &'static [super::ConfigVar] = &[];
+        const RESTART: Restart = Restart::Never;
+
+        fn from_env() -> anyhow::Result<Self> {
+            Ok(Flaky)
+        }
+    }
+
+    static CLOSES: AtomicUsize = AtomicUsize::new(0);
+
+    /// Fails with a cause on its first run, then finishes.
+    struct Closing;
+
+    impl SocialFeeder for Closing {
+        type Message = Post;
+
+        async fn stream(self, _queue: FeederQueue<Post>) -> anyhow::Result<()> {
+            if CLOSES.fetch_add(1, Ordering::SeqCst) == 0 {
+                anyhow::bail!("connection closed with code 1011");
+            }
+            Ok(())
+        }
+    }
+
+    impl Registered for Closing {
+        const NAME: &'static str = "closing";
+        const CONFIG: &'static [super::ConfigVar] = &[];
+        const RESTART: Restart = Restart::Never;
+
+        fn from_env() -> anyhow::Result<Self> {
+            Ok(Closing)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_cause_of_a_failed_run_is_reported() {
+        let registry = Registry::default().register::<Closing>();
+        let (queue, _rx) = FeederQueue::bounded(1, Overflow::Block);
+        let supervisor = registry
+            .supervise(&["closing"], queue, Supervision::default())
+            .unwrap();
+        let statuses = supervisor.statuses();
+        supervisor.wait().await;
+        let status = &statuses.snapshot()["closing"];
+        assert_eq!(status.state, State::Finished);
+        assert_eq!(status.restarts, 1);
+        assert_eq!(
+            status.last_error.as_deref(),
+            Some("connection closed with code 1011")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_crashed_feeder_is_restarted_on_its_own() {
+        let registry = Registry::builtin().register::<Flaky>();
+        assert!(registry.names().any(|name| name == "mastodon"));
+        assert!(registry.config("replay").is_some());
+
+        let (queue, mut rx) = FeederQueue::bounded(1, Overflow::Block);
+        assert!(
+            registry
+                .supervise(&["myspace"], queue.clone(), Supervision::default())
+                .is_err()
+        );
+
+        let supervisor = registry
+            .supervise(&["flaky"], queue, Supervision::default())
+            .unwrap();
+        let statuses = supervisor.statuses();
+        supervisor.wait().await;
+        assert!(rx.recv().await.is_some());
+        let status = &statuses.snapshot()["flaky"];
+        assert_eq!(status.state, State::Finished);
+        assert_eq!(status.restarts, 1);
+        assert!(status.last_error.as_deref().unwrap().contains("panicked"));
+    }
+
+    #[test]
+    fn backoff_doubles_until_the_circuit_opens() {
+        let mut breaker = Breaker::new(Supervision {
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            stable_after: Duration::from_secs(60),
+            circuit_failures: 5,
+            circuit_cooldown: Duration::from_secs(300),
+        });
+        let quick = Duration::from_millis(10);
+        let delays: Vec<u64> = (0..4).map(|_| breaker.failed(quick).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 5]);
+        assert!(!breaker.is_open());
+        assert_eq!(breaker.failed(quick), Duration::from_secs(300));
+        assert!(breaker.is_open());
+        // The probe after the cooldown failed quickly: still open.
+        assert_eq!(breaker.failed(quick), Duration::from_secs(300));
+        // A stable run closes the circuit and resets the backoff.
+        assert_eq!(
+            breaker.failed(Duration::from_secs(120)),
+            Duration::from_secs(1)
+        );
+        assert!(!breaker.is_open());
+    }
+}
@@ -0,0 +1,494 @@
+use crate::{
+    error::Error,
+    registry::{ConfigVar, Registered},
+};
+use anyhow::Context;
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{DefaultBodyLimit, State},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+    },
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use chrono::{DateTime, Utc};
+use prost::Message;
+use prost_types::Timestamp;
+use proto_definitions::social::v1::{Post, PostBatch, Service};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use social_engine::{SocialFeeder, queue::FeederQueue};
+use std::{env, net::SocketAddr, sync::Arc};
+use thiserror::Error as ThisError;
+use tokio::net::TcpListener;
+use tracing::{debug, info, instrument, warn};
+use validator::{Validate, ValidationError, ValidationErrors};
+
+/// Protobuf bodies are a `PostBatch` unless the content type names
+/// `messageType=social.v1.Post`.
+pub const PROTOBUF_CONTENT_TYPES: [&str; 2] = ["application/x-protobuf", "application/protobuf"];
+const POST_MESSAGE_TYPE: &str = "social.v1.Post";
+
+#[derive(Debug, Clone)]
+pub struct IngestConfig {
+    pub addr: SocketAddr,
+    /// Expected as `Authorization: Bearer <secret>`.
+    pub secret: String,
+    pub max_body_bytes: usize,
+    pub max_batch: usize,
+}
+
+impl IngestConfig {
+    /// Reads `INGEST_ADDR` (default `0.0.0.0:8090`), `INGEST_SECRET`,
+    /// `INGEST_MAX_BODY_BYTES` (default 1 MiB) and `INGEST_MAX_BATCH`
+    /// (default 1000).
+    pub fn from_env() -> anyhow::Result<Self> {
+        let parse = |name: &str, default: usize| -> anyhow::Result<usize> {
+            match env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .with_context(|| format!("{name} has an invalid value `{value}`")),
+                Err(_) => Ok(default),
+            }
+        };
+        let addr = env::var("INGEST_ADDR").unwrap_or_else(|_| "0.0.0.0:8090".to_string());
+        Ok(IngestConfig {
+            addr: addr
+                .parse()
+                .with_context(|| format!("INGEST_ADDR has an invalid value `{addr}`"))?,
+            secret: env::var("INGEST_SECRET").unwrap_or_default(),
+            max_body_bytes: parse("INGEST_MAX_BODY_BYTES", 1024 * 1024)?,
+            max_batch: parse("INGEST_MAX_BATCH", 1000)?,
+        })
+    }
+}
+
+/// Accepts posts pushed over HTTP on `POST /ingest`, for sources that have
+/// no streaming API of their own.
+#[derive(Debug, Clone)]
+pub struct Ingest {
+    config: Arc<IngestConfig>,
+}
+
+impl Ingest {
+    #[instrument(level = "debug", skip(config), fields(addr = %config.addr), err)]
+    pub fn new(config: IngestConfig) -> Result<Self, Error> {
+        if config.secret.is_empty() {
+            warn!("Attempted to start the ingest server without a shared secret.");
+            return Err(Error::EmptyAccessToken {
+                service: "ingest".to_string(),
+            });
+        }
+        Ok(Ingest {
+            config: Arc::new(config),
+        })
+    }
+
+    pub fn router(&self, queue: FeederQueue<Post>) -> Router {
+        Router::new()
+            .route("/ingest", post(ingest))
+            .layer(DefaultBodyLimit::max(self.config.max_body_bytes))
+            .with_state(IngestState {
+                config: self.config.clone(),
+                queue,
+            })
+    }
+}
+
+impl Registered for Ingest {
+    const NAME: &'static str = "ingest";
+    const CONFIG: &'static [ConfigVar] = &[
+        ConfigVar {
+            name: "INGEST_ADDR",
+            required: false,
+            default: Some("0.0.0.0:8090"),
+            description: "Address the HTTP server listens on.",
+        },
+        ConfigVar {
+            name: "INGEST_SECRET",
+            required: true,
+            default: None,
+            description: "Shared secret expected as `Authorization: Bearer <secret>`.",
+        },
+        ConfigVar {
+            name: "INGEST_MAX_BODY_BYTES",
+            required: false,
+            default: Some("1048576"),
+            description: "Largest request body accepted.",
+        },
+        ConfigVar {
+            name: "INGEST_MAX_BATCH",
+            required: false,
+            default: Some("1000"),
+            description: "Most posts accepted per request.",
+        },
+    ];
+
+    fn from_env() -> anyhow::Result<Self> {
+        Ok(Ingest::new(IngestConfig::from_env()?)?)
+    }
+}
+
+impl SocialFeeder for Ingest {
+    type Message = Post;
+
+    #[instrument(level = "debug")]
+    async fn stream(self, queue: FeederQueue<Self::Message>) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(self.config.addr)
+            .await
+            .with_context(|| format!("failed to bind ingest server to {}", self.config.addr))?;
+        info!(addr = %self.config.addr, "Accepting posts on POST /ingest");
+        axum::serve(listener, self.router(queue))
+            .await
+            .context("ingest server stopped")
+    }
+}
+
+#[derive(Debug, Clone)]
+struct IngestState {
+    config: Arc<IngestConfig>,
+    queue: FeederQueue<Post>,
+}
+
+#[derive(Debug, ThisError)]
+pub enum IngestError {
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Invalid body: {0}")]
+    BadRequest(String),
+    #[error("Validation Error: {0}")]
+    Validation(#[from] ValidationErrors),
+    #[error("Unsupported content type `{0}`, expected application/json or application/x-protobuf")]
+    UnsupportedMediaType(String),
+    #[error("Feeder queue is closed")]
+    Unavailable,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+impl IntoResponse for IngestError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::BadRequest(_) | Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        let mut response = (
+            status,
+            Json(ErrorResponse {
+                message: self.to_string(),
+            }),
+        )
+            .into_response();
+        if status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+/// A post as accepted over HTTP. Protobuf posts are converted to this shape
+/// too, so both encodings go through the same validation.
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct IngestPost {
+    #[validate(length(min = 1, max = 256))]
+    pub id: String,
+    #[validate(custom(function = "validate_service"))]
+    pub service: String,
+    /// RFC 3339; the time of receipt when missing.
+    pub timestamp: Option<DateTime<Utc>>,
+    #[validate(length(min = 1, max = 65536))]
+    pub content: String,
+    #[serde(default)]
+    #[validate(url)]
+    pub uri: Option<String>,
+    #[serde(default)]
+    #[validate(url)]
+    pub reblog_of: Option<String>,
+    #[serde(default)]
+    #[validate(length(max = 256))]
+    pub author: String,
+    #[serde(default)]
+    pub sensitive: bool,
+    #[serde(default)]
+    #[validate(length(max = 64))]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct IngestBatch {
+    #[validate(length(min = 1), nested)]
+    pub posts: Vec<IngestPost>,
+}
+
+/// A JSON body is either a single post or `{"posts": [...]}`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonBody {
+    Batch(IngestBatch),
+    Post(Box<IngestPost>),
+}
+
+fn validate_service(service: &str) -> Result<(), ValidationError> {
+    // Exhaustive, so a placeholder variant added to the enum is not
+    // accepted by accident.
+    match Service::from_str_name(&service.to_uppercase()) {
+        Some(Service::Mastodon | Service::X) => Ok(()),
+        None => Err(ValidationError::new("unknown_service")),
+    }
+}
+
+impl From<Post> for IngestPost {
+    fn from(post: Post) -> Self {
+        let service = Service::try_from(post.service)
+            .map(|service| service.as_str_name().to_lowercase())
+            .unwrap_or_default();
+        let non_empty = |value: String| (!value.is_empty()).then_some(value);
+        IngestPost {
+            id: post.id,
+            service,
+            timestamp: post
+                .timestamp
+                .and_then(|ts| DateTime::from_timestamp(ts.seconds, ts.nanos.max(0) as u32)),
+            content: post.content,
+            uri: non_empty(post.uri),
+            reblog_of: non_empty(post.reblog_of),
+            author: post.author,
+            sensitive: post.sensitive,
+            tags: post.tags,
+        }
+    }
+}
+
+impl From<IngestPost> for Post {
+    fn from(post: IngestPost) -> Self {
+        let service = Service::from_str_name(&post.service.to_uppercase()).unwrap_or_default();
+        let timestamp = post.timestamp.unwrap_or_else(Utc::now);
+        let mut post = Post {
+            id: post.id,
+            service: service as i32,
+            timestamp: Some(Timestamp {
+                seconds: timestamp.timestamp(),
+                nanos: timestamp.timestamp_subsec_nanos() as i32,
+            }),
+            content: post.content,
+            uri: post.uri.unwrap_or_default(),
+            reblog_of: post.reblog_of.unwrap_or_default(),
+            author: post.author,
+            sensitive: post.sensitive,
+            tags: post.tags,
+            ..Default::default()
+        };
+        social_content::render(&mut post);
+        post
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Accepted {
+    accepted: usize,
+}
+
+/// Compares SHA-256 digests in constant time, so timings give away neither
+/// the secret's bytes nor its length.
+fn secret_matches(expected: &str, given: &str) -> bool {
+    Sha256::digest(expected)
+        .iter()
+        .zip(Sha256::digest(given).iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+fn authorize(headers: &HeaderMap, secret: &str) -> Result<(), IngestError> {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| IngestError::Unauthorized("missing bearer token".to_string()))?;
+    if !secret_matches(secret, token) {
+        return Err(IngestError::Unauthorized(
+            "invalid ingest secret".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Decodes the body according to its content type.
+fn decode(headers: &HeaderMap, body: &[u8]) -> Result<Vec<IngestPost>, IngestError> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let mut params = content_type.split(';').map(str::trim);
+    let mime = params.next().unwrap_or_default().to_lowercase();
+    if mime == "application/json" {
+        let body: JsonBody =
+            serde_json::from_slice(body).map_err(|e| IngestError::BadRequest(e.to_string()))?;
+        return Ok(match body {
+            JsonBody::Batch(batch) => batch.posts,
+            JsonBody::Post(post) => vec![*post],
+        });
+    }
+    if PROTOBUF_CONTENT_TYPES.contains(&mime.as_str()) {
+        let single = params.any(|param| {
+            param.split_once('=').is_some_and(|(key, value)| {
+                key.eq_ignore_ascii_case("messageType")
+                    && value.trim_matches('"') == POST_MESSAGE_TYPE
+            })
+        });
+        let posts = if single {
+            vec![Post::decode(body).map_err(|e| IngestError::BadRequest(e.to_string()))?]
+        } else {
+            PostBatch::decode(body)
+                .map_err(|e| IngestError::BadRequest(e.to_string()))?
+                .posts
+        };
+        return Ok(posts.into_iter().map(IngestPost::from).collect());
+    }
+    Err(IngestError::UnsupportedMediaType(content_type.to_string()))
+}
+
+#[instrument(name = "ingest", level = "debug", skip_all, fields(bytes = body.len()))]
+async fn ingest(
+    State(state): State<IngestState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<Accepted>), IngestError> {
+    authorize(&headers, &state.config.secret)?;
+    let posts = decode(&headers, &body)?;
+    if posts.len() > state.config.max_batch {
+        return Err(IngestError::BadRequest(format!(
+            "{} posts exceed the batch limit of {}",
+            posts.len(),
+            state.config.max_batch
+        )));
+    }
+    let batch = IngestBatch { posts };
+    batch.validate()?;
+    let accepted = batch.posts.len();
+    for post in batch.posts {
+        state
+            .queue
+            .send(Post::from(post))
+            .await
+            .map_err(|_| IngestError::Unavailable)?;
+    }
+    debug!(accepted, "posts ingested");
+    Ok((StatusCode::ACCEPTED, Json(Accepted { accepted })))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Ingest, IngestConfig};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header},
+    };
+    use prost::Message;
+    use proto_definitions::social::v1::{Post, PostBatch, Service};
+    use social_engine::queue::{FeederQueue, FeederReceiver, Overflow};
+    use tower::ServiceExt;
+
+    fn router() -> (axum::Router, FeederReceiver<Post>) {
+        let (queue, rx) = FeederQueue::bounded(16, Overflow::Block);
+        let ingest = Ingest::new(IngestConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            secret: "s3cret".to_string(),
+            max_body_bytes: 1024 * 1024,
+            max_batch: 2,
+        })
+        .unwrap();
+        (ingest.router(queue), rx)
+    }
+
+    fn request(secret: &str, content_type: &str, body: impl Into<Body>) -> Request<Body> {
+        Request::post("/ingest")
+            .header(header::AUTHORIZATION, format!("Bearer {secret}"))
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body.into())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn json_and_protobuf_posts_reach_the_queue() {
+        let (router, mut rx) = router();
+        let json = r#"{"id": "1", "service": "mastodon", "content": "<p>hello #rust</p>",
+                       "uri": "https://example.social/@a/1", "author": "a@example.social"}"#;
+        let response = router
+            .clone()
+            .oneshot(request("s3cret", "application/json", json))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let post = rx.recv().await.unwrap();
+        assert_eq!(post.service, Service::Mastodon as i32);
+        assert_eq!(post.content_text.trim(), "hello #rust");
+        assert!(post.timestamp.is_some());
+
+        let batch = PostBatch {
+            posts: vec![Post {
+                id: "2".to_string(),
+                service: Service::X as i32,
+                content: "from protobuf".to_string(),
+                ..Default::default()
+            }],
+        };
+        let response = router
+            .oneshot(request(
+                "s3cret",
+                "application/x-protobuf",
+                batch.encode_to_vec(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(rx.recv().await.unwrap().id, "2");
+    }
+
+    #[tokio::test]
+    async fn bad_secrets_and_invalid_posts_are_rejected() {
+        let (router, mut rx) = router();
+        let post = r#"{"id": "1", "service": "mastodon", "content": "hi"}"#;
+        for secret in ["guess", "s3cre", "s3cret!"] {
+            let response = router
+                .clone()
+                .oneshot(request(secret, "application/json", post))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        for body in [
+            r#"{"id": "", "service": "mastodon", "content": "hi"}"#,
+            r#"{"id": "1", "service": "myspace", "content": "hi"}"#,
+            r#"{"id": "1", "service": "service_unspecified", "content": "hi"}"#,
+            r#"{"id": "1", "service": "x", "content": "hi", "uri": "not a url"}"#,
+            r#"{"posts": [{"id": "1", "service": "x", "content": "a"},
+                          {"id": "2", "service": "x", "content": "b"},
+                          {"id": "3", "service": "x", "content": "c"}]}"#,
+        ] {
+            let response = router
+                .clone()
+                .oneshot(request("s3cret", "application/json", body))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{body}");
+        }
+
+        let response = router
+            .oneshot(request("s3cret", "text/plain", post))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(rx.try_recv().is_none());
+    }
+}
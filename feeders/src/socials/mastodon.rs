@@ -1,8 +1,13 @@
-use crate::error::Error;
+use crate::{
+    error::Error,
+    registry::{ConfigVar, Registered},
+};
+use anyhow::{Context, bail};
 use megalodon::{Megalodon, mastodon::Mastodon as MastodonClient, streaming::Message};
 use prost_types::Timestamp;
-use proto_definitions::social::v1::{Post, Service};
+use proto_definitions::social::v1::{CustomEmoji, Post, Service};
 use social_engine::{SocialFeeder, queue::FeederQueue};
+use std::env;
 use tracing::{debug, info, instrument, warn};
 use url::Url;
 
@@ -33,11 +38,36 @@ impl Mastodon {
     }
 }
 
+impl Registered for Mastodon {
+    const NAME: &'static str = "mastodon";
+    const CONFIG: &'static [ConfigVar] = &[
+        ConfigVar {
+            name: "MASTODON_URL",
+            required: true,
+            default: None,
+            description: "Instance whose public timeline is streamed.",
+        },
+        ConfigVar {
+            name: "MASTODON_ACCESS_TOKEN",
+            required: true,
+            default: None,
+            description: "Access token with the `read` scope.",
+        },
+    ];
+
+    fn from_env() -> anyhow::Result<Self> {
+        let url = env::var("MASTODON_URL").context("MASTODON_URL must be set")?;
+        let url = Url::parse(&url).with_context(|| format!("invalid MASTODON_URL `{url}`"))?;
+        let token = env::var("MASTODON_ACCESS_TOKEN").unwrap_or_default();
+        Ok(Mastodon::new(url, token)?)
+    }
+}
+
 impl SocialFeeder for Mastodon {
     type Message = Post;
 
     #[instrument(level = "debug")]
-    async fn stream(self, queue: FeederQueue<Self::Message>) {
+    async fn stream(self, queue: FeederQueue<Self::Message>) -> anyhow::Result<()> {
         let streaming = self.client.public_streaming().await;
         streaming
             .listen(Box::new(|message| {
@@ -47,16 +77,52 @@ impl SocialFeeder for Mastodon {
                         match message {
                             Message::Update(status) | Message::StatusUpdate(status) => {
                                 debug!("receieved status form mastodon: {}", status.id);
-                                let post = Post {
+                                // Boosts carry no content of their own; take it from
+                                // the boosted status and remember what it points at.
+                                let (content, emojis, author, sensitive, reblog_of) =
+                                    match status.reblog {
+                                        Some(reblog) => (
+                                            reblog.content,
+                                            reblog.emojis,
+                                            reblog.account.acct,
+                                            reblog.sensitive,
+                                            reblog.uri,
+                                        ),
+                                        None => (
+                                            status.content,
+                                            status.emojis,
+                                            status.account.acct,
+                                            status.sensitive,
+                                            String::new(),
+                                        ),
+                                    };
+                                let emojis = emojis
+                                    .into_iter()
+                                    .map(|emoji| CustomEmoji {
+                                        shortcode: emoji.shortcode,
+                                        url: emoji.url,
+                                        static_url: emoji.static_url,
+                                    })
+                                    .collect();
+                                let mut post = Post {
                                     id: status.id,
                                     service: Service::Mastodon as i32,
                                     timestamp: Some(Timestamp {
                                         seconds: status.created_at.timestamp(),
                                         nanos: status.created_at.timestamp_subsec_nanos() as i32,
                                     }),
-                                    content: status.content,
+                                    content,
+                                    emojis,
+                                    uri: status.uri,
+                                    reblog_of,
+                                    author,
+                                    sensitive,
+                                    ..Default::default()
                                 };
-                                let _ = queue.send(post).await;
+                                social_content::render(&mut post);
+                                if let Err(e) = queue.send(post).await {
+                                    warn!("Failed to queue status: {}", e);
+                                }
                             }
                             _ => {}
                         }
@@ -64,5 +130,12 @@ impl SocialFeeder for Mastodon {
                 })
             }))
             .await;
+        // The streaming client reconnects on its own and only gives up on a
+        // normal close or a rejected token, without saying which; ask the
+        // API so the cause is reported rather than just logged.
+        match self.client.verify_account_credentials().await {
+            Ok(_) => bail!("streaming connection closed by the server"),
+            Err(e) => Err(e).context("streaming stopped, credentials check failed"),
+        }
     }
 }
@@ -1,3 +1,9 @@
+pub mod ingest;
 pub mod mastodon;
+pub mod replay;
+pub mod synthetic;
 
+pub use ingest::{Ingest, IngestConfig};
 pub use mastodon::Mastodon;
+pub use replay::{Replay, ReplayConfig};
+pub use synthetic::{Synthetic, SyntheticConfig};
@@ -0,0 +1,162 @@
+use crate::registry::{ConfigVar, Registered, Restart};
+use anyhow::Context;
+use proto_definitions::social::v1::Post;
+use social_engine::{
+    SocialFeeder,
+    capture::{CaptureReader, Format},
+    queue::FeederQueue,
+};
+use std::{env, path::PathBuf, time::Duration};
+use tokio::{
+    fs::File,
+    io::BufReader,
+    time::{Instant, sleep_until},
+};
+use tracing::{info, instrument, warn};
+
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    pub path: PathBuf,
+    /// Multiplier on the recorded inter-arrival times; `None` replays as fast
+    /// as the queue accepts posts.
+    pub speed: Option<f64>,
+}
+
+impl ReplayConfig {
+    /// Reads `REPLAY_FILE` and `REPLAY_SPEED` (default 1; 0 disables pacing).
+    pub fn from_env() -> anyhow::Result<Self> {
+        let path = env::var("REPLAY_FILE").context("REPLAY_FILE must be set for `replay`")?;
+        let speed = match env::var("REPLAY_SPEED") {
+            Ok(value) => value
+                .parse::<f64>()
+                .ok()
+                .filter(|speed| speed.is_finite() && *speed >= 0.0)
+                .with_context(|| format!("REPLAY_SPEED has an invalid value `{value}`"))?,
+            Err(_) => 1.0,
+        };
+        Ok(ReplayConfig {
+            path: PathBuf::from(path),
+            speed: (speed > 0.0).then_some(speed),
+        })
+    }
+}
+
+/// Replays a capture written by the social-consumer recorder, or any JSONL or
+/// length-delimited protobuf file of posts. With a speed, posts are spaced
+/// by the gaps between their timestamps divided by that speed.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    config: ReplayConfig,
+}
+
+impl Replay {
+    pub fn new(config: ReplayConfig) -> Self {
+        Replay { config }
+    }
+}
+
+/// Seconds since the epoch, with nanosecond precision.
+fn seconds(post: &Post) -> Option<f64> {
+    post.timestamp
+        .as_ref()
+        .map(|ts| ts.seconds as f64 + ts.nanos as f64 / 1e9)
+}
+
+/// When `post` is due, relative to the first timestamped post at `start`.
+/// Posts recorded out of order are sent right away rather than held back.
+fn due(post: &Post, first: f64, start: Instant, speed: f64) -> Instant {
+    let offset = seconds(post).map_or(0.0, |ts| (ts - first).max(0.0) / speed);
+    start + Duration::from_secs_f64(offset)
+}
+
+impl Registered for Replay {
+    const NAME: &'static str = "replay";
+    const CONFIG: &'static [ConfigVar] = &[
+        ConfigVar {
+            name: "REPLAY_FILE",
+            required: true,
+            default: None,
+            description: "Capture to replay; `.jsonl` for JSON lines, otherwise protobuf.",
+        },
+        ConfigVar {
+            name: "REPLAY_SPEED",
+            required: false,
+            default: Some("1"),
+            description: "Multiplier on recorded timing; 0 replays as fast as possible.",
+        },
+    ];
+    const RESTART: Restart = Restart::Never;
+
+    fn from_env() -> anyhow::Result<Self> {
+        Ok(Replay::new(ReplayConfig::from_env()?))
+    }
+}
+
+impl SocialFeeder for Replay {
+    type Message = Post;
+
+    #[instrument(level = "debug")]
+    async fn stream(self, queue: FeederQueue<Self::Message>) -> anyhow::Result<()> {
+        let path = &self.config.path;
+        let file = File::open(path)
+            .await
+            .with_context(|| format!("failed to open replay file {}", path.display()))?;
+        let format = Format::from_path(path);
+        info!(path = %path.display(), ?format, speed = ?self.config.speed, "Replaying capture");
+        let mut reader = CaptureReader::<_, Post>::new(BufReader::new(file), format);
+        let mut clock: Option<(f64, Instant)> = None;
+        let mut replayed = 0usize;
+        loop {
+            let post = match reader.next().await {
+                Ok(Some(post)) => post,
+                Ok(None) => break,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("replay failed after {replayed} posts"));
+                }
+            };
+            if let Some(speed) = self.config.speed {
+                match clock {
+                    Some((first, start)) => sleep_until(due(&post, first, start, speed)).await,
+                    None => clock = seconds(&post).map(|first| (first, Instant::now())),
+                }
+            }
+            if queue.send(post).await.is_err() {
+                warn!("Feeder queue closed, stopping replay");
+                break;
+            }
+            replayed += 1;
+        }
+        info!(replayed, "Replay finished");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::due;
+    use prost_types::Timestamp;
+    use proto_definitions::social::v1::Post;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[test]
+    fn posts_are_spaced_by_their_timestamps_over_speed() {
+        let at = |seconds: i64, nanos: i32| Post {
+            timestamp: Some(Timestamp { seconds, nanos }),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let first = 1_700_000_000.0;
+        assert_eq!(
+            due(&at(1_700_000_010, 0), first, start, 2.0),
+            start + Duration::from_secs(5)
+        );
+        assert_eq!(
+            due(&at(1_700_000_000, 500_000_000), first, start, 1.0),
+            start + Duration::from_millis(500)
+        );
+        // Out of order and untimestamped posts are due immediately.
+        assert_eq!(due(&at(1_699_999_990, 0), first, start, 1.0), start);
+        assert_eq!(due(&Post::default(), first, start, 1.0), start);
+    }
+}
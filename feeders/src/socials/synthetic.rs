@@ -0,0 +1,390 @@
+use crate::registry::{ConfigVar, Registered, Restart};
+use anyhow::{Context, bail};
+use prost_types::Timestamp;
+use proto_definitions::social::v1::{Post, Service};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use social_engine::{SocialFeeder, queue::FeederQueue};
+use std::{
+    env,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::{Instant, sleep_until};
+use tracing::{info, instrument, warn};
+
+/// Tag carried by every generated post, so benchmarks can tell them apart
+/// from live traffic.
+pub const SYNTHETIC_TAG: &str = "synthetic";
+
+const WORDS: &[&str] = &[
+    "release",
+    "server",
+    "music",
+    "coffee",
+    "morning",
+    "weekend",
+    "garden",
+    "update",
+    "photo",
+    "community",
+    "project",
+    "question",
+    "thread",
+    "reading",
+    "weather",
+    "train",
+    "city",
+    "today",
+    "design",
+    "open",
+    "source",
+    "game",
+    "night",
+    "friends",
+    "learning",
+    "rust",
+    "kitchen",
+    "walk",
+    "ocean",
+    "mountain",
+    "bike",
+    "library",
+    "concert",
+    "podcast",
+    "paper",
+    "data",
+    "map",
+    "film",
+    "story",
+    "market",
+];
+const INSTANCES: &[&str] = &[
+    "mastodon.social",
+    "fosstodon.org",
+    "hachyderm.io",
+    "chaos.social",
+    "infosec.exchange",
+];
+
+#[derive(Debug, Clone)]
+pub struct SyntheticConfig {
+    /// Posts per second.
+    pub rate: f64,
+    /// Stop after this many posts; unbounded when `None`.
+    pub limit: Option<u64>,
+    pub services: Vec<Service>,
+    /// Mean post length in words; lengths follow an exponential distribution.
+    pub mean_words: f64,
+    pub max_words: usize,
+    /// Size of the hashtag vocabulary.
+    pub hashtags: usize,
+    /// Zipf exponent over the vocabulary; higher concentrates on fewer tags.
+    pub hashtag_skew: f64,
+    pub max_hashtags_per_post: usize,
+    /// Distinct authors. Keep it large enough that the moderation spam guard
+    /// does not flag them at the configured rate.
+    pub authors: usize,
+    pub seed: Option<u64>,
+}
+
+impl Default for SyntheticConfig {
+    fn default() -> Self {
+        SyntheticConfig {
+            rate: 100.0,
+            limit: None,
+            services: vec![Service::Mastodon],
+            mean_words: 30.0,
+            max_words: 500,
+            hashtags: 200,
+            hashtag_skew: 1.1,
+            max_hashtags_per_post: 3,
+            authors: 10_000,
+            seed: None,
+        }
+    }
+}
+
+impl SyntheticConfig {
+    /// Reads the `SYNTHETIC_*` variables, falling back to the defaults.
+    pub fn from_env() -> anyhow::Result<Self> {
+        fn parse<T: FromStr>(name: &str, default: T) -> anyhow::Result<T> {
+            match env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .ok()
+                    .with_context(|| format!("{name} has an invalid value `{value}`")),
+                Err(_) => Ok(default),
+            }
+        }
+        let defaults = SyntheticConfig::default();
+        let services = match env::var("SYNTHETIC_SERVICES") {
+            Ok(names) => names
+                .split(',')
+                .map(|name| {
+                    Service::from_str_name(&name.trim().to_uppercase())
+                        .with_context(|| format!("unknown service `{name}` in SYNTHETIC_SERVICES"))
+                })
+                .collect::<anyhow::Result<_>>()?,
+            Err(_) => defaults.services,
+        };
+        let limit = parse("SYNTHETIC_LIMIT", 0u64)?;
+        let config = SyntheticConfig {
+            rate: parse("SYNTHETIC_RATE", defaults.rate)?,
+            limit: (limit > 0).then_some(limit),
+            services,
+            mean_words: parse("SYNTHETIC_MEAN_WORDS", defaults.mean_words)?,
+            max_words: parse("SYNTHETIC_MAX_WORDS", defaults.max_words)?,
+            hashtags: parse("SYNTHETIC_HASHTAGS", defaults.hashtags)?,
+            hashtag_skew: parse("SYNTHETIC_HASHTAG_SKEW", defaults.hashtag_skew)?,
+            max_hashtags_per_post: parse(
+                "SYNTHETIC_MAX_HASHTAGS_PER_POST",
+                defaults.max_hashtags_per_post,
+            )?,
+            authors: parse("SYNTHETIC_AUTHORS", defaults.authors)?,
+            seed: env::var("SYNTHETIC_SEED")
+                .is_ok()
+                .then(|| parse("SYNTHETIC_SEED", 0u64))
+                .transpose()?,
+        };
+        if !(config.rate.is_finite() && config.rate > 0.0) {
+            bail!("SYNTHETIC_RATE must be positive");
+        }
+        if config.services.is_empty() || config.authors == 0 || config.max_words == 0 {
+            bail!(
+                "SYNTHETIC_SERVICES, SYNTHETIC_AUTHORS and SYNTHETIC_MAX_WORDS must not be empty"
+            );
+        }
+        Ok(config)
+    }
+}
+
+/// Samples ranks `0..n` with probability proportional to `1 / (rank + 1)^s`.
+#[derive(Debug, Clone)]
+struct Zipf {
+    cumulative: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: usize, s: f64) -> Self {
+        let mut total = 0.0;
+        let cumulative = (1..=n)
+            .map(|rank| {
+                total += 1.0 / (rank as f64).powf(s);
+                total
+            })
+            .collect();
+        Zipf { cumulative }
+    }
+
+    fn sample(&self, rng: &mut impl Rng) -> Option<usize> {
+        let total = *self.cumulative.last()?;
+        let target = rng.random::<f64>() * total;
+        Some(
+            self.cumulative
+                .partition_point(|weight| *weight < target)
+                .min(self.cumulative.len() - 1),
+        )
+    }
+}
+
+/// Generates realistic-looking posts at a fixed rate for benchmarking. Each
+/// post's timestamp is the moment it was emitted, so a client at the end of
+/// the pipeline can measure end-to-end latency from it.
+#[derive(Debug)]
+pub struct Synthetic {
+    config: SyntheticConfig,
+    zipf: Zipf,
+    rng: StdRng,
+    sequence: u64,
+}
+
+impl Synthetic {
+    pub fn new(config: SyntheticConfig) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+        Synthetic {
+            zipf: Zipf::new(config.hashtags, config.hashtag_skew),
+            config,
+            rng,
+            sequence: 0,
+        }
+    }
+
+    fn words(&mut self) -> usize {
+        // Inverse transform sampling of an exponential distribution.
+        let uniform: f64 = self.rng.random_range(f64::EPSILON..1.0);
+        ((-uniform.ln() * self.config.mean_words).round() as usize).clamp(1, self.config.max_words)
+    }
+
+    pub fn next_post(&mut self) -> Post {
+        self.sequence += 1;
+        let service = self.config.services[self.rng.random_range(0..self.config.services.len())];
+        let instance = INSTANCES[self.rng.random_range(0..INSTANCES.len())];
+        let user = self.rng.random_range(0..self.config.authors);
+        let mut words: Vec<String> = (0..self.words())
+            .map(|_| WORDS[self.rng.random_range(0..WORDS.len())].to_string())
+            .collect();
+        let hashtags = self.rng.random_range(0..=self.config.max_hashtags_per_post);
+        for _ in 0..hashtags {
+            if let Some(rank) = self.zipf.sample(&mut self.rng) {
+                words.push(format!("#topic{rank}"));
+            }
+        }
+        // A random token keeps near-duplicate detection from merging posts.
+        words.push(format!("{:016x}", self.rng.random::<u64>()));
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let id = format!("synthetic-{}", self.sequence);
+        let mut post = Post {
+            uri: format!("https://{instance}/users/user{user}/statuses/{id}"),
+            id,
+            service: service as i32,
+            timestamp: Some(Timestamp {
+                seconds: now.as_secs() as i64,
+                nanos: now.subsec_nanos() as i32,
+            }),
+            content: format!("<p>{}</p>", words.join(" ")),
+            author: format!("user{user}@{instance}"),
+            tags: vec![SYNTHETIC_TAG.to_string()],
+            ..Default::default()
+        };
+        social_content::render(&mut post);
+        post
+    }
+}
+
+impl Registered for Synthetic {
+    const NAME: &'static str = "synthetic";
+    const CONFIG: &'static [ConfigVar] = &[
+        ConfigVar {
+            name: "SYNTHETIC_RATE",
+            required: false,
+            default: Some("100"),
+            description: "Posts per second.",
+        },
+        ConfigVar {
+            name: "SYNTHETIC_LIMIT",
+            required: false,
+            default: Some("0"),
+            description: "Stop after this many posts; 0 runs until stopped.",
+        },
+        ConfigVar {
+            name: "SYNTHETIC_SERVICES",
+            required: false,
+            default: Some("mastodon"),
+            description: "Comma-separated services to attribute posts to.",
+        },
+        ConfigVar {
+            name: "SYNTHETIC_MEAN_WORDS",
+            required: false,
+            default: Some("30"),
+            description: "Mean post length in words.",
+        },
+        ConfigVar {
+            name: "SYNTHETIC_MAX_WORDS",
+            required: false,
+            default: Some("500"),
+            description: "Longest post in words.",
+        },
+        ConfigVar {
+            name: "SYNTHETIC_HASHTAGS",
+            required: false,
+            default: Some("200"),
+            description: "Size of the hashtag vocabulary.",
+        },
+        ConfigVar {
+            name: "SYNTHETIC_HASHTAG_SKEW",
+            required: false,
+            default: Some("1.1"),
+            description: "Zipf exponent over the hashtag vocabulary.",
+        },
+        ConfigVar {
+            name: "SYNTHETIC_MAX_HASHTAGS_PER_POST",
+            required: false,
+            default: Some("3"),
+            description: "Most hashtags in one post.",
+        },
+        ConfigVar {
+            name: "SYNTHETIC_AUTHORS",
+            required: false,
+            default: Some("10000"),
+            description: "Distinct authors.",
+        },
+        ConfigVar {
+            name: "SYNTHETIC_SEED",
+            required: false,
+            default: None,
+            description: "Seed for reproducible output.",
+        },
+    ];
+    const RESTART: Restart = Restart::Never;
+
+    fn from_env() -> anyhow::Result<Self> {
+        Ok(Synthetic::new(SyntheticConfig::from_env()?))
+    }
+}
+
+impl SocialFeeder for Synthetic {
+    type Message = Post;
+
+    #[instrument(level = "debug", skip(self), fields(rate = self.config.rate))]
+    async fn stream(mut self, queue: FeederQueue<Self::Message>) -> anyhow::Result<()> {
+        info!(config = ?self.config, "Generating synthetic posts");
+        let start = Instant::now();
+        let interval = Duration::from_secs_f64(1.0 / self.config.rate);
+        let mut sent = 0u64;
+        while self.config.limit.is_none_or(|limit| sent < limit) {
+            // Deadlines are absolute, so timer granularity turns into small
+            // bursts rather than a lower rate.
+            sleep_until(start + interval.mul_f64(sent as f64)).await;
+            let post = self.next_post();
+            if queue.send(post).await.is_err() {
+                warn!("Feeder queue closed, stopping generator");
+                break;
+            }
+            sent += 1;
+        }
+        info!(sent, elapsed = ?start.elapsed(), "Generator finished");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SYNTHETIC_TAG, Synthetic, SyntheticConfig, Zipf};
+    use proto_definitions::social::v1::Service;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn hashtags_follow_the_configured_skew() {
+        let zipf = Zipf::new(100, 1.2);
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut counts = [0usize; 100];
+        for _ in 0..20_000 {
+            counts[zipf.sample(&mut rng).unwrap()] += 1;
+        }
+        assert!(counts[0] > counts[1] && counts[1] > counts[9] && counts[9] > counts[99]);
+        assert!(Zipf::new(0, 1.0).sample(&mut rng).is_none());
+
+        let mut generator = Synthetic::new(SyntheticConfig {
+            services: vec![Service::Mastodon, Service::X],
+            max_words: 5,
+            seed: Some(1),
+            ..Default::default()
+        });
+        let posts: Vec<_> = (0..50).map(|_| generator.next_post()).collect();
+        assert!(posts.iter().all(|post| post.tags == [SYNTHETIC_TAG]));
+        assert!(posts.iter().any(|post| post.service == Service::X as i32));
+        // Up to 5 words, 3 hashtags and the random token.
+        assert!(
+            posts
+                .iter()
+                .all(|post| post.content_text.split_whitespace().count() <= 9)
+        );
+        assert_ne!(posts[0].content_text, posts[1].content_text);
+    }
+}
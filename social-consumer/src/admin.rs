@@ -0,0 +1,162 @@
+//! Admin HTTP endpoint: consumer lag as JSON and Prometheus metrics, plus
+//! health and readiness probes.
+
+use anyhow::Result;
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{StatusCode, header::CONTENT_TYPE},
+    response::IntoResponse,
+    routing::get,
+};
+use serde::Serialize;
+use social_engine::lag::{LagMonitor, LagReport, PartitionLag};
+use std::{env, fmt::Write};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminConfig {
+    pub addr: String,
+    /// Total lag, in records, above which `/ready` reports not ready.
+    pub max_lag: i64,
+}
+
+impl AdminConfig {
+    /// `None` unless `ADMIN_ADDR` is set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(addr) = env::var("ADMIN_ADDR") else {
+            return Ok(None);
+        };
+        let max_lag = match env::var("CONSUMER_MAX_LAG") {
+            Ok(value) => value.parse()?,
+            Err(_) => 10_000,
+        };
+        Ok(Some(AdminConfig { addr, max_lag }))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AdminState {
+    lag: LagMonitor,
+    max_lag: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct Readiness {
+    ready: bool,
+    total_lag: i64,
+    max_lag: i64,
+}
+
+/// `GET /health`, `GET /ready`, `GET /lag` and `GET /metrics`.
+pub fn router(lag: LagMonitor, max_lag: i64) -> Router {
+    Router::new()
+        .route("/health", get(|| async { "OK" }))
+        .route("/ready", get(ready))
+        .route(
+            "/lag",
+            get(|State(state): State<AdminState>| async move { Json(state.lag.report()) }),
+        )
+        .route("/metrics", get(metrics))
+        .with_state(AdminState { lag, max_lag })
+}
+
+async fn ready(State(state): State<AdminState>) -> impl IntoResponse {
+    let report = state.lag.report();
+    let readiness = Readiness {
+        ready: report.total_lag <= state.max_lag,
+        total_lag: report.total_lag,
+        max_lag: state.max_lag,
+    };
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
+
+async fn metrics(State(state): State<AdminState>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render_metrics(&state.lag.report(), state.max_lag),
+    )
+}
+
+/// Renders per-partition offsets and lag in the Prometheus text format.
+pub fn render_metrics(report: &LagReport, max_lag: i64) -> String {
+    let mut out = String::new();
+    let column = |value: fn(&PartitionLag) -> Option<i64>| {
+        report.partitions.iter().map(value).collect::<Vec<_>>()
+    };
+    let gauges = [
+        ("social_consumer_lag", column(|p| p.lag)),
+        ("social_consumer_committed_offset", column(|p| p.committed)),
+        ("social_consumer_position", column(|p| p.position)),
+        (
+            "social_consumer_high_watermark",
+            column(|p| p.high_watermark),
+        ),
+    ];
+    for (name, values) in gauges {
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        for (partition, value) in report.partitions.iter().zip(values) {
+            if let Some(value) = value {
+                let _ = writeln!(
+                    out,
+                    "{name}{{topic=\"{}\",partition=\"{}\"}} {value}",
+                    partition.topic, partition.partition
+                );
+            }
+        }
+    }
+    let _ = writeln!(
+        out,
+        "# TYPE social_consumer_lag_total gauge\nsocial_consumer_lag_total {}",
+        report.total_lag
+    );
+    let _ = writeln!(
+        out,
+        "# TYPE social_consumer_lag_max gauge\nsocial_consumer_lag_max {max_lag}"
+    );
+    let _ = writeln!(
+        out,
+        "# TYPE social_consumer_ready gauge\nsocial_consumer_ready {}",
+        u8::from(report.total_lag <= max_lag)
+    );
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::render_metrics;
+    use social_engine::lag::{LagReport, PartitionLag};
+
+    #[test]
+    fn metrics_cover_every_partition_and_readiness() {
+        let partition = |partition, committed: Option<i64>, lag| PartitionLag {
+            topic: "raw-posts".to_string(),
+            partition,
+            committed,
+            position: committed,
+            high_watermark: Some(100),
+            lag,
+        };
+        let report = LagReport::new(vec![
+            partition(1, None, None),
+            partition(0, Some(40), Some(60)),
+        ]);
+        assert_eq!(report.total_lag, 60);
+        assert_eq!(report.partitions[0].partition, 0);
+
+        let metrics = render_metrics(&report, 50);
+        assert!(metrics.contains("social_consumer_lag{topic=\"raw-posts\",partition=\"0\"} 60\n"));
+        assert!(
+            metrics.contains(
+                "social_consumer_high_watermark{topic=\"raw-posts\",partition=\"1\"} 100\n"
+            )
+        );
+        assert!(!metrics.contains("social_consumer_lag{topic=\"raw-posts\",partition=\"1\"}"));
+        assert!(metrics.contains("social_consumer_ready 0\n"));
+        assert!(render_metrics(&report, 60).contains("social_consumer_ready 1\n"));
+    }
+}
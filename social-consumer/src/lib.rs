@@ -0,0 +1,4 @@
+pub mod admin;
+pub mod publisher;
+pub mod recorder;
+pub mod stages;
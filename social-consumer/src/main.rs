@@ -1,10 +1,16 @@
 use anyhow::Result;
-use prost::Message;
-use proto_definitions::social::v1::{Post, PostBatch};
-use redis::{AsyncCommands, RedisResult, aio::MultiplexedConnection};
-use social_engine::{engine::SocialEngineBuilder, error::Error};
-use std::{env, time::Duration};
-use tokio::{sync::mpsc, time::interval};
+use proto_definitions::social::v1::Post;
+use social_consumer::{
+    admin::{self, AdminConfig},
+    publisher::{Sink, publish_batch},
+    recorder::Recorder,
+    stages,
+};
+use social_engine::{
+    engine::SocialEngineBuilder, envelope::Envelope, error::Error, seek::StartFrom,
+};
+use std::{env, path::Path, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, sync::mpsc, time::interval};
 use tracing::{debug, error, info, instrument};
 use tracing_subscriber::{
     EnvFilter, Layer,
@@ -33,7 +39,7 @@ async fn main() -> Result<()> {
     let redis_url = format!("redis://:{}@{}:{}", redis_password, redis_host, redis_port);
     debug!(%redis_url);
     let kafka_topic = env::var("KAFKA_TOPIC").unwrap_or_else(|_| "social.posts".to_string());
-    let redis_channel = env::var("REDIS_CHANNEL").unwrap_or_else(|_| "posts.live".to_string());
+    let sink = Sink::from_env()?;
     let kafka_username =
         env::var("KAFKA_USERNAME").expect("Missing required environment variable: KAFKA_USERNAME");
     let kafka_password =
@@ -42,18 +48,72 @@ async fn main() -> Result<()> {
     // GROUP_ID
     let group_id = env::var("GROUP_ID").expect("Missing required environment variable: GROUP_ID");
     let schema_url = Url::parse(&schema_registry_url)?;
-    let consumer = SocialEngineBuilder::decoder(schema_url)
-        .with_consumer(&kafka_brokers, &kafka_username, &kafka_password, &group_id)?
+    // CONSUMER_START: `committed`, `earliest`, `latest`, `timestamp:<millis>`,
+    // `since:<duration>` (e.g. `since:6h` to rebuild from the last 6 hours) or
+    // `offsets:<topic>:<partition>:<offset>,...`
+    let start: StartFrom = env::var("CONSUMER_START")
+        .unwrap_or_else(|_| "committed".to_string())
+        .parse()
+        .map_err(anyhow::Error::msg)?;
+    let consumer = SocialEngineBuilder::decoder(schema_url.clone())
+        .with_consumer_from(
+            &kafka_brokers,
+            &kafka_username,
+            &kafka_password,
+            &group_id,
+            start,
+        )?
         .build();
     debug!("consumer setup successful");
+
+    if let Some(admin) = AdminConfig::from_env()? {
+        let listener = TcpListener::bind(&admin.addr).await?;
+        let router = admin::router(consumer.lag_monitor(), admin.max_lag);
+        info!(addr = %admin.addr, max_lag = admin.max_lag, "Serving consumer lag and probes");
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                error!("Admin server stopped: {}", e);
+            }
+        });
+    }
+    let topics = [kafka_topic.as_str()];
+
+    if let Ok(path) = env::var("RECORD_FILE") {
+        let recorder = Arc::new(Recorder::create(Path::new(&path)).await?);
+        info!("Recording '{}' to {}", kafka_topic, path);
+        consumer
+            .run(&topics, move |envelope: Envelope<Post>| {
+                let recorder = recorder.clone();
+                async move { recorder.record(&envelope.message).await }
+            })
+            .await;
+        return Ok(());
+    }
+
     let redis_client = redis::Client::open(redis_url)?;
     let redis_conn = redis_client.get_multiplexed_async_connection().await?;
 
+    let review = match env::var("MODERATION_REVIEW_TOPIC") {
+        Ok(review_topic) => {
+            let (producer, queue) = SocialEngineBuilder::encoder(schema_url)
+                .with_producer(&kafka_brokers, &kafka_username, &kafka_password)?
+                .build_multi(100);
+            info!("Routing moderated posts to '{}' for review", review_topic);
+            tokio::spawn(async move {
+                if let Err(e) = producer.run(&review_topic).await {
+                    error!("Review producer stopped: {}", e);
+                }
+            });
+            Some(queue)
+        }
+        Err(_) => None,
+    };
+    let pipeline = Arc::new(stages::pipeline_from_env(&redis_conn, review)?);
     let (tx, mut rx) = mpsc::channel::<Post>(2049);
 
     let aggregate_task = tokio::spawn({
         let mut redis_publisher = redis_conn.clone();
-        let redis_channel = redis_channel.clone();
+        let sink = sink.clone();
         async move {
             let mut batch = Vec::with_capacity(50);
             let mut ticker = interval(Duration::from_secs(1));
@@ -62,14 +122,14 @@ async fn main() -> Result<()> {
                     _ = ticker.tick() => {
                         if !batch.is_empty() {
                             info!("timer ticked, publishing {} posts", batch.len());
-                            publish_batch(&mut redis_publisher, &redis_channel, &mut batch).await;
+                            publish_batch(&mut redis_publisher, &sink, &mut batch).await;
                         }
                     }
                     Some(post) = rx.recv() => {
                         batch.push(post);
                         if batch.len() >= 50 {
                             info!("batch full, publishing posts {}", batch.len());
-                            publish_batch(&mut redis_publisher, &redis_channel, &mut batch).await;
+                            publish_batch(&mut redis_publisher, &sink, &mut batch).await;
                         }
                     }
                 }
@@ -77,20 +137,23 @@ async fn main() -> Result<()> {
         }
     });
 
-    let topics = [kafka_topic.as_str()];
-
-    let consumer_task = consumer.run(&topics, move |post: Post| {
+    let consumer_task = consumer.run(&topics, move |envelope: Envelope<Post>| {
         let tx = tx.clone();
+        let pipeline = pipeline.clone();
         async move {
-            tx.send(post)
-                .await
-                .map_err(|e| Error::Generic(e.to_string()))
+            for post in pipeline.process(envelope.message).await {
+                tx.send(post)
+                    .await
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+            }
+            Ok(())
         }
     });
 
     info!(
-        "Now consuming from '{}' and publishing to Redis channel '{}'",
-        kafka_topic, redis_channel
+        "Now consuming from '{}' and publishing to Redis '{}'",
+        kafka_topic,
+        sink.name()
     );
     tokio::try_join!(aggregate_task, async {
         consumer_task.await;
@@ -98,22 +161,3 @@ async fn main() -> Result<()> {
     })?;
     Ok(())
 }
-
-#[instrument]
-async fn publish_batch(
-    redis_conn: &mut MultiplexedConnection,
-    channel: &str,
-    batch: &mut Vec<Post>,
-) {
-    let posts = PostBatch {
-        posts: std::mem::take(batch),
-    };
-
-    let mut buffer = Vec::new();
-    if posts.encode(&mut buffer).is_ok() {
-        let result: RedisResult<()> = redis_conn.publish(channel, buffer).await;
-        if let Err(e) = result {
-            error!("Failed to publish to Redis: {}", e);
-        }
-    }
-}
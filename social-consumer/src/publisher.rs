@@ -0,0 +1,115 @@
+use prost::Message;
+use proto_definitions::social::v1::{Post, PostBatch};
+use redis::{Cmd, RedisResult, aio::MultiplexedConnection, streams::StreamMaxlen};
+use std::env;
+use tracing::{error, instrument};
+
+/// Stream entry field holding the encoded `PostBatch`.
+pub const BATCH_FIELD: &str = "batch";
+
+/// Where batches are published. `Stream` keeps a capped history that the
+/// aggregator can replay, `PubSub` only reaches currently connected subscribers.
+#[derive(Debug, Clone)]
+pub enum Sink {
+    PubSub { channel: String },
+    Stream { key: String, maxlen: usize },
+}
+
+impl Sink {
+    pub fn from_env() -> anyhow::Result<Self> {
+        match env::var("REDIS_TRANSPORT").as_deref() {
+            Ok("pubsub") | Err(_) => Ok(Sink::PubSub {
+                channel: env::var("REDIS_CHANNEL").unwrap_or_else(|_| "posts.live".to_string()),
+            }),
+            Ok("stream") => Ok(Sink::Stream {
+                key: env::var("REDIS_STREAM").unwrap_or_else(|_| "posts.stream".to_string()),
+                maxlen: env::var("REDIS_STREAM_MAXLEN")
+                    .unwrap_or_else(|_| "10000".to_string())
+                    .parse()?,
+            }),
+            Ok(other) => {
+                anyhow::bail!("REDIS_TRANSPORT must be `pubsub` or `stream`, got `{other}`")
+            }
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Sink::PubSub { channel } => channel,
+            Sink::Stream { key, .. } => key,
+        }
+    }
+}
+
+#[instrument(skip(redis_conn, batch), fields(posts = batch.len()))]
+pub async fn publish_batch(
+    redis_conn: &mut MultiplexedConnection,
+    sink: &Sink,
+    batch: &mut Vec<Post>,
+) {
+    let posts = PostBatch {
+        posts: std::mem::take(batch),
+    };
+
+    let mut buffer = Vec::new();
+    if posts.encode(&mut buffer).is_ok() {
+        let result: RedisResult<()> = publish_cmd(sink, buffer).query_async(redis_conn).await;
+        if let Err(e) = result {
+            error!("Failed to publish to Redis: {}", e);
+        }
+    }
+}
+
+/// `PUBLISH` or a capped `XADD` of an encoded batch.
+fn publish_cmd(sink: &Sink, payload: Vec<u8>) -> Cmd {
+    match sink {
+        Sink::PubSub { channel } => Cmd::publish(channel, payload),
+        Sink::Stream { key, maxlen } => Cmd::xadd_maxlen(
+            key,
+            StreamMaxlen::Approx(*maxlen),
+            "*",
+            &[(BATCH_FIELD, payload)],
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BATCH_FIELD, Sink, publish_cmd};
+    use redis::Arg;
+
+    fn args(sink: &Sink) -> Vec<Vec<u8>> {
+        publish_cmd(sink, b"batch-bytes".to_vec())
+            .args_iter()
+            .filter_map(|arg| match arg {
+                Arg::Simple(bytes) => Some(bytes.to_vec()),
+                Arg::Cursor => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stream_sink_appends_capped_entries() {
+        let sink = Sink::Stream {
+            key: "posts.stream".to_string(),
+            maxlen: 500,
+        };
+        let expected: Vec<&[u8]> = vec![
+            b"XADD",
+            b"posts.stream",
+            b"MAXLEN",
+            b"~",
+            b"500",
+            b"*",
+            BATCH_FIELD.as_bytes(),
+            b"batch-bytes",
+        ];
+        assert_eq!(args(&sink), expected);
+
+        let sink = Sink::PubSub {
+            channel: "posts.live".to_string(),
+        };
+        let expected: Vec<&[u8]> = vec![b"PUBLISH", b"posts.live", b"batch-bytes"];
+        assert_eq!(args(&sink), expected);
+    }
+}
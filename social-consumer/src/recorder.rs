@@ -0,0 +1,44 @@
+use anyhow::{Context, Result};
+use proto_definitions::social::v1::Post;
+use social_engine::{
+    capture::{CaptureWriter, Format},
+    error::Error,
+};
+use std::path::Path;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::BufWriter,
+    sync::Mutex,
+};
+use tracing::info;
+
+/// Appends consumed posts to a capture the feeders' `replay` source can play
+/// back: JSON lines for `.jsonl` files, length-delimited protobuf otherwise.
+#[derive(Debug)]
+pub struct Recorder {
+    writer: Mutex<CaptureWriter<BufWriter<File>, Post>>,
+}
+
+impl Recorder {
+    pub async fn create(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("failed to open capture file {}", path.display()))?;
+        let format = Format::from_path(path);
+        info!(path = %path.display(), ?format, "Recording posts");
+        Ok(Recorder {
+            writer: Mutex::new(CaptureWriter::new(BufWriter::new(file), format)),
+        })
+    }
+
+    /// Flushed before returning, so the offset is only committed once the
+    /// post is on disk.
+    pub async fn record(&self, post: &Post) -> Result<(), Error> {
+        let mut writer = self.writer.lock().await;
+        writer.write(post).await?;
+        writer.flush().await
+    }
+}
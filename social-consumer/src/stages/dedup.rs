@@ -0,0 +1,405 @@
+use super::text;
+use anyhow::{Result, bail};
+use prost::Message;
+use proto_definitions::{
+    PostId,
+    social::v1::{Post, Source},
+};
+use redis::{AsyncCommands, RedisResult, aio::MultiplexedConnection};
+use social_engine::stage::{Stage, StageFuture};
+use std::{env, fmt};
+use tracing::{debug, instrument, warn};
+use url::Url;
+
+/// The 64 bit fingerprint is split into this many bands. Two fingerprints
+/// within `BANDS - 1` bits of each other share at least one band exactly,
+/// which is what makes the Redis lookup possible.
+const BANDS: u32 = 4;
+const BAND_BITS: u32 = u64::BITS / BANDS;
+/// Words per shingle fed into the fingerprint.
+const SHINGLE: usize = 3;
+/// Query parameters that only track where a link was clicked.
+const TRACKING_PARAMS: &[&str] = &["fbclid", "gclid", "mc_cid", "mc_eid", "ref_src"];
+
+#[derive(Debug, Clone)]
+pub struct DedupConfig {
+    /// Prefix for every Redis key the stage writes.
+    pub prefix: String,
+    /// How long a post stays in the seen set.
+    pub ttl_secs: u64,
+    /// Largest fingerprint Hamming distance still treated as the same content.
+    pub max_distance: u32,
+    /// Posts with fewer words than this are never fingerprinted.
+    pub min_words: usize,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        DedupConfig {
+            prefix: "dedup".to_string(),
+            ttl_secs: 3600,
+            max_distance: 3,
+            min_words: 8,
+        }
+    }
+}
+
+impl DedupConfig {
+    pub fn from_env() -> Result<Self> {
+        let default = DedupConfig::default();
+        let config = DedupConfig {
+            prefix: env::var("DEDUP_PREFIX").unwrap_or(default.prefix),
+            ttl_secs: match env::var("DEDUP_TTL_SECS") {
+                Ok(value) => value.parse()?,
+                Err(_) => default.ttl_secs,
+            },
+            max_distance: match env::var("DEDUP_MAX_DISTANCE") {
+                Ok(value) => value.parse()?,
+                Err(_) => default.max_distance,
+            },
+            min_words: match env::var("DEDUP_MIN_WORDS") {
+                Ok(value) => value.parse()?,
+                Err(_) => default.min_words,
+            },
+        };
+        if config.max_distance >= BANDS {
+            bail!("DEDUP_MAX_DISTANCE must be below {BANDS}");
+        }
+        Ok(config)
+    }
+
+    fn keys(&self, post: &Post) -> Keys {
+        let prefix = &self.prefix;
+        let uris = [&post.uri, &post.reblog_of]
+            .into_iter()
+            .filter_map(|uri| canonical_uri(uri))
+            .map(|uri| format!("{prefix}:uri:{uri}"))
+            .collect();
+        let fingerprint = simhash(text(post), self.min_words);
+        let bands = fingerprint
+            .map(|hash| {
+                (0..BANDS)
+                    .map(|band| format!("{prefix}:simhash:{band}:{:x}", band_value(hash, band)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Keys {
+            uris,
+            fingerprint,
+            bands,
+        }
+    }
+
+    /// `id` is a `PostId::id`, so equal ids from different services do not
+    /// collide.
+    fn post_key(&self, id: &str) -> String {
+        format!("{}:post:{id}", self.prefix)
+    }
+}
+
+/// Merges posts seen within the TTL window that share a canonical URI, a
+/// reblog target or near-identical content. The first post seen is kept and
+/// re-emitted with the duplicate appended to its `sources`; clients replace
+/// posts by id, so the duplicate never shows up on its own.
+///
+/// Redis failures let the post through unmerged rather than dropping it.
+#[derive(Clone)]
+pub struct Dedup {
+    redis: MultiplexedConnection,
+    config: DedupConfig,
+}
+
+impl fmt::Debug for Dedup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dedup")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// The seen-set keys derived from one post.
+#[derive(Debug, Default)]
+struct Keys {
+    uris: Vec<String>,
+    fingerprint: Option<u64>,
+    bands: Vec<String>,
+}
+
+impl Dedup {
+    pub fn new(redis: MultiplexedConnection, config: DedupConfig) -> Self {
+        Dedup { redis, config }
+    }
+
+    /// Looks up the post an earlier copy of this one was stored under.
+    async fn find_primary(
+        &self,
+        redis: &mut MultiplexedConnection,
+        keys: &Keys,
+    ) -> RedisResult<Option<Post>> {
+        let mut primary = None;
+        if !keys.uris.is_empty() {
+            let ids: Vec<Option<String>> = redis.mget(&keys.uris).await?;
+            primary = ids.into_iter().flatten().next();
+        }
+        if primary.is_none()
+            && let Some(fingerprint) = keys.fingerprint
+        {
+            let entries: Vec<Option<String>> = redis.mget(&keys.bands).await?;
+            primary = entries.into_iter().flatten().find_map(|entry| {
+                let (hash, id) = entry.split_once(' ')?;
+                let hash = u64::from_str_radix(hash, 16).ok()?;
+                ((hash ^ fingerprint).count_ones() <= self.config.max_distance)
+                    .then(|| id.to_string())
+            });
+        }
+        let Some(id) = primary else {
+            return Ok(None);
+        };
+        let encoded: Option<Vec<u8>> = redis.get(self.config.post_key(&id)).await?;
+        Ok(encoded.and_then(|bytes| Post::decode(bytes.as_slice()).ok()))
+    }
+
+    /// Stores `primary` and points `keys` at it, all expiring after the TTL.
+    async fn remember(
+        &self,
+        redis: &mut MultiplexedConnection,
+        primary: &Post,
+        keys: &Keys,
+    ) -> RedisResult<()> {
+        let ttl = self.config.ttl_secs;
+        let id = primary.id();
+        let mut pipe = redis::pipe();
+        pipe.set_ex(self.config.post_key(&id), primary.encode_to_vec(), ttl)
+            .ignore();
+        for key in &keys.uris {
+            pipe.set_ex(key, &id, ttl).ignore();
+        }
+        if let Some(fingerprint) = keys.fingerprint {
+            let entry = format!("{fingerprint:016x} {id}");
+            for key in &keys.bands {
+                pipe.set_ex(key, &entry, ttl).ignore();
+            }
+        }
+        pipe.query_async(redis).await
+    }
+
+    #[instrument(name = "dedup", level = "debug", skip_all, fields(id = %post.id()))]
+    async fn dedup(&self, mut post: Post) -> Vec<Post> {
+        if post.sources.is_empty() {
+            post.sources.push(source(&post));
+        }
+        let keys = self.config.keys(&post);
+        let mut redis = self.redis.clone();
+        let primary = match self.find_primary(&mut redis, &keys).await {
+            Ok(primary) => primary,
+            Err(e) => {
+                warn!("Dedup lookup failed, passing post through: {}", e);
+                return vec![post];
+            }
+        };
+        let post = combine(primary, post);
+        if let Err(e) = self.remember(&mut redis, &post, &keys).await {
+            warn!("Failed to record post in dedup set: {}", e);
+        }
+        vec![post]
+    }
+}
+
+impl Stage<Post> for Dedup {
+    fn name(&self) -> &str {
+        "dedup"
+    }
+
+    fn process(&self, post: Post) -> StageFuture<'_, Post> {
+        Box::pin(self.dedup(post))
+    }
+}
+
+fn source(post: &Post) -> Source {
+    Source {
+        service: post.service,
+        id: post.id.clone(),
+        uri: post.uri.clone(),
+    }
+}
+
+/// The post to keep given the `primary` stored for an earlier copy, if any.
+fn combine(primary: Option<Post>, mut post: Post) -> Post {
+    match primary {
+        // A redelivery or an edit of the post we already kept.
+        Some(primary) if primary.service == post.service && primary.id == post.id => {
+            post.sources = primary.sources;
+            post
+        }
+        Some(primary) => {
+            debug!(primary = %primary.id(), "merging duplicate");
+            merge(primary, post)
+        }
+        None => post,
+    }
+}
+
+/// Appends the duplicate's sources to the primary's, skipping ones it already has.
+fn merge(mut primary: Post, duplicate: Post) -> Post {
+    for source in duplicate.sources {
+        if !primary
+            .sources
+            .iter()
+            .any(|seen| seen.service == source.service && seen.id == source.id)
+        {
+            primary.sources.push(source);
+        }
+    }
+    primary
+}
+
+/// Normalizes an http(s) URI so trivially different spellings share a key:
+/// no fragment, no tracking parameters and no trailing slash.
+fn canonical_uri(uri: &str) -> Option<String> {
+    let mut url = Url::parse(uri.trim()).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    url.set_fragment(None);
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_") && !TRACKING_PARAMS.contains(&key.as_ref()))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+    let path = url.path().trim_end_matches('/').to_string();
+    if !path.is_empty() {
+        url.set_path(&path);
+    }
+    Some(url.to_string())
+}
+
+/// SimHash over word shingles of the lowercased text, or `None` when the text
+/// is too short for a fingerprint to mean anything.
+fn simhash(text: &str, min_words: usize) -> Option<u64> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    if words.len() < min_words.max(SHINGLE) {
+        return None;
+    }
+    let mut weights = [0i32; u64::BITS as usize];
+    for shingle in words.windows(SHINGLE) {
+        let hash = fnv1a(&shingle.join(" "));
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if hash >> bit & 1 == 1 { 1 } else { -1 };
+        }
+    }
+    Some(
+        weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0, |hash, (bit, _)| hash | 1 << bit),
+    )
+}
+
+fn band_value(hash: u64, band: u32) -> u64 {
+    hash >> (band * BAND_BITS) & ((1 << BAND_BITS) - 1)
+}
+
+/// FNV-1a, stable across processes and releases unlike `DefaultHasher`,
+/// which matters because fingerprints are shared through Redis.
+fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DedupConfig, canonical_uri, combine, merge, simhash, source};
+    use proto_definitions::{
+        PostId,
+        social::v1::{Post, Service},
+    };
+
+    #[test]
+    fn canonical_uri_drops_tracking_noise() {
+        assert_eq!(
+            canonical_uri("HTTPS://Example.com/blog/post/?utm_source=masto&id=3#comments").unwrap(),
+            "https://example.com/blog/post?id=3"
+        );
+        assert_eq!(
+            canonical_uri("https://example.com/").unwrap(),
+            "https://example.com/"
+        );
+        assert!(canonical_uri("at://did:plc:abc/post/1").is_none());
+    }
+
+    #[test]
+    fn simhash_is_close_for_near_duplicates() {
+        let original = "Rust 1.90 is out today with a faster linker on Linux, \
+                        read the full release notes on the blog";
+        let reposted = "rust 1.90 is out today with a faster linker on linux! \
+                        Read the full release notes on the blog via @rustlang";
+        let unrelated = "Our community garden is looking for volunteers to help \
+                         plant tomatoes this Saturday morning";
+        let original = simhash(original, 8).unwrap();
+        assert!((original ^ simhash(reposted, 8).unwrap()).count_ones() <= 3);
+        assert!((original ^ simhash(unrelated, 8).unwrap()).count_ones() > 3);
+        assert!(simhash("good morning", 8).is_none());
+    }
+
+    #[test]
+    fn merge_appends_new_sources_once() {
+        let post = |id: &str, service: Service| {
+            let mut post = Post {
+                id: id.to_string(),
+                service: service as i32,
+                ..Default::default()
+            };
+            post.sources.push(source(&post));
+            post
+        };
+        let merged = merge(post("1", Service::Mastodon), post("2", Service::Mastodon));
+        let merged = merge(merged, post("2", Service::Mastodon));
+        let merged = merge(merged, post("2", Service::X));
+        let ids: Vec<_> = merged.sources.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(merged.id, "1");
+        assert_eq!(ids, ["1", "2", "2"]);
+    }
+
+    #[test]
+    fn equal_ids_from_other_services_are_not_redeliveries() {
+        let post = |id: &str, service: Service, text: &str| {
+            let mut post = Post {
+                id: id.to_string(),
+                service: service as i32,
+                content: text.to_string(),
+                ..Default::default()
+            };
+            post.sources.push(source(&post));
+            post
+        };
+        let config = DedupConfig::default();
+        let toot = post("42", Service::Mastodon, "original");
+        let tweet = post("42", Service::X, "repost");
+        assert_ne!(config.post_key(&toot.id()), config.post_key(&tweet.id()));
+
+        let merged = combine(Some(toot.clone()), tweet.clone());
+        assert_eq!(merged.service, Service::Mastodon as i32);
+        assert_eq!(merged.content, "original");
+        assert_eq!(merged.sources.len(), 2);
+
+        let mut edited = toot.clone();
+        edited.content = "edited".to_string();
+        edited.sources.clear();
+        let redelivered = combine(Some(merged), edited);
+        assert_eq!(redelivered.content, "edited");
+        assert_eq!(redelivered.sources.len(), 2);
+
+        assert_eq!(combine(None, tweet.clone()), tweet);
+    }
+}
@@ -0,0 +1,17 @@
+use super::{extend_unique, text};
+use proto_definitions::social::v1::Post;
+use regex::Regex;
+use social_engine::stage::{Map, map};
+
+/// Lowercased hashtags, without the leading `#`.
+pub fn stage() -> Map<impl Fn(Post) -> Post + Send + Sync> {
+    let hashtag = Regex::new(r"(?:^|[^\w/#&])#(\w+)").expect("hashtag pattern is valid");
+    map("hashtags", move |mut post: Post| {
+        let tags: Vec<String> = hashtag
+            .captures_iter(text(&post))
+            .map(|captures| captures[1].to_lowercase())
+            .collect();
+        extend_unique(&mut post.hashtags, tags);
+        post
+    })
+}
@@ -0,0 +1,13 @@
+use proto_definitions::social::v1::Post;
+use social_engine::stage::{Map, map};
+
+/// Fills `content_text` and `content_html` for posts whose feeder did not
+/// already render them.
+pub fn stage() -> Map<impl Fn(Post) -> Post + Send + Sync> {
+    map("html_text", |mut post: Post| {
+        if post.content_text.is_empty() {
+            social_content::render(&mut post);
+        }
+        post
+    })
+}
@@ -0,0 +1,59 @@
+use super::{extend_unique, text};
+use anyhow::{Context, Result};
+use proto_definitions::social::v1::Post;
+use regex::Regex;
+use social_engine::stage::{Map, map};
+use std::env;
+
+/// A tag applied when any of its keywords appears as a whole word.
+#[derive(Debug, Clone)]
+pub struct KeywordRule {
+    pub tag: String,
+    pattern: Regex,
+}
+
+impl KeywordRule {
+    pub fn new<S: AsRef<str>>(tag: &str, keywords: &[S]) -> Result<Self> {
+        let alternatives: Vec<String> = keywords
+            .iter()
+            .map(|keyword| regex::escape(keyword.as_ref().trim()))
+            .collect();
+        let pattern = Regex::new(&format!(r"(?i)\b(?:{})\b", alternatives.join("|")))
+            .with_context(|| format!("invalid keywords for tag `{tag}`"))?;
+        Ok(KeywordRule {
+            tag: tag.to_string(),
+            pattern,
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.pattern.is_match(text)
+    }
+}
+
+/// Parses `KEYWORD_TAGS`, e.g. `rust=rust,cargo;ai=llm,gpt`.
+pub fn rules_from_env() -> Result<Vec<KeywordRule>> {
+    let spec = env::var("KEYWORD_TAGS").context("KEYWORD_TAGS must be set for `keywords`")?;
+    spec.split(';')
+        .filter(|rule| !rule.trim().is_empty())
+        .map(|rule| {
+            let (tag, keywords) = rule
+                .split_once('=')
+                .with_context(|| format!("expected `tag=keyword,...`, got `{rule}`"))?;
+            let keywords: Vec<&str> = keywords.split(',').collect();
+            KeywordRule::new(tag.trim(), &keywords)
+        })
+        .collect()
+}
+
+pub fn stage(rules: Vec<KeywordRule>) -> Map<impl Fn(Post) -> Post + Send + Sync> {
+    map("keywords", move |mut post: Post| {
+        let tags: Vec<String> = rules
+            .iter()
+            .filter(|rule| rule.is_match(text(&post)))
+            .map(|rule| rule.tag.clone())
+            .collect();
+        extend_unique(&mut post.tags, tags);
+        post
+    })
+}
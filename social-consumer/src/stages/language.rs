@@ -0,0 +1,17 @@
+use super::text;
+use proto_definitions::social::v1::Post;
+use social_engine::stage::{Map, map};
+
+/// Sets `language` to an ISO 639-3 code when detection is reliable. Leaves a
+/// language already set by the source untouched.
+pub fn stage() -> Map<impl Fn(Post) -> Post + Send + Sync> {
+    map("language", |mut post: Post| {
+        if post.language.is_empty()
+            && let Some(info) = whatlang::detect(text(&post))
+            && info.is_reliable()
+        {
+            post.language = info.lang().code().to_string();
+        }
+        post
+    })
+}
@@ -0,0 +1,137 @@
+use anyhow::{Result, bail};
+use proto_definitions::social::v1::Post;
+use redis::aio::MultiplexedConnection;
+use social_engine::{
+    queue::FeederQueue,
+    stage::{Pipeline, Stage},
+};
+use std::env;
+use tracing::info;
+
+pub mod dedup;
+pub mod hashtags;
+pub mod html_text;
+pub mod keywords;
+pub mod language;
+pub mod moderation;
+pub mod stats;
+pub mod subscriptions;
+pub mod trends;
+pub mod urls;
+
+/// Builds the enrichment chain from `PIPELINE_STAGES`, a comma separated
+/// list of stage names applied in order. Unset means posts pass through as-is.
+/// `review` receives posts the `moderation` stage filters out, if given.
+pub fn pipeline_from_env(
+    redis: &MultiplexedConnection,
+    review: Option<FeederQueue<Post>>,
+) -> Result<Pipeline<Post>> {
+    let names = env::var("PIPELINE_STAGES").unwrap_or_default();
+    let mut pipeline = Pipeline::new();
+    for name in names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        pipeline.push(stage(name, redis, &review)?);
+    }
+    info!(stages = ?pipeline.names(), "enrichment pipeline configured");
+    Ok(pipeline)
+}
+
+fn stage(
+    name: &str,
+    redis: &MultiplexedConnection,
+    review: &Option<FeederQueue<Post>>,
+) -> Result<Box<dyn Stage<Post>>> {
+    let stage: Box<dyn Stage<Post>> = match name {
+        "html_text" => Box::new(html_text::stage()),
+        "urls" => Box::new(urls::stage()),
+        "hashtags" => Box::new(hashtags::stage()),
+        "language" => Box::new(language::stage()),
+        "keywords" => Box::new(keywords::stage(keywords::rules_from_env()?)),
+        "dedup" => Box::new(dedup::Dedup::new(
+            redis.clone(),
+            dedup::DedupConfig::from_env()?,
+        )),
+        "trends" => Box::new(trends::Trends::from_env(redis.clone())),
+        "stats" => Box::new(stats::Stats::from_env(redis.clone())),
+        "moderation" => Box::new(moderation::ModerationStage::new(
+            moderation::Moderation::from_env()?,
+            redis.clone(),
+            review.clone(),
+        )),
+        "subscriptions" => Box::new(subscriptions::Subscriptions::from_env(redis.clone())?),
+        other => bail!("unknown pipeline stage `{other}`"),
+    };
+    Ok(stage)
+}
+
+/// Plain text when `html_text` already ran, otherwise the raw content.
+fn text(post: &Post) -> &str {
+    if post.content_text.is_empty() {
+        &post.content
+    } else {
+        &post.content_text
+    }
+}
+
+/// Appends `values` to `target`, skipping anything already present.
+fn extend_unique(target: &mut Vec<String>, values: impl IntoIterator<Item = String>) {
+    for value in values {
+        if !target.contains(&value) {
+            target.push(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hashtags, html_text, keywords, language, urls};
+    use proto_definitions::social::v1::Post;
+    use social_engine::stage::{Pipeline, filter};
+
+    const CONTENT: &str = r#"<p>Shipping the new parser in <a href="https://mastodon.social/tags/Rust" class="mention hashtag" rel="tag">#<span>Rust</span></a> today, write-up at <a href="https://example.com/blog/parser" rel="nofollow noopener" target="_blank"><span class="invisible">https://</span><span class="">example.com/blog/parser</span><span class="invisible"></span></a> &amp; thanks to everyone who tested the release candidates over the weekend.</p>"#;
+
+    fn pipeline() -> Pipeline<Post> {
+        let rules = vec![
+            keywords::KeywordRule::new("parsing", &["parser", "lexer"]).unwrap(),
+            keywords::KeywordRule::new("go", &["golang"]).unwrap(),
+        ];
+        Pipeline::new()
+            .with(html_text::stage())
+            .with(urls::stage())
+            .with(hashtags::stage())
+            .with(language::stage())
+            .with(keywords::stage(rules))
+    }
+
+    #[tokio::test]
+    async fn builtin_stages_enrich_posts() {
+        let post = Post {
+            id: "1".to_string(),
+            content: CONTENT.to_string(),
+            ..Default::default()
+        };
+        let posts = pipeline().process(post).await;
+        assert_eq!(posts.len(), 1);
+        let post = &posts[0];
+        assert!(
+            post.content_text
+                .starts_with("Shipping the new parser in #Rust today")
+        );
+        assert!(!post.content_text.contains('<'));
+        assert_eq!(post.urls, ["https://example.com/blog/parser"]);
+        assert_eq!(post.hashtags, ["rust"]);
+        assert_eq!(post.language, "eng");
+        assert_eq!(post.tags, ["parsing"]);
+    }
+
+    #[tokio::test]
+    async fn filter_stage_stops_the_chain() {
+        let pipeline = Pipeline::new()
+            .with(filter("non_empty", |post: &Post| !post.content.is_empty()))
+            .with(html_text::stage());
+        assert!(pipeline.process(Post::default()).await.is_empty());
+    }
+}
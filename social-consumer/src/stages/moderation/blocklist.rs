@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use std::{collections::HashSet, fs, path::Path};
+
+/// Blocked instances, as exported from Mastodon's *Moderation → Federation*
+/// page: `#domain,#severity,#reject_media,...`. A block covers subdomains too,
+/// and `noop` entries are ignored.
+#[derive(Debug, Default)]
+pub struct DomainBlocks {
+    domains: HashSet<String>,
+}
+
+impl DomainBlocks {
+    pub fn from_csv(csv: &str) -> Self {
+        let mut lines = csv.lines().map(str::trim).filter(|line| !line.is_empty());
+        let mut domain_column = 0;
+        let mut severity_column = None;
+        let mut first = lines.next();
+        if let Some(header) =
+            first.filter(|line| line.trim_start_matches('#').starts_with("domain"))
+        {
+            let columns: Vec<&str> = header
+                .split(',')
+                .map(|c| c.trim_start_matches('#'))
+                .collect();
+            domain_column = columns.iter().position(|c| *c == "domain").unwrap_or(0);
+            severity_column = columns.iter().position(|c| *c == "severity");
+            first = None;
+        }
+        let domains = first
+            .into_iter()
+            .chain(lines)
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+                let severity = severity_column.and_then(|column| fields.get(column).copied());
+                if severity == Some("noop") {
+                    return None;
+                }
+                let domain = fields.get(domain_column)?.trim_start_matches("*.");
+                (!domain.is_empty()).then(|| domain.to_lowercase())
+            })
+            .collect();
+        DomainBlocks { domains }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let csv = fs::read_to_string(path)
+            .with_context(|| format!("failed to read domain blocks from {}", path.display()))?;
+        Ok(Self::from_csv(&csv))
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    pub fn blocks(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        let mut candidate = host.as_str();
+        loop {
+            if self.domains.contains(candidate) {
+                return true;
+            }
+            match candidate.split_once('.') {
+                Some((_, parent)) => candidate = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Blocked or muted accounts as exported from Mastodon's *Import and export*
+/// page. The first column holds `user@domain`; the muted accounts export adds
+/// an `Account address` header, which is skipped.
+#[derive(Debug, Default)]
+pub struct AccountBlocks {
+    accounts: HashSet<String>,
+}
+
+impl AccountBlocks {
+    pub fn from_csv(csv: &str) -> Self {
+        let accounts = csv
+            .lines()
+            .filter_map(|line| line.split(',').next())
+            .map(|account| account.trim().trim_start_matches('@').to_lowercase())
+            .filter(|account| !account.is_empty() && account != "account address")
+            .collect();
+        AccountBlocks { accounts }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let csv = fs::read_to_string(path)
+            .with_context(|| format!("failed to read account blocks from {}", path.display()))?;
+        Ok(Self::from_csv(&csv))
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn blocks(&self, account: &str) -> bool {
+        self.accounts.contains(&account.to_lowercase())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AccountBlocks, DomainBlocks};
+
+    #[test]
+    fn mastodon_exports_parse() {
+        let domains = DomainBlocks::from_csv(
+            "#domain,#severity,#reject_media,#reject_reports,#public_comment,#obfuscate\n\
+             spam.example,suspend,true,true,spam,false\n\
+             quiet.example,silence,false,false,,false\n\
+             fine.example,noop,true,false,,false\n",
+        );
+        assert_eq!(domains.len(), 2);
+        assert!(domains.blocks("spam.example"));
+        assert!(domains.blocks("Relay.Spam.example"));
+        assert!(!domains.blocks("notspam.example"));
+        assert!(!domains.blocks("fine.example"));
+
+        let accounts =
+            AccountBlocks::from_csv("Account address,Hide notifications\nbot@spam.example,true\n");
+        assert!(accounts.blocks("BOT@spam.example"));
+        assert_eq!(accounts.len(), 1);
+    }
+}
@@ -0,0 +1,375 @@
+use super::{keywords::KeywordRule, text};
+use anyhow::{Context, Result};
+use proto_definitions::social::v1::Post;
+use redis::{AsyncCommands, aio::MultiplexedConnection};
+use regex::Regex;
+use social_engine::{
+    queue::FeederQueue,
+    stage::{Stage, StageFuture},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    env, fmt, fs,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+use url::Url;
+
+pub mod blocklist;
+
+pub use blocklist::{AccountBlocks, DomainBlocks};
+
+/// Redis hash counting filtered posts, one field per `Reason`.
+pub const FILTERED_KEY: &str = "moderation:filtered";
+/// Tag prefix recording why a post was sent to the review topic.
+pub const REVIEW_TAG_PREFIX: &str = "moderation:";
+const SPAM_SWEEP_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Sensitive,
+    Domain,
+    Account,
+    Word,
+    Spam,
+}
+
+impl Reason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reason::Sensitive => "sensitive",
+            Reason::Domain => "domain",
+            Reason::Account => "account",
+            Reason::Word => "word",
+            Reason::Spam => "spam",
+        }
+    }
+}
+
+/// Whole-word terms plus `/regex/` patterns, one per line; blank lines and
+/// lines starting with `#` are skipped.
+#[derive(Debug, Default)]
+pub struct WordFilter {
+    words: Option<KeywordRule>,
+    patterns: Vec<Regex>,
+}
+
+impl WordFilter {
+    pub fn parse(list: &str) -> Result<Self> {
+        let mut words = Vec::new();
+        let mut patterns = Vec::new();
+        for line in list.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line
+                .strip_prefix('/')
+                .and_then(|rest| rest.strip_suffix('/'))
+            {
+                Some(pattern) => patterns.push(
+                    Regex::new(pattern)
+                        .with_context(|| format!("invalid word filter pattern `{line}`"))?,
+                ),
+                None => words.push(line),
+            }
+        }
+        let words = (!words.is_empty())
+            .then(|| KeywordRule::new("word_filter", &words))
+            .transpose()?;
+        Ok(WordFilter { words, patterns })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let list = fs::read_to_string(path)
+            .with_context(|| format!("failed to read word filters from {}", path.display()))?;
+        Self::parse(&list)
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.words
+            .as_ref()
+            .is_some_and(|words| words.is_match(text))
+            || self.patterns.iter().any(|pattern| pattern.is_match(text))
+    }
+}
+
+/// Flags authors posting more than `max_posts` within `window`.
+#[derive(Debug)]
+pub struct SpamGuard {
+    max_posts: usize,
+    window: Duration,
+    recent: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl SpamGuard {
+    pub fn new(max_posts: usize, window: Duration) -> Self {
+        SpamGuard {
+            max_posts,
+            window,
+            recent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_spam(&self, author: &str, now: Instant) -> bool {
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() > SPAM_SWEEP_THRESHOLD {
+            recent.retain(|_, posts| {
+                posts
+                    .back()
+                    .is_some_and(|last| now.duration_since(*last) < self.window)
+            });
+        }
+        let posts = recent.entry(author.to_string()).or_default();
+        while posts
+            .front()
+            .is_some_and(|first| now.duration_since(*first) >= self.window)
+        {
+            posts.pop_front();
+        }
+        posts.push_back(now);
+        posts.len() > self.max_posts
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Moderation {
+    drop_sensitive: bool,
+    domains: DomainBlocks,
+    accounts: AccountBlocks,
+    words: WordFilter,
+    spam: Option<SpamGuard>,
+}
+
+impl Moderation {
+    pub fn new(drop_sensitive: bool) -> Self {
+        Moderation {
+            drop_sensitive,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_domains(mut self, domains: DomainBlocks) -> Self {
+        self.domains = domains;
+        self
+    }
+
+    pub fn with_accounts(mut self, accounts: AccountBlocks) -> Self {
+        self.accounts = accounts;
+        self
+    }
+
+    pub fn with_words(mut self, words: WordFilter) -> Self {
+        self.words = words;
+        self
+    }
+
+    pub fn with_spam_guard(mut self, spam: SpamGuard) -> Self {
+        self.spam = Some(spam);
+        self
+    }
+
+    /// Reads the `MODERATION_*` variables. Blocklists and word filters are
+    /// only loaded when their file is set; the spam guard is off when
+    /// `MODERATION_SPAM_MAX_POSTS` is 0.
+    pub fn from_env() -> Result<Self> {
+        let parse = |name: &str, default: u64| -> Result<u64> {
+            match env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .with_context(|| format!("{name} has an invalid value `{value}`")),
+                Err(_) => Ok(default),
+            }
+        };
+        let drop_sensitive = match env::var("MODERATION_DROP_SENSITIVE") {
+            Ok(value) => value.parse().with_context(|| {
+                format!("MODERATION_DROP_SENSITIVE has an invalid value `{value}`")
+            })?,
+            Err(_) => true,
+        };
+        let mut moderation = Moderation::new(drop_sensitive);
+        if let Ok(path) = env::var("MODERATION_DOMAIN_BLOCKS_FILE") {
+            moderation = moderation.with_domains(DomainBlocks::from_file(Path::new(&path))?);
+        }
+        if let Ok(path) = env::var("MODERATION_ACCOUNT_BLOCKS_FILE") {
+            moderation = moderation.with_accounts(AccountBlocks::from_file(Path::new(&path))?);
+        }
+        if let Ok(path) = env::var("MODERATION_WORDS_FILE") {
+            moderation = moderation.with_words(WordFilter::from_file(Path::new(&path))?);
+        }
+        let max_posts = parse("MODERATION_SPAM_MAX_POSTS", 10)?;
+        if max_posts > 0 {
+            let window = Duration::from_secs(parse("MODERATION_SPAM_WINDOW_SECS", 60)?);
+            moderation = moderation.with_spam_guard(SpamGuard::new(max_posts as usize, window));
+        }
+        info!(
+            drop_sensitive,
+            domains = moderation.domains.len(),
+            accounts = moderation.accounts.len(),
+            spam_guard = moderation.spam.is_some(),
+            "moderation configured"
+        );
+        Ok(moderation)
+    }
+
+    /// Why `post` should be kept out of the live stream, if at all.
+    pub fn check(&self, post: &Post) -> Option<Reason> {
+        let host = Url::parse(&post.uri)
+            .ok()
+            .and_then(|uri| uri.host_str().map(str::to_string));
+        // Local accounts come without a domain; qualify them with the instance.
+        let account = match (post.author.contains('@'), &host) {
+            (false, Some(host)) if !post.author.is_empty() => format!("{}@{host}", post.author),
+            _ => post.author.clone(),
+        };
+        let author_domain = account.split_once('@').map(|(_, domain)| domain);
+
+        if self.drop_sensitive && post.sensitive {
+            Some(Reason::Sensitive)
+        } else if host
+            .iter()
+            .map(String::as_str)
+            .chain(author_domain)
+            .any(|domain| self.domains.blocks(domain))
+        {
+            Some(Reason::Domain)
+        } else if self.accounts.blocks(&account) {
+            Some(Reason::Account)
+        } else if self.words.is_match(text(post)) {
+            Some(Reason::Word)
+        } else if !account.is_empty()
+            && self
+                .spam
+                .as_ref()
+                .is_some_and(|spam| spam.is_spam(&account, Instant::now()))
+        {
+            Some(Reason::Spam)
+        } else {
+            None
+        }
+    }
+}
+
+/// Drops posts that fail moderation. Each drop is counted in the
+/// `FILTERED_KEY` Redis hash and, when a review queue is configured, the post
+/// is tagged `moderation:<reason>` and sent there instead of being discarded.
+///
+/// Run it after `html_text` so word filters see plain text.
+pub struct ModerationStage {
+    moderation: Moderation,
+    redis: MultiplexedConnection,
+    review: Option<FeederQueue<Post>>,
+}
+
+impl fmt::Debug for ModerationStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModerationStage")
+            .field("moderation", &self.moderation)
+            .field("review", &self.review.is_some())
+            .finish()
+    }
+}
+
+impl ModerationStage {
+    pub fn new(
+        moderation: Moderation,
+        redis: MultiplexedConnection,
+        review: Option<FeederQueue<Post>>,
+    ) -> Self {
+        ModerationStage {
+            moderation,
+            redis,
+            review,
+        }
+    }
+
+    async fn moderate(&self, mut post: Post) -> Vec<Post> {
+        let Some(reason) = self.moderation.check(&post) else {
+            return vec![post];
+        };
+        debug!(post = %post.id, reason = reason.as_str(), "post filtered");
+        if let Err(e) = self
+            .redis
+            .clone()
+            .hincr::<_, _, _, ()>(FILTERED_KEY, reason.as_str(), 1)
+            .await
+        {
+            warn!("Failed to count filtered post: {}", e);
+        }
+        if let Some(review) = &self.review {
+            post.tags
+                .push(format!("{REVIEW_TAG_PREFIX}{}", reason.as_str()));
+            if let Err(e) = review.send(post).await {
+                warn!("Failed to route filtered post for review: {}", e);
+            }
+        }
+        Vec::new()
+    }
+}
+
+impl Stage<Post> for ModerationStage {
+    fn name(&self) -> &str {
+        "moderation"
+    }
+
+    fn process(&self, post: Post) -> StageFuture<'_, Post> {
+        Box::pin(self.moderate(post))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AccountBlocks, DomainBlocks, Moderation, Reason, SpamGuard, WordFilter};
+    use proto_definitions::social::v1::Post;
+    use std::time::Duration;
+
+    fn post(author: &str, content: &str) -> Post {
+        Post {
+            uri: "https://mastodon.example/users/x/statuses/1".to_string(),
+            author: author.to_string(),
+            content_text: content.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn moderation_reports_the_first_failing_check() {
+        let moderation = Moderation::new(true)
+            .with_domains(DomainBlocks::from_csv("spam.example\n"))
+            .with_accounts(AccountBlocks::from_csv("troll@mastodon.example\n"))
+            .with_words(
+                WordFilter::parse("# slurs and scams\ncrypto giveaway\n/(?i)fr[e3]{2} m0ney/\n")
+                    .unwrap(),
+            )
+            .with_spam_guard(SpamGuard::new(1, Duration::from_secs(60)));
+
+        assert_eq!(moderation.check(&post("alice", "hello")), None);
+        let sensitive = Post {
+            sensitive: true,
+            ..post("alice", "hello")
+        };
+        assert_eq!(moderation.check(&sensitive), Some(Reason::Sensitive));
+        assert_eq!(
+            moderation.check(&post("bot@relay.spam.example", "hello")),
+            Some(Reason::Domain)
+        );
+        assert_eq!(
+            moderation.check(&post("troll", "hello")),
+            Some(Reason::Account)
+        );
+        assert_eq!(
+            moderation.check(&post("bob", "Huge CRYPTO giveaway today")),
+            Some(Reason::Word)
+        );
+        assert_eq!(
+            moderation.check(&post("bob", "get FREE m0ney")),
+            Some(Reason::Word)
+        );
+        // alice already posted once above.
+        assert_eq!(
+            moderation.check(&post("alice", "hello again")),
+            Some(Reason::Spam)
+        );
+    }
+}
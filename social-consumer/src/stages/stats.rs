@@ -0,0 +1,128 @@
+use proto_definitions::social::v1::{Post, Service};
+use redis::{RedisResult, aio::MultiplexedConnection};
+use social_engine::stage::{Stage, StageFuture};
+use std::{
+    env, fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+use url::Url;
+
+/// Bucket width and retention per resolution. Retention bounds the longest
+/// range the aggregator's `GET /stats` can serve at that resolution.
+const RESOLUTIONS: &[(&str, u64, i64)] = &[
+    ("minute", 60, 25 * 3600),
+    ("hour", 3600, 31 * 24 * 3600),
+    ("day", 24 * 3600, 366 * 24 * 3600),
+];
+
+/// Rolls ingestion counts up into one Redis hash per time bucket and
+/// resolution. Fields are prefixed with the service so the aggregator can sum
+/// only the services a caller may see:
+///
+/// - `{service}:total`
+/// - `{service}:language:{code}`, `und` when the language is unknown
+/// - `{service}:instance:{host}`, taken from the post URI
+///
+/// Run it after `language`; posts always pass through unchanged.
+#[derive(Clone)]
+pub struct Stats {
+    redis: MultiplexedConnection,
+    prefix: String,
+}
+
+impl fmt::Debug for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stats")
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+impl Stats {
+    pub fn new(redis: MultiplexedConnection, prefix: String) -> Self {
+        Stats { redis, prefix }
+    }
+
+    pub fn from_env(redis: MultiplexedConnection) -> Self {
+        let prefix = env::var("STATS_PREFIX").unwrap_or_else(|_| "stats".to_string());
+        Stats::new(redis, prefix)
+    }
+
+    async fn record(&self, post: &Post) -> RedisResult<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let fields = fields(post);
+        let mut pipe = redis::pipe();
+        for (resolution, width, ttl) in RESOLUTIONS {
+            let key = format!("{}:{resolution}:{}", self.prefix, now / width * width);
+            for field in &fields {
+                pipe.hincr(&key, field, 1).ignore();
+            }
+            pipe.expire(&key, *ttl).ignore();
+        }
+        pipe.query_async(&mut self.redis.clone()).await
+    }
+}
+
+impl Stage<Post> for Stats {
+    fn name(&self) -> &str {
+        "stats"
+    }
+
+    fn process(&self, post: Post) -> StageFuture<'_, Post> {
+        Box::pin(async move {
+            if let Err(e) = self.record(&post).await {
+                warn!("Failed to record stats for {}: {}", post.id, e);
+            }
+            vec![post]
+        })
+    }
+}
+
+fn fields(post: &Post) -> Vec<String> {
+    let service = Service::try_from(post.service)
+        .map(|service| service.as_str_name().to_lowercase())
+        .unwrap_or_else(|_| "unknown".to_string());
+    let language = if post.language.is_empty() {
+        "und"
+    } else {
+        &post.language
+    };
+    let mut fields = vec![
+        format!("{service}:total"),
+        format!("{service}:language:{language}"),
+    ];
+    if let Some(host) = Url::parse(&post.uri)
+        .ok()
+        .and_then(|uri| uri.host_str().map(str::to_string))
+    {
+        fields.push(format!("{service}:instance:{host}"));
+    }
+    fields
+}
+
+#[cfg(test)]
+mod test {
+    use super::fields;
+    use proto_definitions::social::v1::{Post, Service};
+
+    #[test]
+    fn fields_are_prefixed_with_the_service() {
+        let post = Post {
+            service: Service::Mastodon as i32,
+            uri: "https://fosstodon.org/users/alice/statuses/1".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            fields(&post),
+            [
+                "mastodon:total",
+                "mastodon:language:und",
+                "mastodon:instance:fosstodon.org"
+            ]
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use super::{keywords::KeywordRule, text};
+use anyhow::{Context, Result, bail};
+use prost::Message;
+use proto_definitions::social::v1::{Post, PostBatch, Service};
+use redis::aio::MultiplexedConnection;
+use regex::Regex;
+use serde::Deserialize;
+use social_engine::stage::{Stage, StageFuture};
+use std::{env, fmt, fs, path::Path, sync::Arc};
+use tracing::{debug, info, warn};
+
+pub mod webhook;
+
+pub use webhook::{Dispatcher, Webhook, WebhookConfig};
+
+/// A rule as stored in `SUBSCRIPTIONS_FILE`.
+#[derive(Debug, Deserialize)]
+struct RuleSpec {
+    id: String,
+    #[serde(default)]
+    keywords: Vec<String>,
+    #[serde(default)]
+    regex: Vec<String>,
+    #[serde(default)]
+    hashtags: Vec<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    services: Vec<String>,
+    webhook: Option<Webhook>,
+}
+
+/// A compiled subscription. A post matches when it comes from one of
+/// `services` (any, if empty) and hits at least one keyword, pattern,
+/// hashtag or author.
+#[derive(Debug)]
+pub struct Rule {
+    pub id: String,
+    keywords: Option<KeywordRule>,
+    patterns: Vec<Regex>,
+    hashtags: Vec<String>,
+    authors: Vec<String>,
+    services: Vec<i32>,
+    pub webhook: Option<Webhook>,
+}
+
+impl Rule {
+    fn compile(spec: RuleSpec) -> Result<Self> {
+        let RuleSpec {
+            id,
+            keywords,
+            regex,
+            hashtags,
+            authors,
+            services,
+            webhook,
+        } = spec;
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("subscription id `{id}` may only use letters, digits, `-` and `_`");
+        }
+        if keywords.is_empty() && regex.is_empty() && hashtags.is_empty() && authors.is_empty() {
+            bail!("subscription `{id}` needs at least one keyword, regex, hashtag or author");
+        }
+        let keywords = (!keywords.is_empty())
+            .then(|| KeywordRule::new(&id, &keywords))
+            .transpose()?;
+        let patterns = regex
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("invalid regex in subscription `{id}`"))?;
+        let services = services
+            .iter()
+            .map(|name| {
+                Service::from_str_name(&name.to_uppercase())
+                    .map(|service| service as i32)
+                    .with_context(|| format!("unknown service `{name}` in subscription `{id}`"))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Rule {
+            keywords,
+            patterns,
+            hashtags: hashtags
+                .iter()
+                .map(|tag| tag.trim_start_matches('#').to_lowercase())
+                .collect(),
+            authors: authors
+                .iter()
+                .map(|author| author.trim_start_matches('@').to_lowercase())
+                .collect(),
+            services,
+            webhook,
+            id,
+        })
+    }
+
+    pub fn matches(&self, post: &Post) -> bool {
+        if !self.services.is_empty() && !self.services.contains(&post.service) {
+            return false;
+        }
+        let text = text(post);
+        self.keywords
+            .as_ref()
+            .is_some_and(|keywords| keywords.is_match(text))
+            || self.patterns.iter().any(|pattern| pattern.is_match(text))
+            || post
+                .hashtags
+                .iter()
+                .any(|tag| self.hashtags.contains(&tag.to_lowercase()))
+            || self.authors.contains(&post.author.to_lowercase())
+    }
+}
+
+/// Parses a JSON list of rules, e.g.
+/// `[{"id": "rust", "keywords": ["rustlang"], "hashtags": ["rust"],
+///    "webhook": {"url": "https://hooks.example.com/rust", "secret": "..."}}]`.
+pub fn rules_from_json(json: &str) -> Result<Vec<Rule>> {
+    let specs: Vec<RuleSpec> = serde_json::from_str(json).context("invalid subscriptions")?;
+    specs.into_iter().map(Rule::compile).collect()
+}
+
+pub fn rules_from_file(path: &Path) -> Result<Vec<Rule>> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("failed to read subscriptions from {}", path.display()))?;
+    rules_from_json(&json)
+}
+
+/// Evaluates every rule against each post. Matches are published as a
+/// one-post `PostBatch` on the rule's Redis channel, which the aggregator
+/// serves as `/subscriptions/{id}/sse`, and queued for the rule's webhook.
+/// Posts always pass through unchanged.
+#[derive(Clone)]
+pub struct Subscriptions {
+    rules: Arc<Vec<Rule>>,
+    redis: MultiplexedConnection,
+    channel_prefix: String,
+    dispatcher: Dispatcher,
+}
+
+impl fmt::Debug for Subscriptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscriptions")
+            .field("rules", &self.rules.len())
+            .field("channel_prefix", &self.channel_prefix)
+            .finish()
+    }
+}
+
+impl Subscriptions {
+    pub fn new(
+        rules: Vec<Rule>,
+        redis: MultiplexedConnection,
+        channel_prefix: String,
+        dispatcher: Dispatcher,
+    ) -> Self {
+        Subscriptions {
+            rules: Arc::new(rules),
+            redis,
+            channel_prefix,
+            dispatcher,
+        }
+    }
+
+    /// Reads `SUBSCRIPTIONS_FILE`, `SUBSCRIPTIONS_CHANNEL_PREFIX` and the
+    /// webhook settings. Must be called from within the Tokio runtime.
+    pub fn from_env(redis: MultiplexedConnection) -> Result<Self> {
+        let path = env::var("SUBSCRIPTIONS_FILE")
+            .context("SUBSCRIPTIONS_FILE must be set for `subscriptions`")?;
+        let rules = rules_from_file(Path::new(&path))?;
+        info!(rules = rules.len(), "subscriptions loaded");
+        let channel_prefix = env::var("SUBSCRIPTIONS_CHANNEL_PREFIX")
+            .unwrap_or_else(|_| "subscriptions".to_string());
+        let dispatcher = Dispatcher::spawn(WebhookConfig::from_env()?)?;
+        Ok(Subscriptions::new(rules, redis, channel_prefix, dispatcher))
+    }
+
+    async fn notify(&self, post: &Post) {
+        let matched: Vec<&Rule> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.matches(post))
+            .collect();
+        if matched.is_empty() {
+            return;
+        }
+        debug!(post = %post.id, rules = matched.len(), "subscriptions matched");
+        let batch = PostBatch {
+            posts: vec![post.clone()],
+        }
+        .encode_to_vec();
+        let mut pipe = redis::pipe();
+        for rule in &matched {
+            pipe.publish(format!("{}.{}", self.channel_prefix, rule.id), &batch)
+                .ignore();
+            if let Some(webhook) = &rule.webhook {
+                self.dispatcher.dispatch(&rule.id, webhook, post);
+            }
+        }
+        if let Err(e) = pipe.query_async::<()>(&mut self.redis.clone()).await {
+            warn!("Failed to publish subscription matches: {}", e);
+        }
+    }
+}
+
+impl Stage<Post> for Subscriptions {
+    fn name(&self) -> &str {
+        "subscriptions"
+    }
+
+    fn process(&self, post: Post) -> StageFuture<'_, Post> {
+        Box::pin(async move {
+            self.notify(&post).await;
+            vec![post]
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::rules_from_json;
+    use proto_definitions::social::v1::{Post, Service};
+
+    const RULES: &str = r##"[
+        {"id": "rust", "keywords": ["rustlang"], "hashtags": ["#Rust"], "services": ["mastodon"]},
+        {"id": "people", "authors": ["@Alice@example.social"], "regex": ["(?i)release v\\d+"]}
+    ]"##;
+
+    #[test]
+    fn rules_match_any_criterion_within_services() {
+        let rules = rules_from_json(RULES).unwrap();
+        let post = |content: &str, hashtags: &[&str], author: &str, service: Service| Post {
+            content: content.to_string(),
+            hashtags: hashtags.iter().map(|tag| tag.to_string()).collect(),
+            author: author.to_string(),
+            service: service as i32,
+            ..Default::default()
+        };
+        let matching = |post: &Post| -> Vec<&str> {
+            rules
+                .iter()
+                .filter(|rule| rule.matches(post))
+                .map(|rule| rule.id.as_str())
+                .collect()
+        };
+
+        let tagged = post("new crate", &["rust"], "bob", Service::Mastodon);
+        assert_eq!(matching(&tagged), ["rust"]);
+        let elsewhere = post("RustLang news", &[], "bob", Service::X);
+        assert!(matching(&elsewhere).is_empty());
+        let alice = post("Release V2 is out", &[], "alice@example.social", Service::X);
+        assert_eq!(matching(&alice), ["people"]);
+
+        assert!(rules_from_json(r#"[{"id": "empty"}]"#).is_err());
+        assert!(rules_from_json(r#"[{"id": "a.b", "keywords": ["x"]}]"#).is_err());
+    }
+}
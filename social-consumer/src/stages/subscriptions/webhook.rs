@@ -0,0 +1,228 @@
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use proto_definitions::social::v1::Post;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    env, fmt,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{Semaphore, mpsc},
+    time::sleep,
+};
+use tracing::{debug, instrument, warn};
+use url::Url;
+
+pub const SIGNATURE_HEADER: &str = "x-social-signature";
+pub const TIMESTAMP_HEADER: &str = "x-social-timestamp";
+pub const RULE_HEADER: &str = "x-social-rule";
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Where a rule's matches are POSTed. With a `secret`, each request carries
+/// `x-social-signature: sha256=<hex>`, an HMAC-SHA256 over
+/// `{x-social-timestamp}.{body}`, so receivers can verify origin and reject
+/// replays.
+#[derive(Clone, Deserialize)]
+pub struct Webhook {
+    pub url: Url,
+    pub secret: Option<String>,
+}
+
+impl fmt::Debug for Webhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Webhook").field("url", &self.url).finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// Attempts per delivery, the first one included.
+    pub max_attempts: u32,
+    pub timeout: Duration,
+    /// Deliveries in flight at once.
+    pub concurrency: usize,
+    /// Deliveries waiting for a slot; further matches are dropped.
+    pub queue_size: usize,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            max_attempts: 5,
+            timeout: Duration::from_secs(10),
+            concurrency: 16,
+            queue_size: 1024,
+        }
+    }
+}
+
+impl WebhookConfig {
+    pub fn from_env() -> Result<Self> {
+        fn parse<T>(name: &str, default: T) -> Result<T>
+        where
+            T: FromStr,
+            T::Err: std::error::Error + Send + Sync + 'static,
+        {
+            match env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .with_context(|| format!("{name} has an invalid value `{value}`")),
+                Err(_) => Ok(default),
+            }
+        }
+        let defaults = WebhookConfig::default();
+        Ok(WebhookConfig {
+            max_attempts: parse("WEBHOOK_MAX_ATTEMPTS", defaults.max_attempts)?,
+            timeout: Duration::from_secs(parse(
+                "WEBHOOK_TIMEOUT_SECS",
+                defaults.timeout.as_secs(),
+            )?),
+            concurrency: parse("WEBHOOK_CONCURRENCY", defaults.concurrency)?,
+            queue_size: parse("WEBHOOK_QUEUE_SIZE", defaults.queue_size)?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    rule: &'a str,
+    post: &'a Post,
+}
+
+#[derive(Debug)]
+struct Delivery {
+    rule: String,
+    webhook: Webhook,
+    body: Vec<u8>,
+}
+
+/// Queues webhook deliveries and runs them in the background, so a slow
+/// receiver never holds up the pipeline.
+#[derive(Debug, Clone)]
+pub struct Dispatcher {
+    tx: mpsc::Sender<Delivery>,
+}
+
+impl Dispatcher {
+    pub fn spawn(config: WebhookConfig) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .context("failed to build webhook client")?;
+        let (tx, mut rx) = mpsc::channel::<Delivery>(config.queue_size.max(1));
+        let slots = Arc::new(Semaphore::new(config.concurrency.max(1)));
+        tokio::spawn(async move {
+            while let Some(delivery) = rx.recv().await {
+                let Ok(slot) = slots.clone().acquire_owned().await else {
+                    break;
+                };
+                let client = client.clone();
+                let max_attempts = config.max_attempts;
+                tokio::spawn(async move {
+                    deliver(&client, &delivery, max_attempts).await;
+                    drop(slot);
+                });
+            }
+        });
+        Ok(Dispatcher { tx })
+    }
+
+    pub fn dispatch(&self, rule: &str, webhook: &Webhook, post: &Post) {
+        let body = match serde_json::to_vec(&Payload { rule, post }) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to serialize webhook payload for `{}`: {}", rule, e);
+                return;
+            }
+        };
+        let delivery = Delivery {
+            rule: rule.to_string(),
+            webhook: webhook.clone(),
+            body,
+        };
+        if self.tx.try_send(delivery).is_err() {
+            warn!("Webhook queue full, dropping match for `{}`", rule);
+        }
+    }
+}
+
+/// `sha256=<hex>` HMAC over `{timestamp}.{body}`.
+pub fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Client errors other than 408 and 429 will not go away by retrying.
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+#[instrument(name = "webhook", level = "debug", skip_all, fields(rule = %delivery.rule, url = %delivery.webhook.url))]
+async fn deliver(client: &Client, delivery: &Delivery, max_attempts: u32) {
+    let mut backoff = MIN_BACKOFF;
+    for attempt in 1..=max_attempts.max(1) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut request = client
+            .post(delivery.webhook.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(RULE_HEADER, &delivery.rule)
+            .header(TIMESTAMP_HEADER, timestamp)
+            .body(delivery.body.clone());
+        if let Some(secret) = &delivery.webhook.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, timestamp, &delivery.body));
+        }
+        let retryable = match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                debug!(attempt, "webhook delivered");
+                return;
+            }
+            Ok(response) => {
+                warn!(attempt, status = %response.status(), "webhook rejected");
+                is_retryable(response.status())
+            }
+            Err(e) => {
+                warn!(attempt, "webhook failed: {}", e);
+                true
+            }
+        };
+        if !retryable || attempt == max_attempts {
+            break;
+        }
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+    warn!("Giving up on webhook delivery");
+}
+
+#[cfg(test)]
+mod test {
+    use super::sign;
+
+    #[test]
+    fn signature_covers_timestamp_and_body() {
+        let signature = sign("secret", 1_700_000_000, br#"{"rule":"rust"}"#);
+        assert_eq!(
+            signature,
+            "sha256=95ce1e8baea573bf98d9ad008064db8182f3601ac078fdadda5e7c9b78981793"
+        );
+        assert_ne!(
+            signature,
+            sign("secret", 1_700_000_001, br#"{"rule":"rust"}"#)
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use super::text;
+use proto_definitions::social::v1::{Post, Service};
+use redis::{RedisResult, aio::MultiplexedConnection};
+use social_engine::stage::{Stage, StageFuture};
+use std::{
+    collections::HashSet,
+    env, fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+/// Minute buckets back the 5m and 1h windows, hour buckets the 24h one. Each
+/// is kept for twice its longest window so the aggregator can compare a window
+/// with the one before it, plus some slack for clock skew.
+const MINUTE_TTL_SECS: i64 = 3 * 3600;
+const HOUR_TTL_SECS: i64 = 50 * 3600;
+/// Caps how many values a bucket keeps; the least mentioned are dropped.
+const MAX_BUCKET_ENTRIES: isize = 10_000;
+/// Caps how many distinct terms a single long post can contribute.
+const MAX_TERMS_PER_POST: usize = 20;
+const MIN_TERM_LEN: usize = 4;
+const STOPWORDS: &[&str] = &[
+    "about", "after", "again", "also", "been", "before", "being", "both", "came", "come", "could",
+    "does", "doing", "down", "each", "even", "from", "further", "have", "having", "here", "into",
+    "just", "know", "like", "made", "make", "many", "more", "most", "much", "must", "never",
+    "next", "only", "other", "over", "really", "same", "should", "some", "still", "such", "than",
+    "that", "their", "them", "then", "there", "these", "they", "thing", "think", "this", "those",
+    "through", "time", "under", "very", "want", "well", "were", "what", "when", "where", "which",
+    "while", "will", "with", "would", "your",
+];
+
+/// Counts hashtags, links and significant terms into per-minute and per-hour
+/// Redis sorted sets, once overall and once per service. The aggregator sums
+/// these into sliding windows for `GET /trends`.
+///
+/// Run it after `hashtags` and `urls`; posts always pass through unchanged.
+#[derive(Clone)]
+pub struct Trends {
+    redis: MultiplexedConnection,
+    prefix: String,
+}
+
+impl fmt::Debug for Trends {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Trends")
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+impl Trends {
+    pub fn new(redis: MultiplexedConnection, prefix: String) -> Self {
+        Trends { redis, prefix }
+    }
+
+    pub fn from_env(redis: MultiplexedConnection) -> Self {
+        let prefix = env::var("TRENDS_PREFIX").unwrap_or_else(|_| "trends".to_string());
+        Trends::new(redis, prefix)
+    }
+
+    async fn record(&self, post: &Post) -> RedisResult<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let service = Service::try_from(post.service)
+            .map(|service| service.as_str_name().to_lowercase())
+            .unwrap_or_else(|_| "unknown".to_string());
+        let items = [
+            ("hashtag", unique(post.hashtags.iter().cloned())),
+            ("link", unique(post.urls.iter().cloned())),
+            ("term", terms(text(post))),
+        ];
+
+        let mut pipe = redis::pipe();
+        for (kind, values) in items.iter().filter(|(_, values)| !values.is_empty()) {
+            for scope in ["all", service.as_str()] {
+                for (bucket, ttl) in [
+                    (format!("m:{}", now / 60), MINUTE_TTL_SECS),
+                    (format!("h:{}", now / 3600), HOUR_TTL_SECS),
+                ] {
+                    let key = format!("{}:{kind}:{scope}:{bucket}", self.prefix);
+                    for value in values {
+                        pipe.zincr(&key, value, 1).ignore();
+                    }
+                    pipe.zremrangebyrank(&key, 0, -MAX_BUCKET_ENTRIES - 1)
+                        .ignore();
+                    pipe.expire(&key, ttl).ignore();
+                }
+            }
+        }
+        pipe.query_async(&mut self.redis.clone()).await
+    }
+}
+
+impl Stage<Post> for Trends {
+    fn name(&self) -> &str {
+        "trends"
+    }
+
+    fn process(&self, post: Post) -> StageFuture<'_, Post> {
+        Box::pin(async move {
+            if let Err(e) = self.record(&post).await {
+                warn!("Failed to record trends for {}: {}", post.id, e);
+            }
+            vec![post]
+        })
+    }
+}
+
+fn unique(values: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    values
+        .into_iter()
+        .filter(|value| seen.insert(value.clone()))
+        .collect()
+}
+
+/// Lowercased words worth trending on: no stopwords, numbers, links,
+/// hashtags or mentions, which are either noise or counted elsewhere.
+fn terms(text: &str) -> Vec<String> {
+    let words = text
+        .split_whitespace()
+        .filter(|token| !token.contains("://") && !token.starts_with(['#', '@']))
+        .flat_map(|token| token.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| word.chars().count() >= MIN_TERM_LEN)
+        .filter(|word| !word.chars().all(|c| c.is_numeric()))
+        .map(str::to_lowercase)
+        .filter(|word| !STOPWORDS.contains(&word.as_str()));
+    unique(words).into_iter().take(MAX_TERMS_PER_POST).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::terms;
+
+    #[test]
+    fn terms_skip_noise() {
+        let text = "Really excited: the Fediverse meetup moved to 2025, details at \
+                    https://example.com/meetup #fediverse @alice@example.social fediverse";
+        assert_eq!(
+            terms(text),
+            ["excited", "fediverse", "meetup", "moved", "details"]
+        );
+    }
+}
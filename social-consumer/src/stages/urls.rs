@@ -0,0 +1,17 @@
+use super::{extend_unique, text};
+use linkify::{LinkFinder, LinkKind};
+use proto_definitions::social::v1::Post;
+use social_engine::stage::{Map, map};
+
+pub fn stage() -> Map<impl Fn(Post) -> Post + Send + Sync> {
+    let mut finder = LinkFinder::new();
+    finder.kinds(&[LinkKind::Url]);
+    map("urls", move |mut post: Post| {
+        let urls: Vec<String> = finder
+            .links(text(&post))
+            .map(|link| link.as_str().to_string())
+            .collect();
+        extend_unique(&mut post.urls, urls);
+        post
+    })
+}